@@ -0,0 +1,306 @@
+//! Images and local files referenced from user messages
+//!
+//! Markdown images (`![diagram](./diagram.png)`) and links to local files
+//! (`[report](report.pdf)`) are attachments. Converters emit remote ones
+//! as multimodal content parts. Local files are only sent when inlined as
+//! base64 data URLs; otherwise they stay links in the message text, as an
+//! API has no way to resolve a relative path.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Whether an attachment is sent as an image or as a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentKind {
+    Image,
+    File,
+}
+
+/// An image or file referenced from message content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    /// The link destination as written: a relative path, URL or data URL
+    pub target: String,
+    /// Alt text or link text
+    pub text: String,
+}
+
+impl Attachment {
+    /// Whether the target refers to a file on disk rather than a URL
+    pub fn is_local(&self) -> bool {
+        is_local_target(&self.target)
+    }
+
+    /// File name of the target, without directories
+    pub fn filename(&self) -> String {
+        Path::new(&self.target)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.target.clone())
+    }
+
+    /// MIME type guessed from the file extension
+    pub fn mime_type(&self) -> &'static str {
+        mime_type(&self.target)
+    }
+
+    /// Path of a local attachment, relative targets resolved against
+    /// `base_dir` and `file:` URLs decoded
+    pub fn path(&self, base_dir: &Path) -> PathBuf {
+        match file_url_path(&self.target) {
+            Some(path) => PathBuf::from(path),
+            None => base_dir.join(&self.target),
+        }
+    }
+
+    /// Read a local attachment into a `data:` URL
+    pub fn data_url(&self, base_dir: &Path) -> io::Result<String> {
+        let bytes = fs::read(self.path(base_dir))?;
+        Ok(format!("data:{};base64,{}", self.mime_type(), STANDARD.encode(bytes)))
+    }
+
+    /// The URL to send: the target of a remote attachment, or the data URL
+    /// of a local one; `None` when a local file is not inlined
+    pub fn url(&self, options: &AttachmentOptions) -> Option<String> {
+        if self.is_local() {
+            self.inline_data(options)
+        } else {
+            Some(self.target.clone())
+        }
+    }
+
+    /// The data URL of a local file, when inlining is enabled and it can be read
+    pub fn inline_data(&self, options: &AttachmentOptions) -> Option<String> {
+        if options.inline && self.is_local() {
+            self.data_url(&options.base_dir).ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// How converters resolve and embed attachments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentOptions {
+    /// Directory that relative paths are resolved against
+    pub base_dir: PathBuf,
+    /// Embed local files as base64 data URLs
+    pub inline: bool,
+}
+
+impl Default for AttachmentOptions {
+    fn default() -> Self {
+        Self {
+            base_dir: PathBuf::from("."),
+            inline: false,
+        }
+    }
+}
+
+impl AttachmentOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn base_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = dir.into();
+        self
+    }
+
+    pub fn inline(mut self, inline: bool) -> Self {
+        self.inline = inline;
+        self
+    }
+}
+
+/// Find the attachments referenced in markdown, in order
+///
+/// Every image is an attachment; links only when they point at a local file.
+pub fn find_attachments(markdown: &str) -> Vec<Attachment> {
+    let mut attachments = Vec::new();
+    let mut current: Option<Attachment> = None;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Image(_, url, _)) => {
+                current = Some(Attachment {
+                    kind: AttachmentKind::Image,
+                    target: url.to_string(),
+                    text: String::new(),
+                });
+            }
+            Event::Start(Tag::Link(_, url, _)) if is_local_target(&url) => {
+                let kind = if mime_type(&url).starts_with("image/") {
+                    AttachmentKind::Image
+                } else {
+                    AttachmentKind::File
+                };
+                current = Some(Attachment {
+                    kind,
+                    target: url.to_string(),
+                    text: String::new(),
+                });
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(ref mut attachment) = current {
+                    attachment.text.push_str(&text);
+                }
+            }
+            Event::End(Tag::Image(..)) | Event::End(Tag::Link(..)) => {
+                if let Some(attachment) = current.take() {
+                    attachments.push(attachment);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    attachments
+}
+
+/// The path of a `file:` URL: `file:///tmp/a.png` and
+/// `file://localhost/tmp/a.png` are `/tmp/a.png`, `file:///C:/a.png` is
+/// `C:/a.png`, and another host gives `//host/a.png`
+fn file_url_path(target: &str) -> Option<String> {
+    let rest = target.get(..5).filter(|scheme| scheme.eq_ignore_ascii_case("file:")).map(|_| &target[5..])?;
+    let path = match rest.strip_prefix("//") {
+        Some(authority) => match authority.find('/').map(|slash| authority.split_at(slash)) {
+            Some(("" | "localhost", path)) => path,
+            Some(_) | None => rest,
+        },
+        None => rest,
+    };
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    };
+
+    // Decode `%XX` escapes, leaving malformed ones as written
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = path.get(i + 1..i + 3).filter(|_| path.as_bytes()[i] == b'%');
+        match escaped.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(path.as_bytes()[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Anything but an empty target, a fragment or a URL with a scheme other
+/// than `file:`; a single letter before `:` is a Windows drive, not a scheme
+fn is_local_target(target: &str) -> bool {
+    if file_url_path(target).is_some() {
+        return true;
+    }
+    let scheme = target.split_once(':').map(|(scheme, _)| scheme);
+    let is_url = scheme.is_some_and(|scheme| {
+        scheme.len() > 1
+            && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    !target.is_empty() && !target.starts_with('#') && !is_url
+}
+
+fn mime_type(target: &str) -> &'static str {
+    let extension = Path::new(target)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_attachments() {
+        let markdown = "See ![the diagram](./diagram.png), [notes](notes.pdf), \
+                        [a site](https://example.com) and ![logo](https://example.com/logo.png)";
+        let attachments = find_attachments(markdown);
+        assert_eq!(attachments.len(), 3);
+        assert_eq!(attachments[0].kind, AttachmentKind::Image);
+        assert_eq!(attachments[0].text, "the diagram");
+        assert_eq!(attachments[1].kind, AttachmentKind::File);
+        assert_eq!(attachments[1].mime_type(), "application/pdf");
+        assert!(!attachments[2].is_local());
+    }
+
+    #[test]
+    fn test_inline_data_url() {
+        let dir = std::env::temp_dir().join("cmf-attachment-test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hi.txt"), "hi").unwrap();
+
+        let attachment = &find_attachments("[hi](hi.txt)")[0];
+        let options = AttachmentOptions::new().base_dir(&dir).inline(true);
+        assert_eq!(attachment.url(&options).as_deref(), Some("data:text/plain;base64,aGk="));
+        assert_eq!(attachment.url(&AttachmentOptions::new()), None);
+
+        let missing = &find_attachments("![x](missing.png)")[0];
+        assert_eq!(missing.url(&options), None);
+
+        let remote = &find_attachments("![x](https://example.com/x.png)")[0];
+        assert_eq!(remote.url(&options).as_deref(), Some("https://example.com/x.png"));
+    }
+
+    #[test]
+    fn test_local_targets() {
+        assert!(is_local_target("./diagram.png"));
+        assert!(is_local_target(r"C:\images\chart.png"));
+        assert!(is_local_target("C:/images/chart.png"));
+        assert!(!is_local_target("https://example.com/a.png"));
+        assert!(!is_local_target("data:image/png;base64,AAAA"));
+        assert!(!is_local_target("mailto:someone@example.com"));
+        assert!(!is_local_target("#section"));
+        assert!(is_local_target("file:///path/img.png"));
+    }
+
+    #[test]
+    fn test_file_urls() {
+        let base = Path::new("/chats");
+        let attachment = |target: &str| Attachment {
+            kind: AttachmentKind::Image,
+            target: target.to_string(),
+            text: String::new(),
+        };
+        assert_eq!(attachment("file:///path/my%20img.png").path(base), PathBuf::from("/path/my img.png"));
+        assert_eq!(attachment("file://localhost/a.png").path(base), PathBuf::from("/a.png"));
+        assert_eq!(attachment("FILE:///C:/a.png").path(base), PathBuf::from("C:/a.png"));
+        assert_eq!(attachment("file://server/a.png").path(base), PathBuf::from("//server/a.png"));
+        assert_eq!(attachment("img.png").path(base), PathBuf::from("/chats/img.png"));
+
+        // Not inlined, so not sent
+        let options = AttachmentOptions::default();
+        assert_eq!(attachment("file:///path/img.png").url(&options), None);
+        assert_eq!(attachment("file:///path/img.png").filename(), "img.png");
+    }
+}
@@ -0,0 +1,302 @@
+//! Borrowed document model for bulk processing
+//!
+//! `DocumentRef::parse` follows the same rules as `Document::parse` but its
+//! turns point into the input. Only content that has to be rebuilt is
+//! copied: multi-line user messages (their `>` prefixes are stripped) and
+//! assistant content with `\r\n` line endings.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use serde::Serialize;
+
+use crate::metadata::parse_comment;
+use crate::{Document, Metadata, Turn, UserMessage};
+
+/// A user message borrowing from the input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UserMessageRef<'a> {
+    pub username: Option<&'a str>,
+    pub content: Cow<'a, str>,
+    #[serde(skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+/// A turn borrowing from the input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TurnRef<'a> {
+    pub user: UserMessageRef<'a>,
+    pub assistant: Cow<'a, str>,
+}
+
+/// A parsed CMF document borrowing from its input
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DocumentRef<'a> {
+    pub turns: Vec<TurnRef<'a>>,
+}
+
+impl TurnRef<'_> {
+    /// Copy into an owned `Turn`
+    pub fn to_turn(&self) -> Turn {
+        Turn {
+            user: UserMessage {
+                username: self.user.username.map(str::to_string),
+                content: self.user.content.to_string(),
+                metadata: self.user.metadata.clone(),
+            },
+            assistant: self.assistant.to_string(),
+        }
+    }
+}
+
+/// Where a turn sits in the input, as byte offsets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TurnSpan {
+    /// Start of the turn, including a metadata comment before the user block
+    pub start: usize,
+    /// The user block, from its first `>` to the end of its last line
+    pub user: Range<usize>,
+    /// End of the last non-blank assistant line, or of the user block
+    pub end: usize,
+}
+
+impl<'a> DocumentRef<'a> {
+    /// Parse without copying, with the same results as `Document::parse`
+    pub fn parse(input: &'a str) -> Self {
+        Self::parse_with_spans(input).0
+    }
+
+    /// Parse, also returning where each turn sits in the input
+    pub(crate) fn parse_with_spans(input: &'a str) -> (Self, Vec<TurnSpan>) {
+        let mut parser = RefParser::default();
+        let mut turns = Vec::new();
+        let mut offset = 0;
+
+        for raw in input.split_inclusive('\n') {
+            // Same line endings as `str::lines`: `\n` or `\r\n`
+            let line = match raw.strip_suffix('\n') {
+                Some(line) => line.strip_suffix('\r').unwrap_or(line),
+                None => raw,
+            };
+            turns.extend(parser.push_line(input, offset, line));
+            offset += raw.len();
+        }
+        turns.extend(parser.finish(input));
+
+        (DocumentRef { turns }, parser.spans)
+    }
+
+    /// Copy into an owned `Document`
+    pub fn to_document(&self) -> Document {
+        Document {
+            turns: self.turns.iter().map(TurnRef::to_turn).collect(),
+        }
+    }
+}
+
+/// The `TurnParser` state machine, tracking lines as offsets into the input
+#[derive(Default)]
+struct RefParser<'a> {
+    user_lines: Vec<&'a str>,
+    /// Start offset and content of each assistant line
+    assistant_lines: Vec<(usize, &'a str)>,
+    metadata: Metadata,
+    in_user_block: bool,
+    seen_first_user: bool,
+    /// Start of the pending turn and its user block
+    turn_start: usize,
+    user: Range<usize>,
+    spans: Vec<TurnSpan>,
+}
+
+impl<'a> RefParser<'a> {
+    fn push_line(&mut self, input: &'a str, offset: usize, line: &'a str) -> Option<TurnRef<'a>> {
+        let mut completed = None;
+
+        if let Some(content) = line.strip_prefix('>') {
+            if !self.in_user_block {
+                let (metadata, comment_start) = self.take_trailing_metadata();
+                if self.seen_first_user && !self.user_lines.is_empty() {
+                    completed = Some(self.take_turn(input, metadata));
+                } else {
+                    self.metadata = metadata;
+                }
+                self.assistant_lines.clear();
+                self.turn_start = comment_start.unwrap_or(offset);
+                self.user = offset..offset;
+            }
+
+            self.in_user_block = true;
+            self.seen_first_user = true;
+            self.user.end = offset + line.len();
+            self.user_lines.push(content.strip_prefix(' ').unwrap_or(content));
+        } else {
+            self.in_user_block = false;
+            if !self.seen_first_user && !line.trim().is_empty() {
+                self.assistant_lines.clear();
+            }
+            self.assistant_lines.push((offset, line));
+        }
+
+        completed
+    }
+
+    fn finish(&mut self, input: &'a str) -> Option<TurnRef<'a>> {
+        if self.user_lines.is_empty() {
+            return None;
+        }
+        Some(self.take_turn(input, Metadata::new()))
+    }
+
+    /// Take a metadata comment ending the assistant lines, with its offset
+    fn take_trailing_metadata(&mut self) -> (Metadata, Option<usize>) {
+        let Some(last) = self.assistant_lines.iter().rposition(|(_, line)| !line.trim().is_empty()) else {
+            return (Metadata::new(), None);
+        };
+        let (offset, line) = self.assistant_lines[last];
+        match parse_comment(line) {
+            Some(metadata) => {
+                self.assistant_lines.truncate(last);
+                (metadata, Some(offset))
+            }
+            None => (Metadata::new(), None),
+        }
+    }
+
+    fn take_turn(&mut self, input: &'a str, next_metadata: Metadata) -> TurnRef<'a> {
+        let metadata = std::mem::replace(&mut self.metadata, next_metadata);
+        let user = user_message(&self.user_lines, metadata);
+        let assistant = assistant_content(input, &self.assistant_lines);
+        let end = match self.assistant_lines.iter().rposition(|(_, line)| !line.trim().is_empty()) {
+            Some(last) => self.assistant_lines[last].0 + self.assistant_lines[last].1.len(),
+            None => self.user.end,
+        };
+        self.spans.push(TurnSpan {
+            start: self.turn_start,
+            user: self.user.clone(),
+            end,
+        });
+        self.user_lines.clear();
+        TurnRef { user, assistant }
+    }
+}
+
+/// Like `parse_user_block`, borrowing single-line messages
+fn user_message<'a>(lines: &[&'a str], metadata: Metadata) -> UserMessageRef<'a> {
+    let (username, first) = match lines[0].strip_prefix('@').and_then(|rest| rest.split_once(':')) {
+        Some((username, first)) => (Some(username), first.trim_start()),
+        None => (None, lines[0]),
+    };
+
+    let content = if lines.len() == 1 {
+        Cow::Borrowed(first)
+    } else {
+        let mut content = first.to_string();
+        for line in &lines[1..] {
+            content.push('\n');
+            content.push_str(line);
+        }
+        Cow::Owned(content)
+    };
+
+    UserMessageRef {
+        username,
+        content,
+        metadata,
+    }
+}
+
+/// Like `trim_assistant_block`, borrowing the span between the first and
+/// last non-blank lines when it can be used as is
+fn assistant_content<'a>(input: &'a str, lines: &[(usize, &'a str)]) -> Cow<'a, str> {
+    let is_content = |(_, line): &(usize, &str)| !line.trim().is_empty();
+    let (Some(first), Some(last)) = (lines.iter().position(is_content), lines.iter().rposition(is_content)) else {
+        return Cow::Borrowed("");
+    };
+
+    let (start, _) = lines[first];
+    let (last_start, last_line) = lines[last];
+    let span = &input[start..last_start + last_line.len()];
+    if !span.contains('\r') {
+        return Cow::Borrowed(span);
+    }
+
+    let lines: Vec<&str> = lines[first..=last].iter().map(|(_, line)| *line).collect();
+    Cow::Owned(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::TurnReader;
+    use crate::Turn;
+    use proptest::prelude::*;
+    use std::io;
+
+    fn assert_same_as_owned(input: &str) {
+        assert_eq!(DocumentRef::parse(input).to_document(), Document::parse(input), "input: {:?}", input);
+    }
+
+    #[test]
+    fn test_matches_owned_parser() {
+        assert_same_as_owned("> Hello\nHi!\n\n> @alice: Two\n> lines\n\nAnswer\n\nmore\n\n");
+        assert_same_as_owned("preamble\n<!-- cmf: ts=1 -->\n\n> Hi\n<!-- cmf: name=bot -->\nYo\n<!-- cmf: ts=2 -->\n\n> Bye");
+        assert_same_as_owned("> Windows\r\n> lines\r\nAnswer\r\n\r\nmore\r\n");
+        assert_same_as_owned(">\n>no space\n\n   \n");
+        assert_same_as_owned("no turns at all");
+        assert_same_as_owned("");
+    }
+
+    /// Lines covering the rules both parsers implement, to shuffle into
+    /// documents
+    const LINES: &[&str] = &[
+        "> Hi",
+        "> @alice: Hey",
+        ">",
+        ">no space",
+        "> @system: Rules",
+        "> @system:",
+        "",
+        "   ",
+        "Answer",
+        " > quoted",
+        "<!-- cmf -->",
+        "<!-- cmf: ts=1 -->",
+        "<!-- cmf: name=bot -->",
+        " <!-- cmf -->",
+        "<!-- note -->",
+        "```",
+        "```tool_result",
+    ];
+
+    proptest! {
+        /// The owned, streaming and borrowed parsers are separate state
+        /// machines; they must agree on every document
+        #[test]
+        fn prop_parsers_agree(lines in prop::collection::vec(prop::sample::select(LINES), 0..40), crlf in any::<bool>()) {
+            let input = lines.join(if crlf { "\r\n" } else { "\n" });
+            let owned = Document::parse(&input);
+            prop_assert_eq!(&DocumentRef::parse(&input).to_document(), &owned);
+            let streamed: Vec<Turn> = TurnReader::new(input.as_bytes()).collect::<io::Result<_>>().unwrap();
+            prop_assert_eq!(&streamed, &owned.turns);
+        }
+    }
+
+    #[test]
+    fn test_borrows_where_possible() {
+        let doc = DocumentRef::parse("> @alice: Hi\nLine one\n\nLine two\n\n> Multi\n> line\nOk");
+        assert!(matches!(doc.turns[0].user.content, Cow::Borrowed("Hi")));
+        assert_eq!(doc.turns[0].user.username, Some("alice"));
+        assert!(matches!(doc.turns[0].assistant, Cow::Borrowed("Line one\n\nLine two")));
+        assert!(matches!(doc.turns[1].user.content, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_turn_spans() {
+        let input = "intro\n<!-- cmf: ts=1 -->\n\n> Hi\n> there\nAnswer\n\n\n> Bye\n";
+        let (_, spans) = DocumentRef::parse_with_spans(input);
+        assert_eq!(&input[spans[0].start..spans[0].end], "<!-- cmf: ts=1 -->\n\n> Hi\n> there\nAnswer");
+        assert_eq!(&input[spans[0].user.clone()], "> Hi\n> there");
+        assert_eq!(&input[spans[1].start..spans[1].end], "> Bye");
+    }
+}
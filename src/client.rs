@@ -0,0 +1,213 @@
+//! Chatting with OpenAI-compatible endpoints
+//!
+//! `ChatClient` posts the Chat Completions messages built by
+//! `Document::to_openai_chat` and streams the reply back as it is
+//! generated. Anything speaking the same protocol works: OpenAI itself,
+//! local servers like llama.cpp or Ollama, or a mock.
+
+use std::io::{self, BufRead, BufReader};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::ChatMessage;
+
+/// Base URL of the OpenAI API
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Client for a `/chat/completions` endpoint
+#[derive(Debug, Clone)]
+pub struct ChatClient {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+/// A streamed chunk or a whole response; only the text is used
+#[derive(Deserialize)]
+struct Completion {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    #[serde(alias = "message")]
+    delta: Option<Delta>,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+impl Completion {
+    fn into_text(self) -> io::Result<String> {
+        if let Some(error) = self.error {
+            let message = error.get("message").and_then(|m| m.as_str()).map(str::to_string);
+            return Err(io::Error::other(message.unwrap_or_else(|| error.to_string())));
+        }
+        Ok(self
+            .choices
+            .into_iter()
+            .filter_map(|choice| choice.delta?.content)
+            .collect())
+    }
+}
+
+impl ChatClient {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            model: model.into(),
+            api_key: None,
+        }
+    }
+
+    /// Send `key` as a bearer token
+    pub fn api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Request a reply to `messages`, calling `on_token` with each piece as
+    /// it arrives; returns the whole reply
+    ///
+    /// Servers that ignore `stream` and answer with plain JSON are handled too.
+    pub fn complete(
+        &self,
+        messages: &[ChatMessage],
+        mut on_token: impl FnMut(&str) -> io::Result<()>,
+    ) -> io::Result<String> {
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let body = json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": true,
+        });
+
+        let mut request = ureq::post(&url).set("Content-Type", "application/json");
+        if let Some(ref key) = self.api_key {
+            request = request.set("Authorization", &format!("Bearer {}", key));
+        }
+        let response = match request.send_string(&body.to_string()) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => {
+                let detail = response.into_string().unwrap_or_default();
+                let message = serde_json::from_str::<Completion>(&detail)
+                    .ok()
+                    .and_then(|completion| completion.into_text().err())
+                    .map_or(detail, |e| e.to_string());
+                return Err(io::Error::other(format!("HTTP {}: {}", code, message.trim())));
+            }
+            Err(e) => return Err(io::Error::other(e)),
+        };
+
+        if response.content_type() != "text/event-stream" {
+            let completion: Completion = serde_json::from_reader(response.into_reader())?;
+            let text = completion.into_text()?;
+            on_token(&text)?;
+            return Ok(text);
+        }
+
+        let mut reply = String::new();
+        for line in BufReader::new(response.into_reader()).lines() {
+            let line = line?;
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                break;
+            }
+            let token = serde_json::from_str::<Completion>(data)?.into_text()?;
+            if !token.is_empty() {
+                on_token(&token)?;
+                reply.push_str(&token);
+            }
+        }
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve one request with `response`, returning the base URL and the
+    /// request body
+    fn stub_server(response: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            let body_start = loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+            };
+            let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+            let length: usize = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |n| n.trim().parse().unwrap());
+            while request.len() < body_start + length {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request[body_start..].to_vec()).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_streams_reply() {
+        let (url, server) = stub_server(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n\
+             data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+             data: {\"choices\":[{\"delta\":{\"content\":\"lo!\"}}]}\n\n\
+             data: [DONE]\n\n",
+        );
+        let doc = Document::parse("> Hi");
+        let mut tokens = Vec::new();
+        let reply = ChatClient::new(url, "test-model")
+            .complete(&doc.to_openai_chat(), |token| {
+                tokens.push(token.to_string());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(reply, "Hello!");
+        assert_eq!(tokens, ["Hel", "lo!"]);
+        let request: serde_json::Value = serde_json::from_str(&server.join().unwrap()).unwrap();
+        assert_eq!(request["model"], "test-model");
+        assert_eq!(request["messages"][0]["content"], "Hi");
+    }
+
+    #[test]
+    fn test_plain_json_and_errors() {
+        let (url, _) = stub_server(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n\
+             {\"choices\":[{\"message\":{\"role\":\"assistant\",\"content\":\"Whole reply\"}}]}",
+        );
+        let reply = ChatClient::new(url, "m").complete(&[], |_| Ok(())).unwrap();
+        assert_eq!(reply, "Whole reply");
+
+        let (url, _) = stub_server(
+            "HTTP/1.1 401 Unauthorized\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n\
+             {\"error\":{\"message\":\"bad api key\"}}\n",
+        );
+        let err = ChatClient::new(url, "m").complete(&[], |_| Ok(())).unwrap_err();
+        assert_eq!(err.to_string(), "HTTP 401: bad api key");
+    }
+}
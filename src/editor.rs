@@ -0,0 +1,370 @@
+//! Editing CMF text in place
+//!
+//! `DocumentEditor` keeps the source text alongside the parsed `Document`.
+//! Every operation splices the smallest span of text it can and reports it
+//! as a `TextEdit`, so preambles, metadata comments and formatting outside
+//! the edited region survive untouched and editors can apply the same
+//! change to their buffers.
+//!
+//! These operations live here rather than on `Document` because a
+//! `Document` is only the parsed turns: it has no text to edit in place.
+
+use std::ops::Range;
+
+use crate::borrowed::{DocumentRef, TurnSpan};
+use crate::{Document, Turn, MESSAGE_SEPARATOR, SYSTEM_USERNAME};
+
+/// A replacement of a byte range of the source, in offsets before the edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+/// A CMF document and its text, edited together
+///
+/// ```
+/// use cmf::editor::DocumentEditor;
+///
+/// let mut editor = DocumentEditor::new("---\ntitle: demo\n---\n\n> Hi\nHello!\n");
+/// editor.push_user(None, "How are you?");
+/// editor.push_assistant("");
+/// editor.append_to_assistant("Fine, ");
+/// editor.append_to_assistant("thanks.");
+/// assert_eq!(editor.document().turns[1].assistant, "Fine, thanks.");
+/// assert!(editor.source().starts_with("---\ntitle: demo\n---\n"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DocumentEditor {
+    source: String,
+    document: Document,
+    spans: Vec<TurnSpan>,
+    /// Where streamed assistant text continues, until another edit
+    stream_pos: Option<usize>,
+}
+
+impl DocumentEditor {
+    pub fn new(source: impl Into<String>) -> Self {
+        let mut editor = Self {
+            source: source.into(),
+            document: Document { turns: Vec::new() },
+            spans: Vec::new(),
+            stream_pos: None,
+        };
+        editor.rescan_from(0);
+        editor
+    }
+
+    /// The current text
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn into_source(self) -> String {
+        self.source
+    }
+
+    /// The current text, parsed
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    /// Append a user message as a new turn
+    pub fn push_user(&mut self, username: Option<&str>, content: &str) -> TextEdit {
+        let end = self.source.len();
+        let text = format!("{}{}\n", self.block_separator(), user_block(username, content));
+        self.splice(end..end, text, self.turns().saturating_sub(1))
+    }
+
+    /// Add an assistant message to the last turn, after a `<!-- cmf -->`
+    /// separator if the turn already has a reply
+    ///
+    /// A document without turns gets an empty `> @system:` line first.
+    pub fn push_assistant(&mut self, content: &str) -> TextEdit {
+        let content = escape_assistant(content, true);
+        let (at, text) = match (self.spans.last(), self.document.turns.last()) {
+            (Some(span), Some(turn)) if turn.assistant.is_empty() => (span.end, format!("\n{}", content)),
+            (Some(span), Some(_)) => (span.end, format!("\n\n{}\n\n{}", MESSAGE_SEPARATOR, content)),
+            _ => {
+                let system = user_block(Some(SYSTEM_USERNAME), "");
+                let end = self.source.len();
+                (end, format!("{}{}\n{}", self.block_separator(), system, content))
+            }
+        };
+
+        let stream_pos = at + text.len();
+        let edit = self.splice(at..at, text, self.turns().saturating_sub(1));
+        self.stream_pos = Some(stream_pos);
+        edit
+    }
+
+    /// Continue the last assistant message with streamed text
+    ///
+    /// Lines starting with `>` or `<!--` are indented so they stay
+    /// assistant content. A last turn without a reply starts one, as
+    /// `push_assistant` does.
+    pub fn append_to_assistant(&mut self, chunk: &str) -> TextEdit {
+        let replied = self.document.turns.last().is_some_and(|turn| !turn.assistant.is_empty());
+        let at = match (self.stream_pos, self.spans.last()) {
+            (Some(at), _) => at,
+            (None, Some(span)) if replied => span.end,
+            _ => return self.push_assistant(chunk),
+        };
+
+        // A chunk can complete a `<!--` begun by the last one, so the line
+        // is escaped from its start
+        let line_start = self.source[..at].rfind('\n').map_or(0, |i| i + 1);
+        let partial = &self.source[line_start..at];
+        let joined = format!("{}{}", partial, chunk);
+        let (from, text) = if !partial.is_empty() && !needs_escape(partial) && needs_escape(&joined) {
+            (line_start, escape_assistant(&joined, true))
+        } else {
+            (at, escape_assistant(chunk, partial.is_empty()))
+        };
+        let stream_pos = from + text.len();
+        let edit = self.splice(from..at, text, self.turns() - 1);
+        self.stream_pos = Some(stream_pos);
+        edit
+    }
+
+    /// Insert a turn before `index`, or at the end when `index` is the turn count
+    pub fn insert_turn(&mut self, index: usize, turn: &Turn) -> Option<TextEdit> {
+        let text = Document { turns: vec![turn.clone()] }.to_cmf();
+        if index == self.turns() {
+            let end = self.source.len();
+            let text = format!("{}{}\n", self.block_separator(), text);
+            return Some(self.splice(end..end, text, index.saturating_sub(1)));
+        }
+
+        let at = self.spans.get(index)?.start;
+        Some(self.splice(at..at, format!("{}\n\n", text), index))
+    }
+
+    /// Remove a turn, including its metadata comment
+    pub fn remove_turn(&mut self, index: usize) -> Option<TextEdit> {
+        let span = self.spans.get(index)?;
+        let range = match (self.spans.get(index + 1), index.checked_sub(1)) {
+            (Some(next), _) => span.start..next.start,
+            (None, Some(previous)) => self.spans[previous].end..self.source.len(),
+            (None, None) => span.start..self.source.len(),
+        };
+        let text = if range.end == self.source.len() { self.final_newline() } else { "" };
+        Some(self.splice(range, text.to_string(), index.saturating_sub(1)))
+    }
+
+    /// Replace the content of a user message, keeping its `@username`
+    pub fn edit_user(&mut self, index: usize, content: &str) -> Option<TextEdit> {
+        let range = self.spans.get(index)?.user.clone();
+        let username = self.document.turns[index].user.username.clone();
+        Some(self.splice(range, user_block(username.as_deref(), content), index))
+    }
+
+    /// Remove every turn after `index`; `None` when there are none
+    pub fn truncate_after(&mut self, index: usize) -> Option<TextEdit> {
+        if index + 1 >= self.turns() {
+            return None;
+        }
+        let range = self.spans[index].end..self.source.len();
+        Some(self.splice(range, self.final_newline().to_string(), index))
+    }
+
+    /// Drop the last turn's assistant reply so it can be regenerated
+    pub fn retry_last(&mut self) -> Option<TextEdit> {
+        let last = self.turns().checked_sub(1)?;
+        if self.document.turns[last].assistant.is_empty() {
+            return None;
+        }
+        let range = self.spans[last].user.end..self.source.len();
+        Some(self.splice(range, self.final_newline().to_string(), last))
+    }
+
+    fn turns(&self) -> usize {
+        self.document.turns.len()
+    }
+
+    /// Blank line needed before a block appended at the end of the source
+    fn block_separator(&self) -> &'static str {
+        if self.source.is_empty() || self.source.ends_with("\n\n") {
+            ""
+        } else if self.source.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        }
+    }
+
+    /// Keep the file's final newline when cutting off its end
+    fn final_newline(&self) -> &'static str {
+        if self.source.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        }
+    }
+
+    /// Apply an edit and re-parse from turn `first`, which must start
+    /// before the edited range
+    fn splice(&mut self, range: Range<usize>, text: String, first: usize) -> TextEdit {
+        self.source.replace_range(range.clone(), &text);
+        self.stream_pos = None;
+        self.rescan_from(first);
+        TextEdit { range, text }
+    }
+
+    /// Re-parse the source from the start of turn `first` onwards
+    fn rescan_from(&mut self, first: usize) {
+        let first = first.min(self.spans.len().saturating_sub(1));
+        let start = match first {
+            0 => 0,
+            _ => self.spans[first].start,
+        };
+
+        let (parsed, spans) = DocumentRef::parse_with_spans(&self.source[start..]);
+        self.document.turns.truncate(first);
+        self.document.turns.extend(parsed.turns.iter().map(|turn| turn.to_turn()));
+        self.spans.truncate(first);
+        self.spans.extend(spans.into_iter().map(|span| TurnSpan {
+            start: span.start + start,
+            user: span.user.start + start..span.user.end + start,
+            end: span.end + start,
+        }));
+    }
+}
+
+/// A user block: `> ` before each line, with an `@username:` prefix
+pub(crate) fn user_block(username: Option<&str>, content: &str) -> String {
+    let content = match username {
+        Some(username) => format!("@{}: {}", username, content),
+        None => content.to_string(),
+    };
+    if content.is_empty() {
+        return ">".to_string();
+    }
+    content.lines().map(|line| format!("> {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+/// Whether an assistant line would be read as a user line or a comment
+pub(crate) fn needs_escape(line: &str) -> bool {
+    line.starts_with('>') || line.starts_with("<!--")
+}
+
+/// Indent lines starting with `>` or `<!--` so they are not read as user
+/// lines or metadata comments
+pub(crate) fn escape_assistant(text: &str, at_line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            escaped.push('\n');
+        }
+        if (i > 0 || at_line_start) && needs_escape(line) {
+            escaped.push(' ');
+        }
+        escaped.push_str(line);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UserMessage;
+
+    const SOURCE: &str = "# Notes\n\n<!-- cmf: ts=1 -->\n\n> @alice: First\nOne.\n\n> Second\nTwo.\n\n> Third\nThree.\n";
+
+    /// Every edit leaves the editor's model equal to a fresh parse
+    fn assert_consistent(editor: &DocumentEditor) {
+        assert_eq!(editor.document(), &Document::parse(editor.source()));
+        assert_eq!(editor.spans, DocumentEditor::new(editor.source()).spans);
+    }
+
+    #[test]
+    fn test_push_and_stream() {
+        let mut editor = DocumentEditor::new(SOURCE);
+        let edit = editor.push_user(Some("bob"), "Fourth");
+        assert_eq!(edit.range, SOURCE.len()..SOURCE.len());
+        assert_eq!(edit.text, "\n> @bob: Fourth\n");
+
+        editor.push_assistant("");
+        for chunk in ["Stre", "amed\n", "> not a user line", "\n<", "!-- cmf -->", "\n\nDone."] {
+            editor.append_to_assistant(chunk);
+            assert_consistent(&editor);
+        }
+        assert_eq!(
+            editor.document().turns[3].assistant,
+            "Streamed\n > not a user line\n <!-- cmf -->\n\nDone."
+        );
+        assert_eq!(editor.document().turns[3].assistant_messages().len(), 1);
+        assert!(editor.source().starts_with(SOURCE));
+
+        editor.push_assistant("Second message");
+        assert_eq!(editor.document().turns[3].assistant_messages().len(), 2);
+        assert_consistent(&editor);
+    }
+
+    #[test]
+    fn test_append_starts_a_reply() {
+        let mut editor = DocumentEditor::new("> Hi\n");
+        editor.append_to_assistant("Hello");
+        editor.append_to_assistant(" there");
+        assert_eq!(editor.source(), "> Hi\nHello there\n");
+        assert_eq!(editor.document().turns[0].assistant, "Hello there");
+
+        editor.push_user(None, "Next");
+        editor.append_to_assistant("Reply");
+        assert_eq!(editor.document().turns[1].user.content, "Next");
+        assert_eq!(editor.document().turns[1].assistant, "Reply");
+        assert_consistent(&editor);
+    }
+
+    #[test]
+    fn test_push_assistant_into_empty_document() {
+        let mut editor = DocumentEditor::new("");
+        editor.append_to_assistant("Welcome!");
+        assert_eq!(editor.source(), "> @system: \nWelcome!");
+        assert_eq!(editor.document().messages()[0].content, "Welcome!");
+    }
+
+    #[test]
+    fn test_structural_edits_keep_preamble_and_metadata() {
+        let mut editor = DocumentEditor::new(SOURCE);
+        let turn = Turn {
+            user: UserMessage {
+                username: None,
+                content: "Inserted".to_string(),
+                metadata: Default::default(),
+            },
+            assistant: "Yes.".to_string(),
+        };
+        editor.insert_turn(1, &turn).unwrap();
+        assert_consistent(&editor);
+        assert_eq!(editor.document().turns[1].user.content, "Inserted");
+
+        editor.remove_turn(0).unwrap();
+        assert_consistent(&editor);
+        assert!(editor.source().starts_with("# Notes\n\n> Inserted"));
+
+        editor.edit_user(1, "Second, edited\non two lines").unwrap();
+        assert_consistent(&editor);
+        assert_eq!(editor.document().turns[1].assistant, "Two.");
+
+        editor.truncate_after(1).unwrap();
+        assert_consistent(&editor);
+        assert_eq!(editor.document().turns.len(), 2);
+        assert!(editor.truncate_after(1).is_none());
+
+        let edit = editor.retry_last().unwrap();
+        assert_eq!(edit.text, "\n");
+        assert_consistent(&editor);
+        assert_eq!(editor.document().turns[1].assistant, "");
+        assert!(editor.retry_last().is_none());
+    }
+
+    #[test]
+    fn test_edit_user_keeps_metadata_and_username() {
+        let mut editor = DocumentEditor::new(SOURCE);
+        let edit = editor.edit_user(0, "Changed").unwrap();
+        assert_eq!(edit.text, "> @alice: Changed");
+        assert_eq!(editor.document().turns[0].user.metadata["ts"], "1");
+        assert!(editor.remove_turn(3).is_none());
+    }
+}
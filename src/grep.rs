@@ -0,0 +1,218 @@
+//! Turn-aware search
+//!
+//! `grep` matches a regular expression line by line, like `grep`, but knows
+//! which turn each line belongs to and who wrote it. Metadata comments and
+//! the `> @name:` prefix are not searched; tool calls and results are not
+//! code.
+
+pub use regex::Regex;
+
+use crate::borrowed::DocumentRef;
+use crate::metadata::parse_comment;
+use crate::stats::TOOL_LANGUAGES;
+use crate::{Fences, Role, SPEAKER_KEY, SYSTEM_USERNAME};
+
+/// What to search
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GrepOptions {
+    /// Only lines of messages with this role
+    pub role: Option<Role>,
+    /// Only lines inside fenced code blocks, other than tool calls and
+    /// results
+    pub code_only: bool,
+}
+
+impl GrepOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = Some(role);
+        self
+    }
+
+    pub fn code_only(mut self, code_only: bool) -> Self {
+        self.code_only = code_only;
+        self
+    }
+}
+
+/// A matching line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    /// 1-based turn number
+    pub turn: usize,
+    pub role: Role,
+    /// `@name` for named users and speakers, otherwise the role
+    pub speaker: String,
+    /// 1-based line number in the input
+    pub line: usize,
+    /// The whole line, as in the input
+    pub text: String,
+}
+
+/// Lines of `input` matching `pattern`, in order
+pub fn grep(input: &str, pattern: &Regex, options: &GrepOptions) -> Vec<Match> {
+    let (doc, spans) = DocumentRef::parse_with_spans(input);
+    let mut matches = Vec::new();
+    let mut turn = 0;
+    let mut speaker = String::new();
+    let mut fences = Fences::default();
+    let mut in_user = false;
+    let mut offset = 0;
+
+    for (i, raw) in input.split_inclusive('\n').enumerate() {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let start = offset;
+        offset += raw.len();
+
+        // Lines before the first user block are not part of any turn
+        while spans.get(turn).is_some_and(|span| span.start <= start) {
+            turn += 1;
+        }
+        let Some(span) = turn.checked_sub(1).map(|i| &spans[i]) else {
+            continue;
+        };
+        let user = &doc.turns[turn - 1].user;
+
+        let (role, content) = if start < span.user.start {
+            continue;
+        } else if start < span.user.end {
+            if !in_user {
+                in_user = true;
+                fences = Fences::default();
+            }
+            let content = line.strip_prefix('>').unwrap_or(line);
+            let mut content = content.strip_prefix(' ').unwrap_or(content);
+            if let (true, Some(name)) = (start == span.user.start, user.username) {
+                let prefix = format!("@{}:", name);
+                content = content.strip_prefix(prefix.as_str()).unwrap_or(content).trim_start();
+            }
+            speaker = match user.username {
+                Some(SYSTEM_USERNAME) => Role::System.to_string(),
+                Some(name) => format!("@{}", name),
+                None => Role::User.to_string(),
+            };
+            let role = if user.username == Some(SYSTEM_USERNAME) { Role::System } else { Role::User };
+            (role, content)
+        } else {
+            if in_user {
+                in_user = false;
+                fences = Fences::default();
+                speaker = Role::Assistant.to_string();
+            }
+            if let Some(mut metadata) = parse_comment(line).filter(|_| !fences.is_open()) {
+                speaker = match metadata.remove(SPEAKER_KEY) {
+                    Some(name) => format!("@{}", name),
+                    None => Role::Assistant.to_string(),
+                };
+                continue;
+            }
+            (Role::Assistant, line)
+        };
+
+        if fences.update(content) {
+            continue;
+        }
+        let in_code = fences.is_open() && !fences.language().is_some_and(|language| TOOL_LANGUAGES.contains(&language));
+        if options.role.is_some_and(|wanted| wanted != role) || (options.code_only && !in_code) {
+            continue;
+        }
+        if pattern.is_match(content) {
+            matches.push(Match {
+                turn,
+                role,
+                speaker: speaker.clone(),
+                line: i + 1,
+                text: line.to_string(),
+            });
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "\
+> @alice: How do I print in Rust?
+> Briefly please
+Use `println!`:
+
+```rust
+println!(\"hi\");
+```
+<!-- cmf: name=reviewer -->
+Or print! without a newline.
+
+> @system: Prefer println
+
+> Thanks, print it is
+Done.
+";
+
+    fn lines(matches: &[Match]) -> Vec<(usize, usize, &str)> {
+        matches.iter().map(|m| (m.line, m.turn, m.speaker.as_str())).collect()
+    }
+
+    #[test]
+    fn test_reports_turn_and_speaker() {
+        let found = grep(INPUT, &Regex::new("(?i)print").unwrap(), &GrepOptions::new());
+        assert_eq!(
+            lines(&found),
+            [
+                (1, 1, "@alice"),
+                (3, 1, "assistant"),
+                (6, 1, "assistant"),
+                (9, 1, "@reviewer"),
+                (11, 2, "system"),
+                (13, 3, "user"),
+            ]
+        );
+        assert_eq!(found[0].text, "> @alice: How do I print in Rust?");
+        assert_eq!(found[4].role, Role::System);
+    }
+
+    #[test]
+    fn test_skips_prefixes_and_metadata() {
+        assert!(grep(INPUT, &Regex::new("alice|reviewer|cmf").unwrap(), &GrepOptions::new()).is_empty());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let print = Regex::new("print").unwrap();
+        let user = grep(INPUT, &print, &GrepOptions::new().role(Role::User));
+        assert_eq!(lines(&user), [(1, 1, "@alice"), (13, 3, "user")]);
+
+        let assistant = grep(INPUT, &print, &GrepOptions::new().role(Role::Assistant));
+        assert_eq!(assistant.len(), 3);
+
+        let code = grep(INPUT, &print, &GrepOptions::new().code_only(true));
+        assert_eq!(lines(&code), [(6, 1, "assistant")]);
+    }
+
+    #[test]
+    fn test_code_fences() {
+        let input = "\
+> Show me
+````markdown
+```rust
+print(1)
+```
+print(2)
+<!-- cmf: name=reviewer -->
+````
+~~~tool_call id=call_1
+print(3)
+~~~
+print(4)
+";
+        let found = grep(input, &Regex::new("print|cmf").unwrap(), &GrepOptions::new().code_only(true));
+        assert_eq!(lines(&found), [(4, 1, "assistant"), (6, 1, "assistant"), (7, 1, "assistant")]);
+        let all = grep(input, &Regex::new("print").unwrap(), &GrepOptions::new());
+        assert_eq!(all.len(), 4);
+    }
+}
@@ -0,0 +1,799 @@
+//! Full-text index over a directory of conversations
+//!
+//! Each turn is a document. Its words are indexed by position in two
+//! fields, the user message and the assistant reply, next to the turn's
+//! participants, `ts` date and code block languages for filtering. The
+//! index is a JSON file; `Index::update` reindexes only the files whose
+//! size or modification time changed, though loading, saving and dropping
+//! files still take time in proportion to the whole index.
+//!
+//! Queries combine words, `"quoted phrases"`, `OR` between alternatives,
+//! `-word` or `NOT word` to exclude, and filters: `user:alice`,
+//! `role:user|assistant|system`, `lang:rust`, `date:2026-01` (a prefix),
+//! `after:2026-01-01` and `before:2026-02-01`. Other `a:b` words, such as
+//! `std::mem` or URLs, are searched as text. Results are ranked by BM25.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::borrowed::DocumentRef;
+use crate::metadata::parse_comment;
+use crate::{Role, SYSTEM_USERNAME};
+
+/// Name of the index file kept in the indexed directory
+pub const INDEX_FILE: &str = ".cmf-index.json";
+
+/// Version of the index format; older indexes have to be rebuilt
+pub const INDEX_VERSION: u32 = 1;
+
+/// Metadata key holding a message's timestamp
+const DATE_KEY: &str = "ts";
+
+/// BM25 term frequency saturation and length normalization
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Size and modification time, to spot changed files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stamp {
+    pub size: u64,
+    pub secs: u64,
+    pub nanos: u32,
+}
+
+impl Stamp {
+    pub fn of(metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Stamp {
+            size: metadata.len(),
+            secs: modified.as_secs(),
+            nanos: modified.subsec_nanos(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct FileEntry {
+    stamp: Stamp,
+    docs: Vec<u32>,
+}
+
+/// An indexed turn
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TurnDoc {
+    /// Path relative to the indexed directory
+    pub file: String,
+    /// 1-based turn number
+    pub turn: usize,
+    /// 1-based line of the user block
+    pub line: usize,
+    /// Role of the user block: user or system
+    pub role: Role,
+    /// Username and assistant speakers, without `@`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub users: Vec<String>,
+    /// `ts` metadata of the user message or the first reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+    /// Languages of the turn's code blocks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub langs: Vec<String>,
+    /// First line of the turn, for listings
+    pub preview: String,
+    /// Words in the user message
+    pub user_words: u32,
+    /// Words in the assistant reply
+    pub assistant_words: u32,
+}
+
+/// Positions of a word in one document, by field
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Posting {
+    doc: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    user: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    assistant: Vec<u32>,
+}
+
+impl Posting {
+    fn positions(&self, fields: Fields) -> [&[u32]; 2] {
+        [
+            if fields.user { &self.user } else { &[] },
+            if fields.assistant { &self.assistant } else { &[] },
+        ]
+    }
+}
+
+/// Fields a query searches
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    user: bool,
+    assistant: bool,
+}
+
+/// Counts from `Index::update`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateSummary {
+    /// Conversation files in the directory
+    pub files: usize,
+    /// New or changed files that were (re)indexed
+    pub indexed: usize,
+    /// Files gone from the directory
+    pub removed: usize,
+    /// Paths that could not be read, with the reason; they are left out of
+    /// the index and tried again on the next update
+    pub skipped: Vec<(String, String)>,
+}
+
+/// An inverted index of turns
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Index {
+    version: u32,
+    files: BTreeMap<String, FileEntry>,
+    docs: BTreeMap<u32, TurnDoc>,
+    next_doc: u32,
+    postings: BTreeMap<String, Vec<Posting>>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: INDEX_VERSION,
+            files: BTreeMap::new(),
+            docs: BTreeMap::new(),
+            next_doc: 0,
+            postings: BTreeMap::new(),
+        }
+    }
+}
+
+/// Lowercased runs of letters, digits and underscores
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl Index {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read an index file, rejecting other format versions
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let index: Index = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if index.version != INDEX_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("index version {} is not supported, rebuild it", index.version),
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Write the index, replacing the file only once it is complete; the
+    /// whole index is rewritten, however little changed
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_string(self)?)?;
+        fs::rename(partial, path)
+    }
+
+    /// Number of indexed turns
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Bring the index up to date with the `.cmf` files under `root`;
+    /// fails only when `root` itself can't be read
+    pub fn update(&mut self, root: &Path) -> io::Result<UpdateSummary> {
+        let mut found = BTreeMap::new();
+        let mut skipped = Vec::new();
+        for entry in fs::read_dir(root)? {
+            find_files(root, entry, &mut found, &mut skipped);
+        }
+
+        let removed: Vec<String> = self.files.keys().filter(|path| !found.contains_key(*path)).cloned().collect();
+        let changed: Vec<(String, Stamp)> = found
+            .iter()
+            .filter(|(path, stamp)| self.files.get(*path).is_none_or(|entry| entry.stamp != **stamp))
+            .map(|(path, stamp)| (path.clone(), *stamp))
+            .collect();
+
+        self.remove_files(removed.iter().chain(changed.iter().map(|(path, _)| path)));
+        let mut indexed = 0;
+        for (path, stamp) in &changed {
+            match fs::read_to_string(root.join(path)) {
+                Ok(text) => {
+                    self.add_file(path, &text, *stamp);
+                    indexed += 1;
+                }
+                Err(e) => skipped.push((path.clone(), e.to_string())),
+            }
+        }
+
+        skipped.sort();
+        Ok(UpdateSummary {
+            files: found.len(),
+            indexed,
+            removed: removed.len(),
+            skipped,
+        })
+    }
+
+    /// Index the turns of a file, replacing any earlier version of it
+    pub fn add_file(&mut self, path: &str, text: &str, stamp: Stamp) {
+        self.remove_files([&path.to_string()]);
+
+        let (doc, spans) = DocumentRef::parse_with_spans(text);
+        let mut ids = Vec::new();
+        for (i, (turn, span)) in doc.turns.iter().zip(&spans).enumerate() {
+            let turn = turn.to_turn();
+            let id = self.next_doc;
+            self.next_doc += 1;
+            ids.push(id);
+
+            let mut words_at: BTreeMap<String, Posting> = BTreeMap::new();
+            let mut add = |text: &str, user: bool| {
+                let mut count = 0;
+                for (position, word) in words(text).enumerate() {
+                    let posting = words_at.entry(word).or_insert_with(|| Posting {
+                        doc: id,
+                        user: Vec::new(),
+                        assistant: Vec::new(),
+                    });
+                    let positions = if user { &mut posting.user } else { &mut posting.assistant };
+                    positions.push(position as u32);
+                    count += 1;
+                }
+                count
+            };
+            let assistant: Vec<&str> = turn.assistant.lines().filter(|line| parse_comment(line).is_none()).collect();
+            let user_words = add(&turn.user.content, true);
+            let assistant_words = add(&assistant.join("\n"), false);
+            for (word, posting) in words_at {
+                self.postings.entry(word).or_default().push(posting);
+            }
+
+            let entries = turn.assistant_entries();
+            let mut users: Vec<String> = turn
+                .user
+                .username
+                .iter()
+                .filter(|name| *name != SYSTEM_USERNAME)
+                .cloned()
+                .chain(entries.iter().filter_map(|entry| entry.name.clone()))
+                .collect();
+            users.sort();
+            users.dedup();
+            let mut langs: Vec<String> = turn.code_blocks().into_iter().filter_map(|block| block.language).collect();
+            langs.sort();
+            langs.dedup();
+            let preview = [turn.user.content.as_str(), turn.assistant.as_str()]
+                .into_iter()
+                .flat_map(str::lines)
+                .find(|line| !line.trim().is_empty() && parse_comment(line).is_none())
+                .unwrap_or_default();
+
+            self.docs.insert(
+                id,
+                TurnDoc {
+                    file: path.to_string(),
+                    turn: i + 1,
+                    line: text[..span.user.start].matches('\n').count() + 1,
+                    role: turn.role(),
+                    users,
+                    date: turn
+                        .user
+                        .metadata
+                        .get(DATE_KEY)
+                        .or_else(|| entries.iter().find_map(|entry| entry.metadata.get(DATE_KEY)))
+                        .cloned(),
+                    langs,
+                    preview: preview_text(preview),
+                    user_words,
+                    assistant_words,
+                },
+            );
+        }
+        self.files.insert(path.to_string(), FileEntry { stamp, docs: ids });
+    }
+
+    /// Drop files and their turns from the index, scanning every word's
+    /// postings once
+    pub fn remove_files<'a>(&mut self, paths: impl IntoIterator<Item = &'a String>) {
+        let mut gone = Vec::new();
+        for path in paths {
+            if let Some(entry) = self.files.remove(path) {
+                gone.extend(entry.docs);
+            }
+        }
+        if gone.is_empty() {
+            return;
+        }
+        for id in &gone {
+            self.docs.remove(id);
+        }
+        gone.sort_unstable();
+        self.postings.retain(|_, postings| {
+            postings.retain(|posting| gone.binary_search(&posting.doc).is_err());
+            !postings.is_empty()
+        });
+    }
+
+    /// Turns matching `query`, best first
+    pub fn search(&self, query: &Query, limit: usize) -> Vec<Hit<'_>> {
+        let fields = Fields {
+            user: query.role != Some(Role::Assistant),
+            assistant: query.role.is_none_or(|role| role == Role::Assistant),
+        };
+        let lengths: HashMap<u32, f64> = self
+            .docs
+            .iter()
+            .map(|(&id, doc)| {
+                let user = if fields.user { doc.user_words } else { 0 };
+                let assistant = if fields.assistant { doc.assistant_words } else { 0 };
+                (id, (user + assistant) as f64)
+            })
+            .collect();
+        let average = lengths.values().sum::<f64>() / lengths.len().max(1) as f64;
+        let total = self.docs.len() as f64;
+
+        let mut scores: Option<HashMap<u32, f64>> = None;
+        for alternatives in &query.required {
+            let mut group: HashMap<u32, f64> = HashMap::new();
+            for clause in alternatives {
+                let found = self.frequencies(clause, fields);
+                let df = found.len() as f64;
+                let idf = (1.0 + (total - df + 0.5) / (df + 0.5)).ln();
+                for (id, tf) in found {
+                    let tf = tf as f64;
+                    let norm = 1.0 - B + B * lengths[&id] / average.max(1.0);
+                    *group.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + K1 * norm);
+                }
+            }
+            scores = Some(match scores {
+                None => group,
+                Some(scores) => scores
+                    .into_iter()
+                    .filter_map(|(id, score)| group.get(&id).map(|more| (id, score + more)))
+                    .collect(),
+            });
+        }
+        let mut scores = scores.unwrap_or_else(|| self.docs.keys().map(|&id| (id, 0.0)).collect());
+        for clause in &query.excluded {
+            for id in self.frequencies(clause, fields).keys() {
+                scores.remove(id);
+            }
+        }
+
+        let mut hits: Vec<Hit> = scores
+            .into_iter()
+            .map(|(id, score)| Hit { doc: &self.docs[&id], score })
+            .filter(|hit| query.accepts(hit.doc))
+            .collect();
+        hits.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.doc.file.cmp(&b.doc.file))
+                .then_with(|| a.doc.turn.cmp(&b.doc.turn))
+        });
+        hits.truncate(limit);
+        hits
+    }
+
+    /// Occurrences of a word or phrase per document
+    fn frequencies(&self, clause: &Clause, fields: Fields) -> HashMap<u32, usize> {
+        let phrase = match clause {
+            Clause::Word(word) => std::slice::from_ref(word),
+            Clause::Phrase(words) => words.as_slice(),
+        };
+        let Some((first, rest)) = phrase.split_first() else {
+            return HashMap::new();
+        };
+        let lookup = |word: &String| -> HashMap<u32, &Posting> {
+            self.postings
+                .get(word)
+                .into_iter()
+                .flatten()
+                .map(|posting| (posting.doc, posting))
+                .collect()
+        };
+        let rest: Vec<HashMap<u32, &Posting>> = rest.iter().map(lookup).collect();
+
+        let mut found = HashMap::new();
+        for posting in self.postings.get(first).into_iter().flatten() {
+            let Some(following) = rest.iter().map(|postings| postings.get(&posting.doc)).collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            let mut count = 0;
+            for (field, starts) in posting.positions(fields).into_iter().enumerate() {
+                count += starts
+                    .iter()
+                    .filter(|&&start| {
+                        following.iter().enumerate().all(|(k, next)| {
+                            next.positions(fields)[field].binary_search(&(start + k as u32 + 1)).is_ok()
+                        })
+                    })
+                    .count();
+            }
+            if count > 0 {
+                found.insert(posting.doc, count);
+            }
+        }
+        found
+    }
+}
+
+/// Collect `.cmf` files at or under a directory entry, skipping hidden
+/// entries and noting those that can't be read; symlinks to files are
+/// followed, symlinks to directories are not, so there are no cycles
+fn find_files(
+    root: &Path,
+    entry: io::Result<fs::DirEntry>,
+    found: &mut BTreeMap<String, Stamp>,
+    skipped: &mut Vec<(String, String)>,
+) {
+    let entry = match entry {
+        Ok(entry) => entry,
+        Err(e) => return skipped.push((root.display().to_string(), e.to_string())),
+    };
+    let path = entry.path();
+    if entry.file_name().to_string_lossy().starts_with('.') {
+        return;
+    }
+    let relative = path.strip_prefix(root).unwrap_or(&path);
+    let relative: Vec<_> = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect();
+    let relative = relative.join("/");
+
+    let metadata = match entry.file_type() {
+        Ok(kind) if kind.is_dir() => match fs::read_dir(&path) {
+            Ok(entries) => {
+                for entry in entries {
+                    find_files(root, entry, found, skipped);
+                }
+                return;
+            }
+            Err(e) => return skipped.push((relative, e.to_string())),
+        },
+        Ok(_) if path.extension().is_some_and(|ext| ext == "cmf") => fs::metadata(&path),
+        Ok(_) => return,
+        Err(e) => Err(e),
+    };
+    match metadata {
+        Ok(metadata) if metadata.is_file() => {
+            found.insert(relative, Stamp::of(&metadata));
+        }
+        Ok(_) => {}
+        Err(e) => skipped.push((relative, e.to_string())),
+    }
+}
+
+fn preview_text(line: &str) -> String {
+    const MAX: usize = 80;
+    let line = line.trim_start_matches(['>', ' ']).trim();
+    match line.char_indices().nth(MAX) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// A search result
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hit<'a> {
+    #[serde(flatten)]
+    pub doc: &'a TurnDoc,
+    pub score: f64,
+}
+
+/// A word or a phrase of consecutive words
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clause {
+    Word(String),
+    Phrase(Vec<String>),
+}
+
+impl Clause {
+    /// The words of `text` as one clause; `None` when it has none
+    fn from_text(text: &str) -> Option<Self> {
+        let mut words: Vec<String> = words(text).collect();
+        match words.len() {
+            0 => None,
+            1 => words.pop().map(Clause::Word),
+            _ => Some(Clause::Phrase(words)),
+        }
+    }
+}
+
+/// A parsed search query
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Clauses that must all match, each with its `OR` alternatives
+    pub required: Vec<Vec<Clause>>,
+    /// Clauses that must not match
+    pub excluded: Vec<Clause>,
+    pub user: Option<String>,
+    pub role: Option<Role>,
+    pub lang: Option<String>,
+    /// Prefix of the `ts` date
+    pub date: Option<String>,
+    /// Inclusive lower bound of the `ts` date
+    pub after: Option<String>,
+    /// Exclusive upper bound of the `ts` date
+    pub before: Option<String>,
+}
+
+impl Query {
+    /// Whether a turn passes the filters
+    fn accepts(&self, doc: &TurnDoc) -> bool {
+        let role_ok = match self.role {
+            None => true,
+            Some(Role::Assistant) => doc.assistant_words > 0 || self.required.is_empty(),
+            Some(role) => doc.role == role,
+        };
+        let date = doc.date.as_deref();
+        role_ok
+            && self.user.as_ref().is_none_or(|user| doc.users.iter().any(|u| u.eq_ignore_ascii_case(user)))
+            && self.lang.as_ref().is_none_or(|lang| doc.langs.iter().any(|l| l.eq_ignore_ascii_case(lang)))
+            && self.date.as_ref().is_none_or(|prefix| date.is_some_and(|date| date.starts_with(prefix.as_str())))
+            && self.after.as_ref().is_none_or(|after| date.is_some_and(|date| date >= after.as_str()))
+            && self.before.as_ref().is_none_or(|before| date.is_some_and(|date| date < before.as_str()))
+    }
+}
+
+/// Keys of `key:value` filters; other words with a colon are searched
+const FILTERS: [&str; 6] = ["user", "role", "lang", "date", "after", "before"];
+
+impl FromStr for Query {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = Query::default();
+        let mut chars = s.chars().peekable();
+        let mut or = false;
+        let mut not = false;
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(&c) = chars.peek() else {
+                break;
+            };
+
+            let negated = std::mem::take(&mut not) | (c == '-');
+            if c == '-' {
+                chars.next();
+            }
+            let mut text = String::new();
+            let quoted = chars.next_if_eq(&'"').is_some();
+            if quoted {
+                text.extend(chars.by_ref().take_while(|&c| c != '"'));
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    text.push(c);
+                }
+            }
+
+            if !quoted {
+                match text.as_str() {
+                    "OR" => {
+                        or = !query.required.is_empty();
+                        continue;
+                    }
+                    "NOT" => {
+                        not = true;
+                        continue;
+                    }
+                    _ => {}
+                }
+                let filter = text.split_once(':').filter(|(key, _)| FILTERS.contains(key));
+                if let Some((key, value)) = filter {
+                    let value = value.to_string();
+                    match key {
+                        "user" => query.user = Some(value.trim_start_matches('@').to_string()),
+                        "role" => {
+                            query.role = Some(match value.as_str() {
+                                "user" => Role::User,
+                                "assistant" => Role::Assistant,
+                                "system" => Role::System,
+                                _ => return Err(format!("unknown role '{}' (expected user, assistant or system)", value)),
+                            })
+                        }
+                        "lang" => query.lang = Some(value),
+                        "date" => query.date = Some(value),
+                        "after" => query.after = Some(value),
+                        "before" => query.before = Some(value),
+                        _ => unreachable!("FILTERS lists the keys handled here"),
+                    }
+                    // A filter applies to the whole query, not as an alternative
+                    or = false;
+                    continue;
+                }
+            }
+
+            let alternative = std::mem::take(&mut or);
+            let Some(clause) = Clause::from_text(&text) else {
+                continue;
+            };
+            if negated {
+                query.excluded.push(clause);
+            } else if alternative {
+                query.required.last_mut().expect("OR follows a clause").push(clause);
+            } else {
+                query.required.push(vec![clause]);
+            }
+        }
+
+        if query == Query::default() {
+            return Err("empty query".to_string());
+        }
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAMP: Stamp = Stamp { size: 0, secs: 0, nanos: 0 };
+
+    fn index() -> Index {
+        let mut index = Index::new();
+        index.add_file(
+            "rust.cmf",
+            "<!-- cmf: ts=2026-01-05 -->\n> @alice: How does a lifetime work?\n\
+             A lifetime error means a borrow outlives its value.\n\n\
+             > @bob: Show a borrow checker example\n```rust\nlet r = &x;\n```\n",
+            STAMP,
+        );
+        index.add_file(
+            "py.cmf",
+            "> @system: Be brief\n\n> What is a borrow in Python?\n\
+             <!-- cmf: name=coder ts=2026-02-01 -->\nPython has no borrow checker.\n\n\
+             > Lifetime of objects?\nReference counting decides it.\n",
+            STAMP,
+        );
+        index
+    }
+
+    fn found(index: &Index, query: &str) -> Vec<(String, usize)> {
+        let query: Query = query.parse().unwrap();
+        index.search(&query, 10).iter().map(|hit| (hit.doc.file.clone(), hit.doc.turn)).collect()
+    }
+
+    fn turn(file: &str, turn: usize) -> (String, usize) {
+        (file.to_string(), turn)
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query: Query = "borrow \"lifetime error\" OR leak -python user:@alice lang:rust".parse().unwrap();
+        assert_eq!(
+            query.required,
+            [
+                vec![Clause::Word("borrow".into())],
+                vec![Clause::Phrase(vec!["lifetime".into(), "error".into()]), Clause::Word("leak".into())],
+            ]
+        );
+        assert_eq!(query.excluded, [Clause::Word("python".into())]);
+        assert_eq!((query.user.as_deref(), query.lang.as_deref()), (Some("alice"), Some("rust")));
+        assert!("role:robot".parse::<Query>().is_err());
+        assert!("  ".parse::<Query>().is_err());
+
+        let words = |words: &[&str]| Clause::Phrase(words.iter().map(|word| word.to_string()).collect());
+        let query: Query = "std::mem::take https://example.com".parse().unwrap();
+        assert_eq!(
+            query.required,
+            [vec![words(&["std", "mem", "take"])], vec![words(&["https", "example", "com"])]]
+        );
+        assert_eq!(query.user, None);
+
+        let query: Query = "foo OR user:alice bar".parse().unwrap();
+        assert_eq!(query.required, [vec![Clause::Word("foo".into())], vec![Clause::Word("bar".into())]]);
+        assert_eq!(query.user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_boolean_and_phrases() {
+        let index = index();
+        assert_eq!(found(&index, "borrow checker").len(), 2);
+        assert_eq!(found(&index, "\"lifetime error\""), [turn("rust.cmf", 1)]);
+        assert_eq!(found(&index, "\"error lifetime\""), []);
+        assert_eq!(found(&index, "borrow -python").len(), 2);
+        assert_eq!(found(&index, "borrow NOT python NOT checker"), [turn("rust.cmf", 1)]);
+        assert_eq!(found(&index, "counting OR outlives").len(), 2);
+    }
+
+    #[test]
+    fn test_filters() {
+        let index = index();
+        assert_eq!(found(&index, "borrow user:alice"), [turn("rust.cmf", 1)]);
+        assert_eq!(found(&index, "borrow user:coder"), [turn("py.cmf", 2)]);
+        assert_eq!(found(&index, "lang:rust"), [turn("rust.cmf", 2)]);
+        assert_eq!(found(&index, "python role:assistant"), [turn("py.cmf", 2)]);
+        assert_eq!(found(&index, "lifetime role:user"), [turn("py.cmf", 3), turn("rust.cmf", 1)]);
+        assert_eq!(found(&index, "brief role:system"), [turn("py.cmf", 1)]);
+        assert_eq!(found(&index, "borrow date:2026-02"), [turn("py.cmf", 2)]);
+        assert_eq!(found(&index, "after:2026-01-01 before:2026-02-01"), [turn("rust.cmf", 1)]);
+    }
+
+    #[test]
+    fn test_ranking_and_fields() {
+        let index = index();
+        let query: Query = "borrow".parse().unwrap();
+        let hits = index.search(&query, 10);
+        assert_eq!(hits.len(), 3);
+        assert!(hits.windows(2).all(|w| w[0].score >= w[1].score));
+        // Both the question and the reply of this turn mention borrows
+        assert_eq!(hits[0].doc.file, "py.cmf");
+        assert_eq!(hits[0].doc.users, ["coder"]);
+
+        let mut team = Index::new();
+        team.add_file("team.cmf", "> @bob: Hi\n<!-- cmf: name=alice -->\nHey\n<!-- cmf: name=bob -->\nHo\n", STAMP);
+        assert_eq!(team.search(&"hi".parse().unwrap(), 1)[0].doc.users, ["alice", "bob"]);
+        assert_eq!(hits[0].doc.line, 3);
+        assert_eq!(hits[0].doc.preview, "What is a borrow in Python?");
+        assert_eq!(index.search(&query, 1).len(), 1);
+    }
+
+    #[test]
+    fn test_update_reindexes_changed_files() {
+        let dir = std::env::temp_dir().join(format!("cmf-index-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.cmf"), "> alpha\nyes\n").unwrap();
+        fs::write(dir.join("sub/b.cmf"), "> beta\nyes\n").unwrap();
+        fs::write(dir.join("notes.txt"), "> gamma\n").unwrap();
+
+        let mut index = Index::new();
+        let summary = index.update(&dir).unwrap();
+        assert_eq!((summary.files, summary.indexed, summary.removed), (2, 2, 0));
+        assert_eq!(found(&index, "beta"), [turn("sub/b.cmf", 1)]);
+        assert_eq!(index.update(&dir).unwrap().indexed, 0);
+
+        fs::write(dir.join("a.cmf"), "> alpha again\nyes\n\n> delta\nno\n").unwrap();
+        fs::remove_file(dir.join("sub/b.cmf")).unwrap();
+        let summary = index.update(&dir).unwrap();
+        assert_eq!((summary.files, summary.indexed, summary.removed), (1, 1, 1));
+        assert_eq!(found(&index, "beta"), []);
+        assert_eq!(found(&index, "yes OR no").len(), 2);
+
+        // Unreadable files are skipped and a directory cycle is not followed
+        fs::write(dir.join("bad.cmf"), b"> caf\xe9\n").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, dir.join("sub/loop")).unwrap();
+            std::os::unix::fs::symlink(dir.join("gone.cmf"), dir.join("dangling.cmf")).unwrap();
+            std::os::unix::fs::symlink(dir.join("a.cmf"), dir.join("sub/link.cmf")).unwrap();
+        }
+        let summary = index.update(&dir).unwrap();
+        let skipped: Vec<&str> = summary.skipped.iter().map(|(path, _)| path.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!((skipped, summary.files), (vec!["bad.cmf", "dangling.cmf"], 3));
+        assert_eq!(found(&index, "alpha").len(), 1 + cfg!(unix) as usize);
+        assert_eq!(found(&index, "caf"), []);
+
+        let path = dir.join(INDEX_FILE);
+        index.save(&path).unwrap();
+        assert_eq!(Index::load(&path).unwrap(), index);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+//! Canonical, versioned JSON form of a parsed document
+//!
+//! ```json
+//! {"version": 1, "turns": [{"user": {"username": null, "content": "Hi"}, "assistant": "Hello!"}]}
+//! ```
+//!
+//! The JSON Schema for this form is generated from the model and kept in
+//! `schema/cmf.schema.json`.
+
+use schemars::{schema_for, JsonSchema};
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{Document, Turn};
+
+/// Version of the JSON representation; bumped on incompatible changes
+pub const JSON_VERSION: u32 = 1;
+
+/// A document as serialized by `Document::to_json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(title = "CMF document")]
+pub struct JsonDocument {
+    /// Version of this representation, currently 1
+    pub version: u32,
+    pub turns: Vec<Turn>,
+}
+
+impl Document {
+    /// Serialize to the canonical, versioned JSON form
+    pub fn to_json(&self) -> String {
+        let json = JsonDocument {
+            version: JSON_VERSION,
+            turns: self.turns.clone(),
+        };
+        serde_json::to_string_pretty(&json).expect("documents always serialize")
+    }
+
+    /// Parse the canonical JSON form, rejecting versions this crate doesn't know
+    pub fn from_json(input: &str) -> Result<Self, serde_json::Error> {
+        let json: JsonDocument = serde_json::from_str(input)?;
+        if json.version != JSON_VERSION {
+            return Err(serde_json::Error::custom(format!(
+                "unsupported version {} (expected {})",
+                json.version, JSON_VERSION
+            )));
+        }
+        Ok(Document { turns: json.turns })
+    }
+
+    /// JSON Schema of the canonical JSON form
+    pub fn json_schema() -> String {
+        serde_json::to_string_pretty(&schema_for!(JsonDocument)).expect("schemas always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let doc = Document::parse("<!-- cmf: ts=1 -->\n\n> @alice: Hi\nHello!\n<!-- cmf: name=bot -->\nMore.");
+        let json = doc.to_json();
+        assert!(json.starts_with("{\n  \"version\": 1,"));
+        assert_eq!(Document::from_json(&json).unwrap(), doc);
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let err = Document::from_json(r#"{"version": 2, "turns": []}"#).unwrap_err();
+        assert!(err.to_string().contains("unsupported version 2"));
+    }
+
+    #[test]
+    fn test_schema_file_is_current() {
+        // Regenerate with `cmf schema > schema/cmf.schema.json`
+        let committed = include_str!("../schema/cmf.schema.json");
+        assert_eq!(committed.trim_end(), Document::json_schema());
+    }
+}
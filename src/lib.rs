@@ -3,28 +3,311 @@
 //! A markdown-based interchange format for LLM conversations.
 //! User messages are blockquotes (`>`), assistant messages are plain markdown.
 
+pub mod attachments;
+pub mod borrowed;
+pub mod client;
+pub mod editor;
+pub mod grep;
+pub mod index;
+pub mod json;
+pub mod lsp;
+pub mod metadata;
+pub mod plain_renderer;
+pub mod reader;
+pub mod server;
+pub mod stats;
 pub mod terminal_renderer;
+pub mod tokens;
+pub mod tool_calls;
+pub mod truncate;
+pub mod viewer;
+pub mod writer;
 
-use serde::Serialize;
+pub use attachments::{Attachment, AttachmentKind, AttachmentOptions};
+pub use borrowed::{DocumentRef, TurnRef, UserMessageRef};
+pub use client::ChatClient;
+pub use editor::{DocumentEditor, TextEdit};
+pub use json::{JsonDocument, JSON_VERSION};
+pub use metadata::Metadata;
+pub use tool_calls::ToolCall;
+pub use writer::CmfWriter;
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use attachments::find_attachments;
+use editor::escape_assistant;
+use metadata::{parse_comment, write_comment};
+use reader::{TurnParser, TurnReader};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::str::FromStr;
+use tool_calls::{find_tool_blocks, write_tool_call, write_tool_result, ToolBlockKind, ToolIds};
 
 /// A parsed user message with optional attribution
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct UserMessage {
     /// Optional username (from `@username:` prefix)
     pub username: Option<String>,
     /// The message content (without the `>` prefix)
     pub content: String,
+    /// From a `<!-- cmf: ... -->` comment before the user block
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+/// Username reserved for system messages (`> @system: ...`)
+pub const SYSTEM_USERNAME: &str = "system";
+
+/// Line separating consecutive assistant messages within one turn
+pub const MESSAGE_SEPARATOR: &str = "<!-- cmf -->";
+
+/// Metadata key naming the speaker of an assistant message
+pub const SPEAKER_KEY: &str = "name";
+
+/// Who a message is from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    /// Output of a tool call, from a `tool_result` block
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single message in the flat, role-aware view of a document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    /// Optional participant name (the `@username` of user messages,
+    /// or the tool name of tool results)
+    pub name: Option<String>,
+    pub content: String,
+    /// Tool calls made by an assistant message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// The call a tool message answers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// Images and files referenced from a user message
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// Timestamps, model names, ids and the like from `<!-- cmf: ... -->`
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
+}
+
+impl Message {
+    /// A plain message without tool data or attachments
+    pub fn new(role: Role, content: &str) -> Self {
+        Self {
+            role,
+            name: None,
+            content: content.to_string(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+            attachments: Vec::new(),
+            metadata: Metadata::new(),
+        }
+    }
+}
+
+/// One assistant message of a turn, with its attribution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssistantMessage {
+    /// Speaker name, from `name=` in the `<!-- cmf: ... -->` comment opening the message
+    pub name: Option<String>,
+    pub content: String,
+    /// Remaining entries of the opening comment
+    #[serde(default, skip_serializing_if = "Metadata::is_empty")]
+    pub metadata: Metadata,
 }
 
 /// A single turn in a conversation (user + assistant)
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct Turn {
     pub user: UserMessage,
     pub assistant: String,
 }
 
+/// A fenced or indented code block found in assistant content
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CodeBlock {
+    /// Language from the fence info string, if any
+    pub language: Option<String>,
+    pub code: String,
+}
+
+impl Turn {
+    /// Role of the opening message: `> @system:` lines are system messages
+    pub fn role(&self) -> Role {
+        if self.user.username.as_deref() == Some(SYSTEM_USERNAME) {
+            Role::System
+        } else {
+            Role::User
+        }
+    }
+
+    /// The assistant response split into separate messages at
+    /// `<!-- cmf -->` lines; empty messages are skipped
+    pub fn assistant_messages(&self) -> Vec<String> {
+        self.assistant_entries()
+            .into_iter()
+            .map(|message| message.content)
+            .collect()
+    }
+
+    /// Assistant messages with their speaker names and metadata
+    pub fn assistant_entries(&self) -> Vec<AssistantMessage> {
+        let mut entries = Vec::new();
+        let mut metadata = Metadata::new();
+        let mut lines: Vec<&str> = Vec::new();
+
+        let mut push = |mut metadata: Metadata, lines: &[&str]| {
+            let content = trim_assistant_block(lines);
+            if !content.is_empty() {
+                entries.push(AssistantMessage {
+                    name: metadata.remove(SPEAKER_KEY),
+                    content,
+                    metadata,
+                });
+            }
+        };
+
+        // A comment inside a code block is code, not a message boundary
+        let mut fences = Fences::default();
+        for line in self.assistant.lines() {
+            let code = fences.update(line) || fences.is_open();
+            if let Some(next) = parse_comment(line).filter(|_| !code) {
+                push(std::mem::replace(&mut metadata, next), &lines);
+                lines.clear();
+            } else {
+                lines.push(line);
+            }
+        }
+        push(metadata, &lines);
+
+        entries
+    }
+
+    /// Whether the user message is attributed to `username` (with or without the leading `@`)
+    pub fn is_from(&self, username: &str) -> bool {
+        let username = username.strip_prefix('@').unwrap_or(username);
+        self.user.username.as_deref() == Some(username)
+    }
+
+    /// All tool calls made in the assistant response
+    pub fn tool_calls(&self) -> Vec<ToolCall> {
+        self.messages()
+            .into_iter()
+            .flat_map(|message| message.tool_calls)
+            .collect()
+    }
+
+    /// This turn as a flat sequence of messages
+    pub fn messages(&self) -> Vec<Message> {
+        let mut ids = ToolIds::default();
+        ids.reserve(&self.assistant);
+        self.messages_with_ids(&mut ids)
+    }
+
+    fn messages_with_ids(&self, ids: &mut ToolIds) -> Vec<Message> {
+        let mut messages = Vec::new();
+        match self.role() {
+            // An empty system line only exists to open with assistant content
+            Role::System if self.user.content.is_empty() => {}
+            Role::System => messages.push(Message {
+                metadata: self.user.metadata.clone(),
+                ..Message::new(Role::System, &self.user.content)
+            }),
+            _ => messages.push(Message {
+                name: self.user.username.clone(),
+                attachments: find_attachments(&self.user.content),
+                metadata: self.user.metadata.clone(),
+                ..Message::new(Role::User, &self.user.content)
+            }),
+        }
+        for entry in self.assistant_entries() {
+            let first = messages.len();
+            split_tool_blocks(&entry.content, ids, &mut messages);
+            // The speaker stays the same across tool results; other
+            // metadata describes the first message only
+            for message in &mut messages[first..] {
+                if message.role == Role::Assistant {
+                    message.name = entry.name.clone();
+                }
+            }
+            if let Some(message) = messages.get_mut(first) {
+                message.metadata = entry.metadata;
+            }
+        }
+        messages
+    }
+
+    /// Extract the code blocks from the assistant response, in order
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        let mut blocks = Vec::new();
+        let mut current: Option<CodeBlock> = None;
+
+        for event in Parser::new(&self.assistant) {
+            match event {
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let language = match kind {
+                        CodeBlockKind::Fenced(info) => info
+                            .split_whitespace()
+                            .next()
+                            .map(|lang| lang.to_string()),
+                        CodeBlockKind::Indented => None,
+                    };
+                    current = Some(CodeBlock {
+                        language,
+                        code: String::new(),
+                    });
+                }
+                Event::Text(text) => {
+                    if let Some(ref mut block) = current {
+                        block.code.push_str(&text);
+                    }
+                }
+                Event::End(Tag::CodeBlock(_)) => {
+                    if let Some(block) = current.take() {
+                        blocks.push(block);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        blocks
+    }
+}
+
 /// A parsed CMF document
-#[derive(Debug, Clone, PartialEq, Serialize)]
+///
+/// A `Document` holds turns, not the text they came from. To edit a file
+/// without rewriting it, with `push_user`, `push_assistant`,
+/// `append_to_assistant`, `insert_turn`, `remove_turn`, `edit_user`,
+/// `truncate_after` and `retry_last`, use [`DocumentEditor`], which keeps
+/// the source next to the parsed document.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Document {
     pub turns: Vec<Turn>,
 }
@@ -46,6 +329,11 @@ impl Document {
                 output.push_str("\n\n");
             }
 
+            if !turn.user.metadata.is_empty() {
+                output.push_str(&write_comment(&turn.user.metadata));
+                output.push_str("\n\n");
+            }
+
             // Format user message with > prefix
             let user_content = if let Some(ref username) = turn.user.username {
                 format!("@{}: {}", username, turn.user.content)
@@ -73,48 +361,120 @@ impl Document {
 
     /// Parse a CMF document from markdown text
     pub fn parse(input: &str) -> Self {
-        let mut turns = Vec::new();
-        let mut current_user_lines: Vec<String> = Vec::new();
-        let mut current_assistant_lines: Vec<String> = Vec::new();
-        let mut in_user_block = false;
-        let mut seen_first_user = false;
-
-        for line in input.lines() {
-            let is_user_line = line.starts_with('>');
-
-            if is_user_line {
-                // If we were collecting assistant content, finalize the previous turn
-                if seen_first_user && !in_user_block && !current_user_lines.is_empty() {
-                    let user = parse_user_block(&current_user_lines);
-                    let assistant = trim_assistant_block(&current_assistant_lines);
-                    turns.push(Turn { user, assistant });
-                    current_user_lines.clear();
-                    current_assistant_lines.clear();
-                }
+        let mut parser = TurnParser::default();
+        let mut turns: Vec<Turn> = input.lines().filter_map(|line| parser.push_line(line)).collect();
+        turns.extend(parser.finish());
+        Document { turns }
+    }
 
-                in_user_block = true;
-                seen_first_user = true;
-                // Strip the leading `>` and optional single space
-                let content = line.strip_prefix('>').unwrap_or(line);
-                let content = content.strip_prefix(' ').unwrap_or(content);
-                current_user_lines.push(content.to_string());
-            } else {
-                if in_user_block {
-                    // Transition from user to assistant
-                    in_user_block = false;
+    /// Parse from a reader, one line at a time
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let turns = TurnReader::new(reader).collect::<io::Result<_>>()?;
+        Ok(Document { turns })
+    }
+
+    /// Flatten the document into a uniform sequence of messages
+    ///
+    /// An empty `> @system:` line yields no message, which lets documents
+    /// start with assistant content.
+    pub fn messages(&self) -> Vec<Message> {
+        let mut ids = self.tool_ids();
+        self.turns
+            .iter()
+            .flat_map(|turn| turn.messages_with_ids(&mut ids))
+            .collect()
+    }
+
+    /// Tool id assignment with every id written in the document reserved
+    pub(crate) fn tool_ids(&self) -> ToolIds {
+        let mut ids = ToolIds::default();
+        for turn in &self.turns {
+            ids.reserve(&turn.assistant);
+        }
+        ids
+    }
+
+    /// Build a document from a flat message sequence, the inverse of `messages()`
+    ///
+    /// Assistant lines starting with `>` or `<!--` are indented one space,
+    /// as `DocumentEditor::push_assistant` does, so they stay assistant
+    /// content.
+    pub fn from_messages(messages: &[Message]) -> Self {
+        let mut turns: Vec<Turn> = Vec::new();
+        let mut previous_role = None;
+        // Speaker named by the last comment written in the current turn
+        let mut speaker: Option<String> = None;
+
+        for message in messages {
+            match message.role {
+                Role::System | Role::User => {
+                    let username = match message.role {
+                        Role::System => Some(SYSTEM_USERNAME.to_string()),
+                        _ => message.name.clone(),
+                    };
+                    turns.push(Turn {
+                        user: UserMessage {
+                            username,
+                            content: message.content.clone(),
+                            metadata: message.metadata.clone(),
+                        },
+                        assistant: String::new(),
+                    });
+                    speaker = None;
                 }
-                if seen_first_user {
-                    current_assistant_lines.push(line.to_string());
+                Role::Assistant | Role::Tool => {
+                    if turns.is_empty() {
+                        // Assistant-first: open with an empty system line
+                        turns.push(Turn {
+                            user: UserMessage {
+                                username: Some(SYSTEM_USERNAME.to_string()),
+                                content: String::new(),
+                                metadata: Metadata::new(),
+                            },
+                            assistant: String::new(),
+                        });
+                    }
+                    let turn = turns.last_mut().expect("a turn was just ensured");
+                    let mut parts = Vec::new();
+                    // Metadata always needs its comment; otherwise only back-to-back
+                    // assistant messages need an explicit split, as tool results
+                    // already end the message before them
+                    let name = match message.role {
+                        Role::Assistant => message.name.clone(),
+                        _ => speaker.clone(),
+                    };
+                    if !message.metadata.is_empty()
+                        || name != speaker
+                        || (message.role == Role::Assistant && previous_role == Some(Role::Assistant))
+                    {
+                        let mut metadata = message.metadata.clone();
+                        if let Some(ref name) = name {
+                            metadata.insert(SPEAKER_KEY.to_string(), name.clone());
+                        }
+                        parts.push(write_comment(&metadata));
+                        speaker = name;
+                    }
+                    if !message.content.is_empty() {
+                        parts.push(match message.role {
+                            Role::Tool => write_tool_result(
+                                message.tool_call_id.as_deref().unwrap_or_default(),
+                                message.name.as_deref(),
+                                &message.content,
+                            ),
+                            _ => escape_assistant(&message.content, true),
+                        });
+                    }
+                    parts.extend(message.tool_calls.iter().map(write_tool_call));
+
+                    for part in parts {
+                        if !turn.assistant.is_empty() {
+                            turn.assistant.push_str("\n\n");
+                        }
+                        turn.assistant.push_str(&part);
+                    }
                 }
-                // Lines before the first user block are ignored (preamble/frontmatter)
             }
-        }
-
-        // Finalize the last turn if we have user content
-        if !current_user_lines.is_empty() {
-            let user = parse_user_block(&current_user_lines);
-            let assistant = trim_assistant_block(&current_assistant_lines);
-            turns.push(Turn { user, assistant });
+            previous_role = Some(message.role);
         }
 
         Document { turns }
@@ -130,15 +490,15 @@ impl Document {
     pub fn check(input: &str) -> Vec<Issue> {
         let mut issues = Vec::new();
         let mut prev_was_blank_or_start = true;
-        let mut line_num = 0;
 
-        for line in input.lines() {
-            line_num += 1;
+        for (idx, line) in input.lines().enumerate() {
+            let line_num = idx + 1;
 
             // Check for user lines that don't start after blank/BOF
             if line.starts_with('>') && !prev_was_blank_or_start {
                 issues.push(Issue {
                     line: line_num,
+                    kind: IssueKind::MissingBlankLine,
                     message: "User line not preceded by blank line or start of file".to_string(),
                 });
             }
@@ -148,21 +508,162 @@ impl Document {
                 // This is fine - it's an escaped assistant blockquote
             }
 
-            prev_was_blank_or_start = line.trim().is_empty();
+            prev_was_blank_or_start = line.trim().is_empty() || parse_comment(line).is_some();
         }
 
+        // Tool results must answer a call made before them
+        let (_, spans) = DocumentRef::parse_with_spans(input);
+        let mut ids = ToolIds::default();
+        for span in &spans {
+            let assistant = span.user.end;
+            for block in find_tool_blocks(&input[assistant..span.end]) {
+                match block.kind {
+                    ToolBlockKind::Call { .. } => {
+                        ids.call_id(block.id);
+                    }
+                    ToolBlockKind::Result { .. } => {
+                        if !ids.is_answer(block.id.as_deref()) {
+                            let offset = assistant + block.range.start;
+                            issues.push(Issue {
+                                line: input[..offset].matches('\n').count() + 1,
+                                kind: IssueKind::OrphanToolResult,
+                                message: match block.id {
+                                    Some(ref id) => format!("tool_result id={} answers no pending tool_call", id),
+                                    None => "tool_result without a pending tool_call".to_string(),
+                                },
+                            });
+                        }
+                        ids.result_id(block.id);
+                    }
+                }
+            }
+        }
+        issues.sort_by_key(|issue| issue.line);
+
         issues
     }
+
+    /// Report attachments in user lines whose files do not exist under `base_dir`
+    pub fn check_attachments(input: &str, base_dir: &Path) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        for (idx, line) in input.lines().enumerate() {
+            let Some(user_line) = line.strip_prefix('>') else {
+                continue;
+            };
+            for attachment in find_attachments(user_line) {
+                if attachment.is_local() && !attachment.path(base_dir).exists() {
+                    issues.push(Issue {
+                        line: idx + 1,
+                        kind: IssueKind::MissingAttachment,
+                        message: format!("attachment not found: {}", attachment.target),
+                    });
+                }
+            }
+        }
+        issues
+    }
+}
+
+/// A 1-based, inclusive range of turns: `3`, `3..5`, `3..` or `..5`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TurnRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl TurnRange {
+    /// Whether the 1-based turn number falls in the range
+    pub fn contains(&self, turn: usize) -> bool {
+        turn >= self.start && self.end.is_none_or(|end| turn <= end)
+    }
+}
+
+impl FromStr for TurnRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_bound = |bound: &str| -> Result<usize, String> {
+            match bound.trim().parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n),
+                _ => Err(format!("invalid turn number '{}' (turns start at 1)", bound)),
+            }
+        };
+
+        let range = match s.split_once("..") {
+            Some((start, end)) => TurnRange {
+                start: if start.is_empty() { 1 } else { parse_bound(start)? },
+                end: if end.is_empty() { None } else { Some(parse_bound(end)?) },
+            },
+            None => {
+                let n = parse_bound(s)?;
+                TurnRange { start: n, end: Some(n) }
+            }
+        };
+
+        if range.end.is_some_and(|end| end < range.start) {
+            return Err(format!("invalid turn range '{}'", s));
+        }
+        Ok(range)
+    }
+}
+
+impl Document {
+    /// Keep only the turns within `range`
+    pub fn select(&self, range: TurnRange) -> Document {
+        Document {
+            turns: self
+                .turns
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| range.contains(i + 1))
+                .map(|(_, turn)| turn.clone())
+                .collect(),
+        }
+    }
+
+    /// Keep only the last `n` turns
+    pub fn last(&self, n: usize) -> Document {
+        let skip = self.turns.len().saturating_sub(n);
+        Document {
+            turns: self.turns[skip..].to_vec(),
+        }
+    }
+
+    /// Keep only turns whose user message is attributed to `username`
+    /// (with or without the leading `@`)
+    pub fn by_user(&self, username: &str) -> Document {
+        Document {
+            turns: self
+                .turns
+                .iter()
+                .filter(|turn| turn.is_from(username))
+                .cloned()
+                .collect(),
+        }
+    }
 }
 
 /// A conformance issue found during checking
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Issue {
     pub line: usize,
+    pub kind: IssueKind,
     pub message: String,
 }
 
-fn parse_user_block(lines: &[String]) -> UserMessage {
+/// What an `Issue` is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A user line not preceded by a blank line; fixed by inserting one
+    MissingBlankLine,
+    /// A `tool_result` block answering no pending `tool_call`
+    OrphanToolResult,
+    /// An attachment whose file does not exist
+    MissingAttachment,
+}
+
+fn parse_user_block(lines: &[String], metadata: Metadata) -> UserMessage {
     let content = lines.join("\n");
 
     // Check for @username: prefix on first line
@@ -179,6 +680,7 @@ fn parse_user_block(lines: &[String]) -> UserMessage {
                 return UserMessage {
                     username: Some(username),
                     content: format!("{}{}", first_content, rest),
+                    metadata,
                 };
             }
         }
@@ -187,15 +689,117 @@ fn parse_user_block(lines: &[String]) -> UserMessage {
     UserMessage {
         username: None,
         content,
+        metadata,
+    }
+}
+
+/// Split one assistant message at its tool blocks: text and the calls that
+/// follow it form an assistant message, each result becomes a tool message
+fn split_tool_blocks(content: &str, ids: &mut ToolIds, messages: &mut Vec<Message>) {
+    let mut current = Message::new(Role::Assistant, "");
+    let mut pos = 0;
+
+    fn flush(current: &mut Message, messages: &mut Vec<Message>) {
+        if !current.content.is_empty() || !current.tool_calls.is_empty() {
+            messages.push(std::mem::replace(current, Message::new(Role::Assistant, "")));
+        }
+    }
+
+    fn push_text(text: &str, current: &mut Message, messages: &mut Vec<Message>) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        // Text after tool calls starts a new message
+        if !current.tool_calls.is_empty() {
+            flush(current, messages);
+        }
+        if !current.content.is_empty() {
+            current.content.push_str("\n\n");
+        }
+        current.content.push_str(text);
+    }
+
+    for block in find_tool_blocks(content) {
+        push_text(&content[pos..block.range.start], &mut current, messages);
+        pos = block.range.end;
+
+        match block.kind {
+            ToolBlockKind::Call { name } => current.tool_calls.push(ToolCall {
+                id: ids.call_id(block.id),
+                name,
+                arguments: block.body,
+            }),
+            ToolBlockKind::Result { name } => {
+                flush(&mut current, messages);
+                messages.push(Message {
+                    name,
+                    tool_call_id: Some(ids.result_id(block.id)),
+                    ..Message::new(Role::Tool, &block.body)
+                });
+            }
+        }
+    }
+
+    push_text(&content[pos..], &mut current, messages);
+    flush(&mut current, messages);
+}
+
+/// Tracks fenced code blocks line by line; a block closes only at a fence of
+/// its own character, at least as long as the opening one
+#[derive(Debug, Default)]
+pub(crate) struct Fences {
+    open: Option<(char, usize)>,
+    language: String,
+}
+
+impl Fences {
+    /// Feed the next line, returning whether it opens or closes a block
+    pub fn update(&mut self, line: &str) -> bool {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let trimmed = &line[indent..];
+        let Some(marker) = trimmed.chars().next().filter(|&c| indent <= 3 && (c == '`' || c == '~')) else {
+            return false;
+        };
+        let len = trimmed.len() - trimmed.trim_start_matches(marker).len();
+        let rest = trimmed[len..].trim();
+        if len < 3 {
+            return false;
+        }
+        match self.open {
+            Some((open, open_len)) => {
+                let closes = marker == open && len >= open_len && rest.is_empty();
+                if closes {
+                    self.open = None;
+                }
+                closes
+            }
+            None if marker == '`' && rest.contains('`') => false,
+            None => {
+                self.open = Some((marker, len));
+                self.language = rest.split_whitespace().next().unwrap_or_default().to_string();
+                true
+            }
+        }
+    }
+
+    /// Language of the open block, from the first word of its info string
+    pub fn language(&self) -> Option<&str> {
+        self.open.and(Some(self.language.as_str())).filter(|language| !language.is_empty())
+    }
+
+    /// Whether the lines fed so far leave a block open
+    pub fn is_open(&self) -> bool {
+        self.open.is_some()
     }
 }
 
-fn trim_assistant_block(lines: &[String]) -> String {
+fn trim_assistant_block<S: AsRef<str>>(lines: &[S]) -> String {
     // Trim leading and trailing blank lines
-    let start = lines.iter().position(|l| !l.trim().is_empty()).unwrap_or(0);
+    let start = lines.iter().position(|l| !l.as_ref().trim().is_empty()).unwrap_or(0);
     let end = lines
         .iter()
-        .rposition(|l| !l.trim().is_empty())
+        .rposition(|l| !l.as_ref().trim().is_empty())
         .map(|i| i + 1)
         .unwrap_or(0);
 
@@ -203,76 +807,275 @@ fn trim_assistant_block(lines: &[String]) -> String {
         return String::new();
     }
 
-    lines[start..end].join("\n")
+    lines[start..end]
+        .iter()
+        .map(|l| l.as_ref())
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 /// OpenAI Chat Completions message format
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
-    pub content: String,
+    /// Participant name of user and assistant messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub content: ChatContent,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ChatToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+/// Chat message content: plain text, or parts when attachments are present
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+/// A multimodal content part in Chat Completions format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ChatImageUrl },
+    File { file: ChatFile },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatImageUrl {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatFile {
+    pub filename: String,
+    pub file_data: String,
+}
+
+/// A tool call in Chat Completions format
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub function: ChatFunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatFunctionCall {
+    pub name: String,
+    pub arguments: String,
 }
 
 /// OpenAI Responses API message format
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponsesMessage {
     #[serde(rename = "type")]
     pub msg_type: String,
+    /// From the message's `id` metadata
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub role: String,
     pub content: Vec<ContentPart>,
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct ContentPart {
+/// A content part in Responses API format
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    InputText {
+        text: String,
+    },
+    OutputText {
+        text: String,
+    },
+    InputImage {
+        image_url: String,
+    },
+    InputFile {
+        filename: String,
+        file_data: String,
+    },
+}
+
+/// A `function_call` item in Responses API input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesFunctionCall {
+    #[serde(rename = "type")]
+    pub item_type: String,
+    pub call_id: String,
+    pub name: String,
+    pub arguments: String,
+}
+
+/// A `function_call_output` item in Responses API input
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponsesFunctionCallOutput {
     #[serde(rename = "type")]
-    pub part_type: String,
-    pub text: String,
+    pub item_type: String,
+    pub call_id: String,
+    pub output: String,
+}
+
+/// An input item for the Responses API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ResponsesItem {
+    Message(ResponsesMessage),
+    FunctionCall(ResponsesFunctionCall),
+    FunctionCallOutput(ResponsesFunctionCallOutput),
+}
+
+impl ResponsesItem {
+    /// Role of the message this item came from
+    pub fn role(&self) -> &str {
+        match self {
+            ResponsesItem::Message(message) => &message.role,
+            ResponsesItem::FunctionCall(_) => Role::Assistant.as_str(),
+            ResponsesItem::FunctionCallOutput(_) => Role::Tool.as_str(),
+        }
+    }
 }
 
 impl Document {
     /// Convert to OpenAI Chat Completions format
     pub fn to_openai_chat(&self) -> Vec<ChatMessage> {
-        let mut messages = Vec::new();
-        for turn in &self.turns {
-            messages.push(ChatMessage {
-                role: "user".to_string(),
-                content: turn.user.content.clone(),
-            });
-            if !turn.assistant.is_empty() {
-                messages.push(ChatMessage {
-                    role: "assistant".to_string(),
-                    content: turn.assistant.clone(),
-                });
-            }
-        }
-        messages
+        self.to_openai_chat_with(&AttachmentOptions::default())
+    }
+
+    /// Convert to OpenAI Chat Completions format, resolving attachments
+    /// with the given options
+    pub fn to_openai_chat_with(&self, options: &AttachmentOptions) -> Vec<ChatMessage> {
+        self.messages()
+            .into_iter()
+            .map(|message| ChatMessage::from_message(message, options))
+            .collect()
     }
 
     /// Convert to OpenAI Responses API format
-    pub fn to_openai_responses(&self) -> Vec<ResponsesMessage> {
-        let mut messages = Vec::new();
-        for turn in &self.turns {
-            messages.push(ResponsesMessage {
+    pub fn to_openai_responses(&self) -> Vec<ResponsesItem> {
+        self.to_openai_responses_with(&AttachmentOptions::default())
+    }
+
+    /// Convert to OpenAI Responses API format, resolving attachments
+    /// with the given options
+    pub fn to_openai_responses_with(&self, options: &AttachmentOptions) -> Vec<ResponsesItem> {
+        self.messages()
+            .into_iter()
+            .flat_map(|message| ResponsesItem::from_message(message, options))
+            .collect()
+    }
+}
+
+impl ChatMessage {
+    /// Convert one message, resolving attachments with the given options
+    pub fn from_message(message: Message, options: &AttachmentOptions) -> Self {
+        ChatMessage {
+            role: message.role.to_string(),
+            name: match message.role {
+                Role::User | Role::Assistant => message.name,
+                _ => None,
+            },
+            content: chat_content(message.content, &message.attachments, options),
+            tool_calls: message
+                .tool_calls
+                .into_iter()
+                .map(|call| ChatToolCall {
+                    id: call.id,
+                    call_type: "function".to_string(),
+                    function: ChatFunctionCall {
+                        name: call.name,
+                        arguments: call.arguments,
+                    },
+                })
+                .collect(),
+            tool_call_id: message.tool_call_id,
+        }
+    }
+}
+
+impl ResponsesItem {
+    /// Convert one message into its input items: the message itself, then
+    /// one `function_call` per tool call
+    pub fn from_message(message: Message, options: &AttachmentOptions) -> Vec<Self> {
+        if message.role == Role::Tool {
+            return vec![ResponsesItem::FunctionCallOutput(ResponsesFunctionCallOutput {
+                item_type: "function_call_output".to_string(),
+                call_id: message.tool_call_id.unwrap_or_default(),
+                output: message.content,
+            })];
+        }
+
+        let mut items = Vec::new();
+        if !message.content.is_empty() {
+            let text = match message.role {
+                Role::Assistant => ContentPart::OutputText { text: message.content },
+                _ => ContentPart::InputText { text: message.content },
+            };
+            let mut content = vec![text];
+            // Local attachments that are not inlined stay links in the text
+            content.extend(message.attachments.iter().filter_map(|attachment| {
+                let url = attachment.url(options)?;
+                Some(match attachment.kind {
+                    AttachmentKind::Image => ContentPart::InputImage { image_url: url },
+                    AttachmentKind::File => ContentPart::InputFile {
+                        filename: attachment.filename(),
+                        file_data: url,
+                    },
+                })
+            }));
+            items.push(ResponsesItem::Message(ResponsesMessage {
                 msg_type: "message".to_string(),
-                role: "user".to_string(),
-                content: vec![ContentPart {
-                    part_type: "input_text".to_string(),
-                    text: turn.user.content.clone(),
-                }],
-            });
-            if !turn.assistant.is_empty() {
-                messages.push(ResponsesMessage {
-                    msg_type: "message".to_string(),
-                    role: "assistant".to_string(),
-                    content: vec![ContentPart {
-                        part_type: "output_text".to_string(),
-                        text: turn.assistant.clone(),
-                    }],
-                });
-            }
+                id: message.metadata.get("id").cloned(),
+                role: message.role.to_string(),
+                content,
+            }));
         }
-        messages
+        for call in message.tool_calls {
+            items.push(ResponsesItem::FunctionCall(ResponsesFunctionCall {
+                item_type: "function_call".to_string(),
+                call_id: call.id,
+                name: call.name,
+                arguments: call.arguments,
+            }));
+        }
+        items
+    }
+}
+
+/// Text alone, or a text part followed by one part per attachment
+///
+/// Local attachments are only sent when inlined, as Chat has no way to
+/// reference a path; otherwise they stay links in the text.
+fn chat_content(text: String, attachments: &[Attachment], options: &AttachmentOptions) -> ChatContent {
+    let attached: Vec<ChatContentPart> = attachments
+        .iter()
+        .filter_map(|attachment| {
+            let url = attachment.url(options)?;
+            Some(match attachment.kind {
+                AttachmentKind::Image => ChatContentPart::ImageUrl {
+                    image_url: ChatImageUrl { url },
+                },
+                AttachmentKind::File => ChatContentPart::File {
+                    file: ChatFile {
+                        filename: attachment.filename(),
+                        file_data: url,
+                    },
+                },
+            })
+        })
+        .collect();
+    if attached.is_empty() {
+        return ChatContent::Text(text);
     }
+
+    let mut parts = vec![ChatContentPart::Text { text }];
+    parts.extend(attached);
+    ChatContent::Parts(parts)
 }
 
 #[cfg(test)]
@@ -352,6 +1155,7 @@ Third paragraph."#;
                     user: UserMessage {
                         username: None,
                         content: "Hello!".to_string(),
+                        metadata: Metadata::new(),
                     },
                     assistant: "Hi there!".to_string(),
                 },
@@ -367,6 +1171,7 @@ Third paragraph."#;
                 user: UserMessage {
                     username: None,
                     content: "Line one\nLine two".to_string(),
+                    metadata: Metadata::new(),
                 },
                 assistant: "Got it!".to_string(),
             }],
@@ -381,6 +1186,7 @@ Third paragraph."#;
                 user: UserMessage {
                     username: Some("alice".to_string()),
                     content: "Hello".to_string(),
+                    metadata: Metadata::new(),
                 },
                 assistant: "Hi Alice!".to_string(),
             }],
@@ -408,6 +1214,374 @@ The answer is 4."#;
         }
     }
 
+    #[test]
+    fn test_code_blocks() {
+        let input = "> Show me\nHere:\n\n```rust\nfn main() {}\n```\n\n```\nplain\n```";
+        let doc = Document::parse(input);
+        let blocks = doc.turns[0].code_blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].language.as_deref(), Some("rust"));
+        assert_eq!(blocks[0].code, "fn main() {}\n");
+        assert_eq!(blocks[1].language, None);
+    }
+
+    #[test]
+    fn test_turn_range_parse() {
+        assert_eq!("3".parse(), Ok(TurnRange { start: 3, end: Some(3) }));
+        assert_eq!("3..5".parse(), Ok(TurnRange { start: 3, end: Some(5) }));
+        assert_eq!("2..".parse(), Ok(TurnRange { start: 2, end: None }));
+        assert_eq!("..4".parse(), Ok(TurnRange { start: 1, end: Some(4) }));
+        assert!("0".parse::<TurnRange>().is_err());
+        assert!("5..3".parse::<TurnRange>().is_err());
+        assert!("x".parse::<TurnRange>().is_err());
+    }
+
+    #[test]
+    fn test_select_last_and_by_user() {
+        let input = "> @alice: one\nA\n\n> @bob: two\nB\n\n> @alice: three\nC\n\n> four\nD";
+        let doc = Document::parse(input);
+
+        let selected = doc.select("2..3".parse().unwrap());
+        assert_eq!(selected.turns.len(), 2);
+        assert_eq!(selected.turns[0].user.content, "two");
+
+        assert_eq!(doc.select("3..".parse().unwrap()).turns.len(), 2);
+        assert!(doc.select("9".parse().unwrap()).turns.is_empty());
+
+        let last = doc.last(2);
+        assert_eq!(last.turns[0].user.content, "three");
+        assert_eq!(doc.last(10).turns.len(), 4);
+
+        let alice = doc.by_user("@alice");
+        assert_eq!(alice.turns.len(), 2);
+        assert_eq!(alice.turns[1].assistant, "C");
+    }
+
+    #[test]
+    fn test_messages_with_roles() {
+        let input = "> @system: Be terse.\nHello, what do you need?\n\n> @alice: Hi\nFirst.\n\n<!-- cmf -->\n\nSecond.";
+        let doc = Document::parse(input);
+        let messages = doc.messages();
+        let roles: Vec<Role> = messages.iter().map(|m| m.role).collect();
+        assert_eq!(
+            roles,
+            vec![Role::System, Role::Assistant, Role::User, Role::Assistant, Role::Assistant]
+        );
+        assert_eq!(messages[0].content, "Be terse.");
+        assert_eq!(messages[2].name.as_deref(), Some("alice"));
+        assert_eq!(messages[3].content, "First.");
+        assert_eq!(messages[4].content, "Second.");
+    }
+
+    #[test]
+    fn test_from_messages_roundtrip() {
+        let messages = vec![
+            Message::new(Role::Assistant, "Welcome!"),
+            Message::new(Role::User, "Hi"),
+            Message::new(Role::Assistant, "One"),
+            Message::new(Role::Assistant, "Two"),
+        ];
+        let doc = Document::from_messages(&messages);
+        let reparsed = Document::parse(&doc.to_cmf());
+        assert_eq!(reparsed.messages(), messages);
+
+        // Quotes and comments in replies come back indented, as escaped
+        let messages = vec![
+            Message::new(Role::User, "Hi"),
+            Message::new(Role::Assistant, "quote:\n> hi\n<!-- cmf: name=x -->\nend"),
+        ];
+        let reparsed = Document::parse(&Document::from_messages(&messages).to_cmf()).messages();
+        assert_eq!(reparsed.len(), 2);
+        assert_eq!(reparsed[1].role, Role::Assistant);
+        assert_eq!(reparsed[1].name, None);
+        assert_eq!(reparsed[1].content, "quote:\n > hi\n <!-- cmf: name=x -->\nend");
+    }
+
+    #[test]
+    fn test_openai_chat_roles() {
+        let doc = Document::parse("> @system: Rules\n\n> Hi\nHello");
+        let chat = doc.to_openai_chat();
+        let roles: Vec<&str> = chat.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["system", "user", "assistant"]);
+
+        let responses = doc.to_openai_responses();
+        let json = serde_json::to_value(&responses).unwrap();
+        assert_eq!(json[0]["content"][0]["type"], "input_text");
+        assert_eq!(json[2]["content"][0]["type"], "output_text");
+    }
+
+    const TOOL_TRANSCRIPT: &str = r#"> Weather in Paris?
+Let me check.
+
+```tool_call name=get_weather id=call_a
+{"city": "Paris"}
+```
+
+```tool_result id=call_a
+{"temp": 21}
+```
+
+It is 21 degrees."#;
+
+    #[test]
+    fn test_tool_messages() {
+        let doc = Document::parse(TOOL_TRANSCRIPT);
+        let messages = doc.messages();
+        let roles: Vec<Role> = messages.iter().map(|m| m.role).collect();
+        assert_eq!(roles, vec![Role::User, Role::Assistant, Role::Tool, Role::Assistant]);
+        assert_eq!(messages[1].content, "Let me check.");
+        assert_eq!(messages[1].tool_calls[0].name, "get_weather");
+        assert_eq!(messages[1].tool_calls[0].arguments, r#"{"city": "Paris"}"#);
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call_a"));
+        assert_eq!(messages[3].content, "It is 21 degrees.");
+        assert_eq!(doc.turns[0].tool_calls().len(), 1);
+    }
+
+    #[test]
+    fn test_tool_calls_in_openai_chat() {
+        let doc = Document::parse(TOOL_TRANSCRIPT);
+        let json = serde_json::to_value(doc.to_openai_chat()).unwrap();
+        assert_eq!(json[1]["tool_calls"][0]["id"], "call_a");
+        assert_eq!(json[1]["tool_calls"][0]["type"], "function");
+        assert_eq!(json[1]["tool_calls"][0]["function"]["name"], "get_weather");
+        assert_eq!(json[2]["role"], "tool");
+        assert_eq!(json[2]["tool_call_id"], "call_a");
+        assert!(json[0].get("tool_calls").is_none());
+    }
+
+    #[test]
+    fn test_tool_calls_in_openai_responses() {
+        let doc = Document::parse(TOOL_TRANSCRIPT);
+        let json = serde_json::to_value(doc.to_openai_responses()).unwrap();
+        let types: Vec<&str> = json
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            vec!["message", "message", "function_call", "function_call_output", "message"]
+        );
+        assert_eq!(json[2]["call_id"], "call_a");
+        assert_eq!(json[3]["output"], r#"{"temp": 21}"#);
+    }
+
+    #[test]
+    fn test_attachments_in_converters() {
+        let doc = Document::parse(
+            "> What is in ![the chart](chart.png) and ![the logo](https://example.com/logo.png)?\n\nA bar chart.",
+        );
+        let messages = doc.messages();
+        assert_eq!(messages[0].attachments[0].target, "chart.png");
+
+        // The local image is not inlined, so only the remote one is a part
+        let chat = serde_json::to_value(doc.to_openai_chat()).unwrap();
+        assert_eq!(chat[0]["content"][0]["type"], "text");
+        assert_eq!(chat[0]["content"][1]["type"], "image_url");
+        assert_eq!(chat[0]["content"][1]["image_url"]["url"], "https://example.com/logo.png");
+        assert_eq!(chat[0]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(chat[1]["content"], "A bar chart.");
+
+        let responses = serde_json::to_value(doc.to_openai_responses()).unwrap();
+        assert_eq!(responses[0]["content"][1]["type"], "input_image");
+        assert_eq!(responses[0]["content"][1]["image_url"], "https://example.com/logo.png");
+        assert_eq!(responses[0]["content"].as_array().unwrap().len(), 2);
+
+        // With only local attachments, the content stays plain text
+        let local = Document::parse("> See ![the chart](chart.png)");
+        let chat = serde_json::to_value(local.to_openai_chat()).unwrap();
+        assert_eq!(chat[0]["content"], "See ![the chart](chart.png)");
+    }
+
+    #[test]
+    fn test_inlined_file_attachments() {
+        let dir = std::env::temp_dir().join("cmf-inline-test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "hi").unwrap();
+
+        let doc = Document::parse("> Summarise [my notes](notes.txt)");
+        let options = AttachmentOptions::new().base_dir(&dir).inline(true);
+        let responses = serde_json::to_value(doc.to_openai_responses_with(&options)).unwrap();
+        assert_eq!(responses[0]["content"][1]["type"], "input_file");
+        assert_eq!(responses[0]["content"][1]["filename"], "notes.txt");
+        assert_eq!(responses[0]["content"][1]["file_data"], "data:text/plain;base64,aGk=");
+
+        let chat = serde_json::to_value(doc.to_openai_chat_with(&options)).unwrap();
+        assert_eq!(chat[0]["content"][1]["file"]["filename"], "notes.txt");
+
+        // Without inlining the file stays a link in the text
+        let chat = serde_json::to_value(doc.to_openai_chat()).unwrap();
+        assert_eq!(chat[0]["content"], "Summarise [my notes](notes.txt)");
+    }
+
+    #[test]
+    fn test_check_orphan_tool_results() {
+        let input = "> Go\n```tool_call name=f id=a\n{}\n```\n\n```tool_result id=a\nok\n```\n\n```tool_result\nlost\n```\n\n> Again\n```tool_result id=b\nlost\n```";
+        let issues = Document::check(input);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].line, 10);
+        assert!(issues[0].message.contains("without a pending tool_call"));
+        assert_eq!(issues[1].line, 15);
+        assert!(issues[1].message.contains("id=b"));
+
+        // Converted, the orphan gets an id of its own rather than an empty one
+        let messages = Document::parse(input).messages();
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_check_missing_attachments() {
+        let input = "> Look at ![x](does-not-exist.png)\n\n![y](also-missing.png) in assistant text";
+        let issues = Document::check_attachments(input, Path::new("."));
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 1);
+        assert!(issues[0].message.contains("does-not-exist.png"));
+    }
+
+    const METADATA_TRANSCRIPT: &str = r#"<!-- cmf: ts=2026-01-01T12:00Z -->
+
+> What time is it?
+<!-- cmf: id=msg_1 model=gpt-x -->
+Noon.
+<!-- cmf: id=msg_2 -->
+Roughly.
+
+<!-- cmf: ts="2026-01-01 12:01" -->
+
+> Thanks"#;
+
+    #[test]
+    fn test_message_metadata() {
+        let doc = Document::parse(METADATA_TRANSCRIPT);
+        assert_eq!(doc.turns[0].user.metadata["ts"], "2026-01-01T12:00Z");
+        assert_eq!(doc.turns[1].user.metadata["ts"], "2026-01-01 12:01");
+        assert_eq!(doc.turns[0].assistant_messages(), vec!["Noon.", "Roughly."]);
+
+        let messages = doc.messages();
+        assert_eq!(messages[1].metadata["model"], "gpt-x");
+        assert_eq!(messages[2].metadata["id"], "msg_2");
+        assert!(Document::check(METADATA_TRANSCRIPT).is_empty());
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let doc = Document::parse(METADATA_TRANSCRIPT);
+        assert_eq!(Document::parse(&doc.to_cmf()), doc);
+
+        let messages = doc.messages();
+        let rebuilt = Document::from_messages(&messages);
+        assert_eq!(Document::parse(&rebuilt.to_cmf()).messages(), messages);
+    }
+
+    #[test]
+    fn test_metadata_id_in_responses() {
+        let doc = Document::parse(METADATA_TRANSCRIPT);
+        let json = serde_json::to_value(doc.to_openai_responses()).unwrap();
+        assert!(json[0].get("id").is_none());
+        assert_eq!(json[1]["id"], "msg_1");
+    }
+
+    const MULTI_AGENT_TRANSCRIPT: &str = r#"> @alice: Add a cache
+<!-- cmf: name=planner -->
+Plan: wrap the lookup.
+<!-- cmf: name=coder -->
+Done.
+
+```tool_call name=run_tests
+{}
+```
+
+```tool_result
+ok
+```
+
+Tests pass.
+<!-- cmf -->
+Summary from nobody in particular."#;
+
+    #[test]
+    fn test_named_assistants() {
+        let doc = Document::parse(MULTI_AGENT_TRANSCRIPT);
+        let entries = doc.turns[0].assistant_entries();
+        assert_eq!(entries[0].name.as_deref(), Some("planner"));
+        assert_eq!(entries[1].name.as_deref(), Some("coder"));
+        assert!(entries[1].metadata.is_empty());
+        assert_eq!(entries[2].name, None);
+
+        let messages = doc.messages();
+        let names: Vec<Option<&str>> = messages.iter().map(|m| m.name.as_deref()).collect();
+        assert_eq!(
+            names,
+            vec![Some("alice"), Some("planner"), Some("coder"), None, Some("coder"), None]
+        );
+    }
+
+    #[test]
+    fn test_comment_in_code_block_is_code() {
+        let doc = Document::parse("> Show me\nUse this:\n\n```markdown\nFirst\n<!-- cmf -->\nSecond\n```\n\nDone.\n<!-- cmf: name=x -->\nAfter.");
+        let entries = doc.turns[0].assistant_entries();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].content.contains("<!-- cmf -->\nSecond\n```\n\nDone."));
+        assert_eq!(entries[1].name.as_deref(), Some("x"));
+
+        // A shorter or different fence does not close the block
+        let doc = Document::parse("> Show me\n````\n```\n<!-- cmf -->\n~~~~\n````\nDone.");
+        assert_eq!(doc.turns[0].assistant_entries().len(), 1);
+    }
+
+    #[test]
+    fn test_named_assistants_in_openai_chat() {
+        let doc = Document::parse(MULTI_AGENT_TRANSCRIPT);
+        let json = serde_json::to_value(doc.to_openai_chat()).unwrap();
+        assert_eq!(json[0]["name"], "alice");
+        assert_eq!(json[1]["name"], "planner");
+        assert!(json[3].get("name").is_none());
+        assert!(json[5].get("name").is_none());
+    }
+
+    #[test]
+    fn test_named_assistants_roundtrip() {
+        let messages = Document::parse(MULTI_AGENT_TRANSCRIPT).messages();
+        let rebuilt = Document::from_messages(&messages);
+        assert_eq!(Document::parse(&rebuilt.to_cmf()).messages(), messages);
+    }
+
+    #[test]
+    fn test_tool_messages_roundtrip() {
+        let messages = Document::parse(TOOL_TRANSCRIPT).messages();
+        let rebuilt = Document::from_messages(&messages);
+        assert_eq!(Document::parse(&rebuilt.to_cmf()).messages(), messages);
+    }
+
+    #[test]
+    fn test_quoted_tool_output_roundtrip() {
+        let call = ToolCall {
+            id: "call_2".to_string(),
+            name: "shell".to_string(),
+            arguments: "{}".to_string(),
+        };
+        let messages = vec![
+            Message::new(Role::User, "List files"),
+            Message {
+                tool_calls: vec![call],
+                ..Message::new(Role::Assistant, "")
+            },
+            Message {
+                tool_call_id: Some("call_2".to_string()),
+                ..Message::new(Role::Tool, "$ ls\n> prompt\ndone")
+            },
+        ];
+        let reparsed = Document::parse(&Document::from_messages(&messages).to_cmf());
+        assert_eq!(reparsed.turns.len(), 1);
+        assert_eq!(reparsed.messages(), messages);
+
+        // A call without an id doesn't take the explicit call_1 of a later one
+        let doc = Document::parse("> Go\n```tool_call name=f\n{}\n```\n```tool_call name=g id=call_1\n{}\n```\n");
+        let ids: Vec<String> = doc.turns[0].tool_calls().into_iter().map(|call| call.id).collect();
+        assert_eq!(ids, ["call_2", "call_1"]);
+    }
+
     #[test]
     fn test_display_impl() {
         let doc = Document {
@@ -415,6 +1589,7 @@ The answer is 4."#;
                 user: UserMessage {
                     username: None,
                     content: "Test".to_string(),
+                    metadata: Metadata::new(),
                 },
                 assistant: "Response".to_string(),
             }],
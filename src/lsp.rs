@@ -0,0 +1,573 @@
+//! Language server for CMF files
+//!
+//! `cmf lsp` speaks the Language Server Protocol over stdio. Documents are
+//! synced in full and every feature is computed from the current text:
+//!
+//! - diagnostics from `Document::check` and missing attachments
+//! - an outline with one symbol per turn, and a folding range per turn
+//! - hover with the stats of the turn under the cursor
+//! - formatting and quick fixes that add the blank lines `check` asks for
+//! - completion of the `@usernames` already used in the document
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    CodeActionRequest, Completion, DocumentSymbolRequest, FoldingRangeRequest, Formatting, HoverRequest,
+    Request as _,
+};
+use lsp_types::{
+    CodeAction, CodeActionKind, CodeActionOrCommand, CodeActionProviderCapability, CompletionItem,
+    CompletionItemKind, CompletionOptions, CompletionResponse, CompletionTextEdit, Diagnostic, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbol,
+    DocumentSymbolResponse, FoldingRange, FoldingRangeKind, FoldingRangeProviderCapability, Hover, HoverContents,
+    HoverProviderCapability, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Uri, WorkspaceEdit,
+};
+
+use crate::borrowed::DocumentRef;
+use crate::{Document, Issue, IssueKind};
+
+/// Longest user message preview shown in the outline
+const PREVIEW_CHARS: usize = 60;
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Run the server on stdin and stdout until the client shuts it down
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    serve(&connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Run the server on an established connection
+pub fn serve(connection: &Connection) -> Result<()> {
+    connection.initialize(serde_json::to_value(capabilities())?)?;
+    // Keyed by URI text: `Uri` caches parsed parts and makes a poor key
+    let mut documents: HashMap<String, String> = HashMap::new();
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                let response = handle_request(&documents, request);
+                connection.sender.send(Message::Response(response))?;
+            }
+            Message::Notification(notification) => {
+                if let Some(params) = handle_notification(&mut documents, notification)? {
+                    let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+                    connection.sender.send(Message::Notification(notification))?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["@".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Track document text; returns the diagnostics to publish for it
+fn handle_notification(
+    documents: &mut HashMap<String, String>,
+    notification: Notification,
+) -> Result<Option<PublishDiagnosticsParams>> {
+    let (uri, version) = match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+            let document = params.text_document;
+            documents.insert(document.uri.to_string(), document.text);
+            (document.uri, Some(document.version))
+        }
+        DidChangeTextDocument::METHOD => {
+            let params: DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+            // Full sync: the last change holds the whole text
+            if let Some(change) = params.content_changes.into_iter().last() {
+                documents.insert(params.text_document.uri.to_string(), change.text);
+            }
+            (params.text_document.uri, Some(params.text_document.version))
+        }
+        DidCloseTextDocument::METHOD => {
+            let params: DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+            documents.remove(params.text_document.uri.as_str());
+            return Ok(Some(PublishDiagnosticsParams::new(params.text_document.uri, Vec::new(), None)));
+        }
+        _ => return Ok(None),
+    };
+
+    let text = documents.get(uri.as_str()).map_or("", String::as_str);
+    let diagnostics = diagnostics(text, file_dir(&uri));
+    Ok(Some(PublishDiagnosticsParams::new(uri, diagnostics, version)))
+}
+
+fn handle_request(documents: &HashMap<String, String>, request: Request) -> Response {
+    let id = request.id.clone();
+    let text = |uri: &Uri| documents.get(uri.as_str()).map_or("", String::as_str);
+
+    let result = match request.method.as_str() {
+        DocumentSymbolRequest::METHOD => respond::<DocumentSymbolRequest>(request, |params| {
+            Some(DocumentSymbolResponse::Nested(symbols(text(&params.text_document.uri))))
+        }),
+        FoldingRangeRequest::METHOD => respond::<FoldingRangeRequest>(request, |params| {
+            Some(folding_ranges(text(&params.text_document.uri)))
+        }),
+        HoverRequest::METHOD => respond::<HoverRequest>(request, |params| {
+            let position = params.text_document_position_params;
+            hover(text(&position.text_document.uri), position.position)
+        }),
+        Formatting::METHOD => respond::<Formatting>(request, |params| {
+            Some(format_edits(text(&params.text_document.uri)))
+        }),
+        CodeActionRequest::METHOD => respond::<CodeActionRequest>(request, |params| {
+            let uri = params.text_document.uri;
+            Some(code_actions(&uri, text(&uri), params.range))
+        }),
+        Completion::METHOD => respond::<Completion>(request, |params| {
+            let position = params.text_document_position;
+            let items = completions(text(&position.text_document.uri), position.position);
+            Some(CompletionResponse::Array(items))
+        }),
+        method => {
+            let message = format!("unhandled method: {}", method);
+            return Response::new_err(id, ErrorCode::MethodNotFound as i32, message);
+        }
+    };
+
+    match result {
+        Ok(result) => Response {
+            id,
+            result: Some(result),
+            error: None,
+        },
+        Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+    }
+}
+
+fn respond<R: lsp_types::request::Request>(
+    request: Request,
+    handler: impl FnOnce(R::Params) -> R::Result,
+) -> serde_json::Result<serde_json::Value> {
+    let params = serde_json::from_value(request.params)?;
+    serde_json::to_value(handler(params))
+}
+
+/// Directory of a `file:` URI, for resolving attachments
+fn file_dir(uri: &Uri) -> Option<PathBuf> {
+    if !uri.scheme().is_some_and(|scheme| scheme.as_str().eq_ignore_ascii_case("file")) {
+        return None;
+    }
+    let path = PathBuf::from(uri.path().as_estr().decode().into_string_lossy().into_owned());
+    path.parent().map(PathBuf::from)
+}
+
+/// Converts between byte offsets and LSP positions (UTF-16 columns)
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let character = self.text[self.starts[line]..offset].encode_utf16().count();
+        Position::new(line as u32, character as u32)
+    }
+
+    fn range(&self, start: usize, end: usize) -> Range {
+        Range::new(self.position(start), self.position(end))
+    }
+
+    fn offset(&self, position: Position) -> usize {
+        let Some(&start) = self.starts.get(position.line as usize) else {
+            return self.text.len();
+        };
+        let line = self.text[start..].split('\n').next().unwrap_or_default();
+        let mut units = 0;
+        for (i, c) in line.char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+        start + line.len()
+    }
+
+    /// The whole of a 1-based line, without its line ending
+    fn line_range(&self, line: usize) -> Range {
+        let start = self.starts[line - 1];
+        let content = self.text[start..].lines().next().unwrap_or_default();
+        self.range(start, start + content.len())
+    }
+}
+
+/// Conformance errors, plus warnings for missing attachments when the
+/// document is a file
+fn diagnostics(text: &str, dir: Option<PathBuf>) -> Vec<Diagnostic> {
+    let index = LineIndex::new(text);
+    let diagnostic = |issue: Issue, severity| Diagnostic {
+        range: index.line_range(issue.line),
+        severity: Some(severity),
+        source: Some("cmf".to_string()),
+        message: issue.message,
+        ..Default::default()
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = Document::check(text)
+        .into_iter()
+        .map(|issue| diagnostic(issue, DiagnosticSeverity::ERROR))
+        .collect();
+    if let Some(dir) = dir {
+        let missing = Document::check_attachments(text, &dir);
+        diagnostics.extend(missing.into_iter().map(|issue| diagnostic(issue, DiagnosticSeverity::WARNING)));
+    }
+    diagnostics
+}
+
+/// One symbol per turn, named after the start of the user message
+fn symbols(text: &str) -> Vec<DocumentSymbol> {
+    let index = LineIndex::new(text);
+    let (doc, spans) = DocumentRef::parse_with_spans(text);
+
+    doc.turns
+        .iter()
+        .zip(&spans)
+        .enumerate()
+        .map(|(i, (turn, span))| {
+            let first_line = turn.user.content.lines().next().unwrap_or_default().trim();
+            let mut preview: String = first_line.chars().take(PREVIEW_CHARS).collect();
+            if preview.len() < first_line.len() {
+                preview.push('…');
+            }
+            #[allow(deprecated)]
+            DocumentSymbol {
+                name: format!("{}. {}", i + 1, if preview.is_empty() { "(empty)" } else { &preview }),
+                detail: turn.user.username.map(|username| format!("@{}", username)),
+                kind: SymbolKind::EVENT,
+                tags: None,
+                deprecated: None,
+                range: index.range(span.start, span.end),
+                selection_range: index.range(span.user.start, span.user.end),
+                children: None,
+            }
+        })
+        .collect()
+}
+
+/// A region per turn that spans more than one line
+fn folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let index = LineIndex::new(text);
+    let (_, spans) = DocumentRef::parse_with_spans(text);
+
+    spans
+        .iter()
+        .map(|span| index.range(span.start, span.end))
+        .filter(|range| range.end.line > range.start.line)
+        .map(|range| FoldingRange {
+            start_line: range.start.line,
+            end_line: range.end.line,
+            kind: Some(FoldingRangeKind::Region),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Stats of the turn under the cursor
+fn hover(text: &str, position: Position) -> Option<Hover> {
+    let index = LineIndex::new(text);
+    let offset = index.offset(position);
+    let (doc, spans) = DocumentRef::parse_with_spans(text);
+    let i = spans.iter().position(|span| (span.start..=span.end).contains(&offset))?;
+    let turn = doc.turns[i].to_turn();
+
+    let mut value = format!("**Turn {} of {}**", i + 1, doc.turns.len());
+    if let Some(ref username) = turn.user.username {
+        value.push_str(&format!(" · @{}", username));
+    }
+    value.push_str(&format!("\n\n- user: {}", words(&turn.user.content)));
+    let messages = turn.assistant_messages().len();
+    value.push_str(&format!(
+        "\n- assistant: {} in {}",
+        words(&turn.assistant),
+        plural(messages, "message")
+    ));
+    let (tool_calls, code_blocks) = (turn.tool_calls().len(), turn.code_blocks().len());
+    if tool_calls + code_blocks > 0 {
+        value.push_str(&format!(
+            "\n- {}, {}",
+            plural(tool_calls, "tool call"),
+            plural(code_blocks, "code block")
+        ));
+    }
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range: Some(index.range(spans[i].start, spans[i].end)),
+    })
+}
+
+fn words(text: &str) -> String {
+    plural(text.split_whitespace().count(), "word")
+}
+
+fn plural(n: usize, noun: &str) -> String {
+    format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+}
+
+/// Lines that need a blank line inserted before them to conform
+fn fixable_lines(text: &str) -> Vec<usize> {
+    blank_line_issues(text).map(|issue| issue.line).collect()
+}
+
+/// Issues fixed by inserting a blank line; other issues have no quick fix
+fn blank_line_issues(text: &str) -> impl Iterator<Item = Issue> {
+    Document::check(text).into_iter().filter(|issue| issue.kind == IssueKind::MissingBlankLine)
+}
+
+/// The text with check fixes applied and a single final newline
+fn format(text: &str) -> String {
+    let fixes = fixable_lines(text);
+    let mut formatted = String::with_capacity(text.len());
+    for (i, line) in text.lines().enumerate() {
+        if fixes.contains(&(i + 1)) {
+            formatted.push('\n');
+        }
+        formatted.push_str(line);
+        formatted.push('\n');
+    }
+    let end = formatted.trim_end().len();
+    formatted.truncate(end);
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    formatted
+}
+
+fn format_edits(text: &str) -> Vec<TextEdit> {
+    let formatted = format(text);
+    if formatted == text {
+        return Vec::new();
+    }
+    let index = LineIndex::new(text);
+    vec![TextEdit::new(index.range(0, text.len()), formatted)]
+}
+
+/// Quick fixes for the issues on lines within `range`
+fn code_actions(uri: &Uri, text: &str, range: Range) -> Vec<CodeActionOrCommand> {
+    let index = LineIndex::new(text);
+    let lines = range.start.line as usize + 1..=range.end.line as usize + 1;
+
+    blank_line_issues(text)
+        .filter(|issue| lines.contains(&issue.line))
+        .map(|issue| {
+            let start = Position::new(issue.line as u32 - 1, 0);
+            let edit = TextEdit::new(Range::new(start, start), "\n".to_string());
+            let diagnostic = Diagnostic {
+                range: index.line_range(issue.line),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("cmf".to_string()),
+                message: issue.message,
+                ..Default::default()
+            };
+            CodeActionOrCommand::CodeAction(CodeAction {
+                title: "Insert blank line before user line".to_string(),
+                kind: Some(CodeActionKind::QUICKFIX),
+                diagnostics: Some(vec![diagnostic]),
+                edit: Some(WorkspaceEdit {
+                    changes: Some(HashMap::from([(uri.clone(), vec![edit])])),
+                    ..Default::default()
+                }),
+                is_preferred: Some(true),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Usernames already in the document, after `>@` or `> @`
+fn completions(text: &str, position: Position) -> Vec<CompletionItem> {
+    let index = LineIndex::new(text);
+    let offset = index.offset(position);
+    let Some(&line_start) = index.starts.get(position.line as usize) else {
+        return Vec::new();
+    };
+    let before = &text[line_start..offset];
+
+    let Some(partial) = before
+        .strip_prefix('>')
+        .map(|rest| rest.strip_prefix(' ').unwrap_or(rest))
+        .and_then(|rest| rest.strip_prefix('@'))
+        .filter(|partial| !partial.contains([':', ' ']))
+    else {
+        return Vec::new();
+    };
+
+    let at = index.position(offset - partial.len() - 1);
+    let (doc, _) = DocumentRef::parse_with_spans(text);
+    let mut seen = Vec::new();
+    for username in doc.turns.iter().filter_map(|turn| turn.user.username) {
+        if !seen.contains(&username) {
+            seen.push(username);
+        }
+    }
+
+    seen.into_iter()
+        .map(|username| CompletionItem {
+            label: format!("@{}", username),
+            kind: Some(CompletionItemKind::VALUE),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                Range::new(at, position),
+                format!("@{}: ", username),
+            ))),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_server::RequestId;
+    use std::str::FromStr;
+    use std::thread;
+
+    const TEXT: &str = "# Notes\n\n> @alice: Hi there\nHello!\n\n```tool_call name=f\n{}\n```\n\n> @bob: Yo\nSup\n> @";
+
+    #[test]
+    fn test_diagnostics_and_fixes() {
+        let diagnostics = diagnostics(TEXT, None);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].range, Range::new(Position::new(11, 0), Position::new(11, 3)));
+
+        let uri = Uri::from_str("file:///tmp/chat.cmf").unwrap();
+        let actions = code_actions(&uri, TEXT, Range::new(Position::new(11, 0), Position::new(11, 0)));
+        assert_eq!(actions.len(), 1);
+        assert!(code_actions(&uri, TEXT, Range::new(Position::new(0, 0), Position::new(3, 0))).is_empty());
+
+        let formatted = format(TEXT);
+        assert!(formatted.ends_with("Sup\n\n> @\n"));
+        assert!(Document::check(&formatted).is_empty());
+        assert!(format_edits(&formatted).is_empty());
+        assert_eq!(file_dir(&uri), Some(PathBuf::from("/tmp")));
+    }
+
+    #[test]
+    fn test_orphan_tool_result_is_not_fixed() {
+        let text = "> Go\n```tool_result id=x\n...\n```\n";
+        assert_eq!(Document::check(text)[0].kind, IssueKind::OrphanToolResult);
+        let formatted = format(text);
+        assert_eq!(formatted, text);
+        assert_eq!(format(&formatted), formatted);
+        assert!(format_edits(text).is_empty());
+
+        let uri = Uri::from_str("file:///tmp/chat.cmf").unwrap();
+        assert!(code_actions(&uri, text, Range::new(Position::new(0, 0), Position::new(3, 0))).is_empty());
+        assert_eq!(diagnostics(text, None).len(), 1);
+    }
+
+    #[test]
+    fn test_outline_folding_and_hover() {
+        let symbols = symbols(TEXT);
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols[0].name, "1. Hi there");
+        assert_eq!(symbols[0].detail.as_deref(), Some("@alice"));
+        assert_eq!(symbols[0].range, Range::new(Position::new(2, 0), Position::new(7, 3)));
+
+        let folds = folding_ranges(TEXT);
+        assert_eq!((folds[0].start_line, folds[0].end_line), (2, 7));
+
+        let HoverContents::Markup(markup) = hover(TEXT, Position::new(5, 2)).unwrap().contents else {
+            panic!("expected markdown");
+        };
+        assert_eq!(
+            markup.value,
+            "**Turn 1 of 3** · @alice\n\n- user: 2 words\n- assistant: 5 words in 1 message\n- 1 tool call, 1 code block"
+        );
+        assert!(hover(TEXT, Position::new(0, 0)).is_none());
+    }
+
+    #[test]
+    fn test_username_completion() {
+        let items = completions(TEXT, Position::new(11, 3));
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["@alice", "@bob"]);
+        assert!(completions(TEXT, Position::new(10, 2)).is_empty());
+
+        // Columns count UTF-16 units
+        let index = LineIndex::new("> é😀x\n");
+        assert_eq!(index.offset(Position::new(0, 5)), "> é😀".len());
+        assert_eq!(index.position("> é😀".len()), Position::new(0, 5));
+    }
+
+    #[test]
+    fn test_protocol_round_trip() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || serve(&server).unwrap());
+
+        let send_request = |id: i32, method: &str, params: serde_json::Value| {
+            let request = Request::new(RequestId::from(id), method.to_string(), params);
+            client.sender.send(Message::Request(request)).unwrap();
+        };
+        let notify = |method: &str, params: serde_json::Value| {
+            let notification = Notification::new(method.to_string(), params);
+            client.sender.send(Message::Notification(notification)).unwrap();
+        };
+
+        send_request(1, "initialize", serde_json::json!({ "capabilities": {} }));
+        let Message::Response(initialized) = client.receiver.recv().unwrap() else {
+            panic!("expected initialize response");
+        };
+        assert!(initialized.result.unwrap()["capabilities"]["hoverProvider"].as_bool().unwrap());
+        notify("initialized", serde_json::json!({}));
+
+        let document = serde_json::json!({ "uri": "untitled:chat", "languageId": "cmf", "version": 1, "text": TEXT });
+        notify("textDocument/didOpen", serde_json::json!({ "textDocument": document }));
+        let Message::Notification(published) = client.receiver.recv().unwrap() else {
+            panic!("expected diagnostics");
+        };
+        assert_eq!(published.method, "textDocument/publishDiagnostics");
+        assert_eq!(published.params["diagnostics"].as_array().unwrap().len(), 1);
+
+        send_request(2, "textDocument/documentSymbol", serde_json::json!({ "textDocument": { "uri": "untitled:chat" } }));
+        let Message::Response(response) = client.receiver.recv().unwrap() else {
+            panic!("expected symbols");
+        };
+        assert_eq!(response.result.unwrap()[1]["name"], "2. Yo");
+
+        send_request(3, "shutdown", serde_json::Value::Null);
+        client.receiver.recv().unwrap();
+        notify("exit", serde_json::Value::Null);
+        server.join().unwrap();
+    }
+}
@@ -1,8 +1,38 @@
-use clap::{Parser, Subcommand};
-use cmf::Document;
-use cmf::terminal_renderer::MarkdownRenderer;
-use std::fs;
-use std::process::ExitCode;
+use clap::{Args, Parser, Subcommand};
+use cmf::attachments::find_attachments;
+use cmf::client::DEFAULT_BASE_URL;
+use cmf::grep::{self, GrepOptions, Regex};
+use cmf::index::{Index, Query, INDEX_FILE};
+use cmf::reader::{self, TurnReader};
+use cmf::server::{self, DEFAULT_MAX_BODY, DEFAULT_WORKERS};
+use cmf::stats::Stats;
+use cmf::tokens::{self, Bpe, Encoding, Heuristic, Tokenizer};
+use cmf::truncate::Strategy;
+use cmf::{
+    Attachment, AttachmentOptions, ChatClient, ChatMessage, CmfWriter, Document, Message, Metadata, ResponsesItem, Role, Turn,
+    TurnRange, UserMessage,
+};
+use cmf::plain_renderer::PlainTextRenderer;
+use cmf::terminal_renderer::{
+    Charset, ColorMode, HeadingStyle, HyperlinkMode, MarkdownRenderer, RendererOptions, TableStyle, Theme,
+};
+use cmf::viewer::Viewer;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{
+    self, Clear, ClearType, DisableLineWrap, EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use crossterm::{cursor, execute, queue};
+use serde::ser::{Error as _, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Command, ExitCode, Stdio};
+use terminal_size::{terminal_size, Height};
 
 #[derive(Parser)]
 #[command(name = "cmf")]
@@ -29,33 +59,513 @@ enum Commands {
     Render {
         /// Path to the markdown file
         file: String,
+        #[command(flatten)]
+        selection: Selection,
+        /// Print directly instead of paging long output through $PAGER
+        #[arg(long)]
+        no_pager: bool,
+        /// Output plain text without any formatting or decoration
+        #[arg(long)]
+        plain: bool,
+        #[command(flatten)]
+        style: RenderArgs,
+    },
+    /// Interactively browse a conversation turn by turn
+    View {
+        /// Path to the markdown file
+        file: String,
+        #[command(flatten)]
+        style: RenderArgs,
     },
     /// Convert to OpenAI Chat Completions format
     #[command(name = "to-openai-chat")]
     ToOpenaiChat {
         /// Path to the markdown file
         file: String,
+        #[command(flatten)]
+        selection: Selection,
+        #[command(flatten)]
+        budget: Budget,
+        /// Embed local images and files as base64 data URLs
+        #[arg(long)]
+        inline_attachments: bool,
     },
     /// Convert to OpenAI Responses API format
     #[command(name = "to-openai-responses")]
     ToOpenaiResponses {
         /// Path to the markdown file
         file: String,
+        #[command(flatten)]
+        selection: Selection,
+        #[command(flatten)]
+        budget: Budget,
+        /// Embed local images and files as base64 data URLs
+        #[arg(long)]
+        inline_attachments: bool,
+    },
+    /// Dump the parsed document as versioned JSON
+    #[command(name = "to-json")]
+    ToJson {
+        /// Path to the markdown file
+        file: String,
+        #[command(flatten)]
+        selection: Selection,
+    },
+    /// Convert versioned JSON from `to-json` back to CMF
+    #[command(name = "from-json")]
+    FromJson {
+        /// Path to the JSON file
+        file: String,
+    },
+    /// Print the JSON Schema of the `to-json` output
+    Schema,
+    /// Chat with an OpenAI-compatible endpoint, appending both sides to the file
+    Chat {
+        /// Path to the markdown file (created if missing)
+        file: String,
+        /// API base URL, e.g. http://localhost:11434/v1 for Ollama
+        #[arg(long, env = "OPENAI_BASE_URL", default_value = DEFAULT_BASE_URL)]
+        base_url: String,
+        /// Model to request replies from
+        #[arg(long, env = "OPENAI_MODEL", default_value = "gpt-4o-mini")]
+        model: String,
+        /// API key, sent as a bearer token
+        #[arg(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+        api_key: Option<String>,
+        /// Embed local images and files as base64 data URLs
+        #[arg(long)]
+        inline_attachments: bool,
+        #[command(flatten)]
+        style: RenderArgs,
     },
+    /// Serve parse, check, convert and render over a local HTTP API
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: String,
+        /// Largest request body accepted, in bytes
+        #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_BODY)]
+        max_body: usize,
+        /// Number of threads serving requests
+        #[arg(long, default_value_t = DEFAULT_WORKERS)]
+        workers: usize,
+    },
+    /// Run a language server for editors, over stdio
+    Lsp,
+    /// Report statistics for one or more files, with totals
+    Stats {
+        /// Paths to the markdown files
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Output JSON instead of a text report
+        #[arg(long)]
+        json: bool,
+    },
+    /// Search conversations by regular expression, reporting turn and speaker
+    ///
+    /// Exits with failure when nothing matches.
+    Grep {
+        /// Regular expression to search for
+        pattern: String,
+        /// Paths to the markdown files; standard input when none are given
+        files: Vec<String>,
+        /// Match case-insensitively
+        #[arg(short = 'i', long)]
+        ignore_case: bool,
+        /// Only search user messages
+        #[arg(long, conflicts_with = "assistant")]
+        user: bool,
+        /// Only search assistant messages
+        #[arg(long)]
+        assistant: bool,
+        /// Only search inside fenced code blocks, not tool calls or results
+        #[arg(long)]
+        code: bool,
+        /// Lines of context around each match
+        #[arg(short = 'C', long, value_name = "N", default_value_t = 0)]
+        context: usize,
+        /// Print whole matching turns as CMF instead of lines
+        #[arg(long, conflicts_with = "context")]
+        turns: bool,
+    },
+    /// Build or refresh the search index of a directory of conversations
+    ///
+    /// Only new and changed `.cmf` files are reindexed.
+    Index {
+        /// Directory to index, including subdirectories
+        #[arg(default_value = ".")]
+        dir: String,
+    },
+    /// Search the turns of an indexed directory, best matches first
+    Search {
+        /// Words, "phrases", OR, -word or NOT word, and filters: user:NAME,
+        /// role:user|assistant|system, lang:LANG, date:PREFIX, after:DATE,
+        /// before:DATE
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Directory indexed with `cmf index`
+        #[arg(long, default_value = ".")]
+        dir: String,
+        /// Most results to show
+        #[arg(long, value_name = "N", default_value_t = 10)]
+        limit: usize,
+        /// Output JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Count the tokens of the conversation sent as Chat Completions messages
+    Tokens {
+        /// Path to the markdown file
+        file: String,
+        /// cl100k_base (the default), o200k_base, a rank table file in
+        /// tiktoken format, or heuristic to estimate from the text length
+        #[arg(long, value_name = "ENCODING", env = "CMF_ENCODING")]
+        encoding: Option<String>,
+        /// Context window to check the total against; fails when it doesn't fit
+        #[arg(long, value_name = "TOKENS")]
+        limit: Option<usize>,
+        /// List turns instead of messages
+        #[arg(long)]
+        turns: bool,
+        /// Output JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Terminal rendering options shared by render and view
+#[derive(Args)]
+struct RenderArgs {
+    /// When to use colours: auto, always or never
+    #[arg(long, value_name = "WHEN", default_value = "auto")]
+    color: ColorMode,
+    /// Output width in columns (defaults to the terminal width)
+    #[arg(long, value_name = "COLUMNS")]
+    width: Option<usize>,
+    /// Colour theme: default or mono
+    #[arg(long, default_value = "default")]
+    theme: Theme,
+    /// Don't style code block contents
+    #[arg(long)]
+    no_highlight: bool,
+    /// How to show link URLs: off, inline or osc8
+    #[arg(long, value_name = "MODE", default_value = "off")]
+    hyperlinks: HyperlinkMode,
+    /// Decoration characters: auto (from the locale), unicode or ascii
+    #[arg(long, value_name = "CHARSET", default_value = "auto")]
+    charset: Charset,
+    /// Table borders: unicode or ascii (defaults to the charset's)
+    #[arg(long, value_name = "STYLE")]
+    table_style: Option<TableStyle>,
+    /// Heading decoration: rules, underline or boxed
+    #[arg(long, value_name = "STYLE", default_value = "rules")]
+    heading_style: HeadingStyle,
+    /// Prefix headings with section numbers like 1.2
+    #[arg(long)]
+    numbered_headings: bool,
+    /// Glyph for unordered list items
+    #[arg(long, value_name = "GLYPH")]
+    bullet: Option<String>,
+    /// Glyph prefixing blockquote lines
+    #[arg(long, value_name = "GLYPH")]
+    quote: Option<String>,
+}
+
+impl RenderArgs {
+    fn options(&self) -> RendererOptions {
+        if self.color == ColorMode::Always {
+            // Don't let the colour library second-guess an explicit request
+            colored::control::set_override(true);
+        }
+
+        let mut options = RendererOptions::new()
+            .color(self.color)
+            .theme(self.theme)
+            .highlight_code(!self.no_highlight)
+            .hyperlinks(self.hyperlinks)
+            .charset(self.charset)
+            .heading_style(self.heading_style)
+            .numbered_headings(self.numbered_headings);
+        if let Some(table_style) = self.table_style {
+            options = options.table_style(table_style);
+        }
+        if let Some(width) = self.width {
+            options = options.width(width);
+        }
+        if let Some(ref bullet) = self.bullet {
+            options = options.bullet(bullet);
+        }
+        if let Some(ref quote) = self.quote {
+            options = options.quote_glyph(quote);
+        }
+        options
+    }
 }
 
+/// Turn and participant filters shared by render and the converters
+#[derive(Args)]
+struct Selection {
+    /// Only include these turns (1-based): 3, 3..5, 3.. or ..5
+    #[arg(long, value_name = "RANGE")]
+    turn: Option<TurnRange>,
+    /// Only include the last N turns
+    #[arg(long, value_name = "N")]
+    last: Option<usize>,
+    /// Only include turns from this participant (@username)
+    #[arg(long, value_name = "USERNAME")]
+    user: Option<String>,
+    /// Only output assistant messages (not supported by to-json)
+    #[arg(long)]
+    assistant_only: bool,
+}
+
+impl Selection {
+    fn is_active(&self) -> bool {
+        self.turn.is_some() || self.last.is_some() || self.user.is_some() || self.assistant_only
+    }
+
+    /// The selected turns of `file`, read incrementally; `--last N` buffers N turns
+    fn read_turns(&self, file: &str) -> Result<Box<dyn Iterator<Item = io::Result<Turn>>>, ExitCode> {
+        let reader = open_file(file)?;
+        let range = self.turn;
+        let user = self.user.clone();
+        let turns = TurnReader::new(reader)
+            .enumerate()
+            .filter(move |(i, turn)| match turn {
+                Ok(turn) => {
+                    range.is_none_or(|range| range.contains(i + 1))
+                        && user.as_deref().is_none_or(|user| turn.is_from(user))
+                }
+                Err(_) => true,
+            })
+            .map(|(_, turn)| turn);
+
+        let Some(n) = self.last else {
+            return Ok(Box::new(turns));
+        };
+        let mut last = VecDeque::new();
+        for turn in turns {
+            match turn {
+                Ok(_) if n == 0 => {}
+                Ok(turn) => {
+                    if last.len() == n {
+                        last.pop_front();
+                    }
+                    last.push_back(Ok(turn));
+                }
+                Err(e) => {
+                    last.push_back(Err(e));
+                    break;
+                }
+            }
+        }
+        Ok(Box::new(last.into_iter()))
+    }
+
+    /// Messages of the selected turns, keeping only assistant ones with `--assistant-only`
+    fn messages(&self, turns: impl Iterator<Item = io::Result<Turn>>) -> impl Iterator<Item = io::Result<Message>> {
+        let assistant_only = self.assistant_only;
+        reader::messages(turns).filter(move |message| match message {
+            Ok(message) => !assistant_only || message.role == Role::Assistant,
+            Err(_) => true,
+        })
+    }
+
+    fn apply(&self, mut doc: Document) -> Document {
+        if let Some(range) = self.turn {
+            doc = doc.select(range);
+        }
+        if let Some(ref username) = self.user {
+            doc = doc.by_user(username);
+        }
+        if let Some(n) = self.last {
+            doc = doc.last(n);
+        }
+        doc
+    }
+}
+
+#[derive(Args)]
+struct Budget {
+    /// Drop turns until the input fits in this many tokens (counted as
+    /// Chat Completions messages); dropped turns are reported on stderr
+    #[arg(long, value_name = "TOKENS")]
+    max_tokens: Option<usize>,
+    /// Which turns to drop first: drop-oldest, keep-first[=N][,last=M]
+    /// (the first N turns, the last M and as many more of the last as fit),
+    /// keep-system or placeholder (keep-system with a note in place of each
+    /// run of dropped turns)
+    #[arg(long, value_name = "STRATEGY", default_value = "drop-oldest", requires = "max_tokens")]
+    strategy: Strategy,
+    /// cl100k_base (the default), o200k_base, a rank table file in
+    /// tiktoken format, or heuristic
+    #[arg(long, value_name = "ENCODING", env = "CMF_ENCODING")]
+    encoding: Option<String>,
+}
+
+impl Budget {
+    /// The turns, truncated to `--max-tokens` when given
+    fn fit(
+        &self,
+        file: &str,
+        turns: Box<dyn Iterator<Item = io::Result<Turn>>>,
+    ) -> Result<Box<dyn Iterator<Item = io::Result<Turn>>>, ExitCode> {
+        let Some(max_tokens) = self.max_tokens else {
+            return Ok(turns);
+        };
+        let tokenizer = load_tokenizer(self.encoding.as_deref())?;
+        let turns = turns.collect::<io::Result<Vec<_>>>().map_err(|e| {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        })?;
+
+        let result = Document { turns }.truncate(max_tokens, self.strategy, tokenizer.as_ref());
+        if !result.dropped.is_empty() {
+            eprintln!(
+                "{}: dropped {} to fit {} tokens ({})",
+                file,
+                describe_turns(&result.dropped),
+                max_tokens,
+                self.strategy
+            );
+        }
+        if result.tokens > max_tokens {
+            eprintln!("{}: warning: {} tokens still exceed --max-tokens {}", file, result.tokens, max_tokens);
+        }
+        Ok(Box::new(result.document.turns.into_iter().map(Ok)))
+    }
+}
+
+/// `turn 3` or `turns 1..4, 7`
+fn describe_turns(turns: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &turn in turns {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == turn => *end = turn,
+            _ => ranges.push((turn, turn)),
+        }
+    }
+    let ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}..{}", start, end) })
+        .collect();
+    let noun = if turns.len() == 1 { "turn" } else { "turns" };
+    format!("{} {}", noun, ranges.join(", "))
+}
+
+fn load_tokenizer(encoding: Option<&str>) -> Result<Box<dyn Tokenizer>, ExitCode> {
+    let encoding = encoding.unwrap_or(DEFAULT_ENCODING);
+    if encoding == "heuristic" {
+        return Ok(Box::new(Heuristic));
+    }
+    if let Ok(bundled) = encoding.parse::<Encoding>() {
+        #[cfg(feature = "bundled-encodings")]
+        return Ok(Box::new(Bpe::bundled(bundled)));
+        #[cfg(not(feature = "bundled-encodings"))]
+        {
+            eprintln!("error: {}: built without bundled encodings, pass a .tiktoken file", bundled);
+            return Err(ExitCode::FAILURE);
+        }
+    }
+    match Bpe::open(encoding) {
+        Ok(bpe) => Ok(Box::new(bpe)),
+        Err(e) => {
+            eprintln!("error: {}: {}", encoding, e);
+            Err(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Counting falls back to an estimate when no table is bundled
+#[cfg(feature = "bundled-encodings")]
+const DEFAULT_ENCODING: &str = "cl100k_base";
+#[cfg(not(feature = "bundled-encodings"))]
+const DEFAULT_ENCODING: &str = "heuristic";
+
 fn main() -> ExitCode {
     let cli = Cli::parse();
 
     match cli.command {
         Commands::Detect { file } => cmd_detect(&file),
         Commands::Check { file } => cmd_check(&file),
-        Commands::Render { file } => cmd_render(&file),
-        Commands::ToOpenaiChat { file } => cmd_to_openai_chat(&file),
-        Commands::ToOpenaiResponses { file } => cmd_to_openai_responses(&file),
+        Commands::Render { file, no_pager, plain, style, selection } => {
+            cmd_render(&file, !no_pager, plain, &style, &selection)
+        }
+        Commands::View { file, style } => cmd_view(&file, &style),
+        Commands::ToOpenaiChat {
+            file,
+            selection,
+            budget,
+            inline_attachments,
+        } => cmd_to_openai_chat(&file, &selection, &budget, inline_attachments),
+        Commands::ToOpenaiResponses {
+            file,
+            selection,
+            budget,
+            inline_attachments,
+        } => cmd_to_openai_responses(&file, &selection, &budget, inline_attachments),
+        Commands::ToJson { file, selection } => cmd_to_json(&file, &selection),
+        Commands::FromJson { file } => cmd_from_json(&file),
+        Commands::Schema => {
+            println!("{}", Document::json_schema());
+            ExitCode::SUCCESS
+        }
+        Commands::Chat {
+            file,
+            base_url,
+            model,
+            api_key,
+            inline_attachments,
+            style,
+        } => {
+            let mut client = ChatClient::new(base_url, model);
+            if let Some(key) = api_key {
+                client = client.api_key(key);
+            }
+            cmd_chat(&file, &client, inline_attachments, &style)
+        }
+        Commands::Serve { addr, max_body, workers } => cmd_serve(&addr, max_body, workers),
+        Commands::Stats { files, json } => cmd_stats(&files, json),
+        Commands::Grep {
+            pattern,
+            files,
+            ignore_case,
+            user,
+            assistant,
+            code,
+            context,
+            turns,
+        } => {
+            let mut options = GrepOptions::new().code_only(code);
+            if user {
+                options = options.role(Role::User);
+            } else if assistant {
+                options = options.role(Role::Assistant);
+            }
+            cmd_grep(&pattern, &files, ignore_case, &options, context, turns)
+        }
+        Commands::Index { dir } => cmd_index(&dir),
+        Commands::Search { query, dir, limit, json } => cmd_search(&query.join(" "), &dir, limit, json),
+        Commands::Tokens { file, encoding, limit, turns, json } => {
+            cmd_tokens(&file, encoding.as_deref(), limit, turns, json)
+        }
+        Commands::Lsp => match cmf::lsp::run() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                ExitCode::FAILURE
+            }
+        },
     }
 }
 
+fn open_file(path: &str) -> Result<BufReader<File>, ExitCode> {
+    File::open(path).map(BufReader::new).map_err(|e| {
+        eprintln!("error: {}: {}", path, e);
+        ExitCode::FAILURE
+    })
+}
+
 fn read_file(path: &str) -> Result<String, ExitCode> {
     fs::read_to_string(path).map_err(|e| {
         eprintln!("error: {}: {}", path, e);
@@ -64,14 +574,22 @@ fn read_file(path: &str) -> Result<String, ExitCode> {
 }
 
 fn cmd_detect(file: &str) -> ExitCode {
-    let content = match read_file(file) {
-        Ok(c) => c,
+    let reader = match open_file(file) {
+        Ok(r) => r,
         Err(code) => return code,
     };
 
-    if Document::is_valid_cmf(&content) {
-        let doc = Document::parse(&content);
-        println!("{} turns", doc.turns.len());
+    let mut turns = 0;
+    for turn in TurnReader::new(reader) {
+        if let Err(e) = turn {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+        turns += 1;
+    }
+
+    if turns > 0 {
+        println!("{} turns", turns);
         ExitCode::SUCCESS
     } else {
         ExitCode::FAILURE
@@ -84,6 +602,11 @@ fn cmd_check(file: &str) -> ExitCode {
         Err(code) => return code,
     };
 
+    // Missing attachments are warnings: the conversation itself is still valid
+    for issue in Document::check_attachments(&content, base_dir(file)) {
+        eprintln!("{}:{}: warning: {}", file, issue.line, issue.message);
+    }
+
     let issues = Document::check(&content);
     if issues.is_empty() {
         // Rule of Silence: say nothing on success
@@ -96,31 +619,366 @@ fn cmd_check(file: &str) -> ExitCode {
     }
 }
 
-fn cmd_render(file: &str) -> ExitCode {
+fn cmd_render(
+    file: &str,
+    use_pager: bool,
+    plain: bool,
+    style: &RenderArgs,
+    selection: &Selection,
+) -> ExitCode {
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
-    let renderer = MarkdownRenderer::new();
-    let rendered = renderer.render(&content);
-    print!("{}", rendered);
+    // Without filters the file is rendered verbatim, preamble included
+    let markdown = if selection.is_active() {
+        let doc = selection.apply(Document::parse(&content));
+        if selection.assistant_only {
+            doc.turns
+                .iter()
+                .map(|turn| turn.assistant.as_str())
+                .filter(|assistant| !assistant.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n\n")
+        } else {
+            doc.to_cmf()
+        }
+    } else {
+        content
+    };
+
+    let rendered = if plain {
+        PlainTextRenderer::new().render(&markdown)
+    } else {
+        MarkdownRenderer::with_options(style.options()).render(&markdown)
+    };
+    print_paged(&rendered, use_pager);
     ExitCode::SUCCESS
 }
 
-fn cmd_to_openai_chat(file: &str) -> ExitCode {
+/// Print output, going through the pager when it won't fit on the terminal
+fn print_paged(output: &str, use_pager: bool) {
+    if use_pager && atty::is(atty::Stream::Stdout) {
+        if let Some((_, Height(height))) = terminal_size() {
+            if output.lines().count() >= height as usize && spawn_pager(output).is_ok() {
+                return;
+            }
+        }
+    }
+    print!("{}", output);
+}
+
+fn spawn_pager(output: &str) -> io::Result<()> {
+    let pager = env::var("PAGER")
+        .ok()
+        .filter(|p| !p.trim().is_empty())
+        .unwrap_or_else(|| "less -R".to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+
+    let mut child = Command::new(program)
+        .args(parts)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may exit before reading everything; that's not an error
+        let _ = stdin.write_all(output.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+const VIEW_HELP: &str = "\
+n, →, space    next turn
+p, ←           previous turn
+j/k, ↓/↑       scroll one line
+PgDn/PgUp      scroll one page
+g              go to turn n
+/              search forward (empty repeats the last search)
+f              fold/unfold the current answer
+w              write a code block of the current turn to a file
+q, Esc         quit
+?              show/hide this help";
+
+fn cmd_view(file: &str, style: &RenderArgs) -> ExitCode {
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
     let doc = Document::parse(&content);
-    let messages = doc.to_openai_chat();
-    match serde_json::to_string_pretty(&messages) {
-        Ok(json) => {
-            println!("{}", json);
-            ExitCode::SUCCESS
+    if doc.turns.is_empty() {
+        eprintln!("error: {}: no turns", file);
+        return ExitCode::FAILURE;
+    }
+
+    let renderer = MarkdownRenderer::with_options(style.options());
+    let mut viewer = Viewer::new(&doc, &renderer);
+
+    // Without a terminal to take over, show every turn in a row
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stdout) {
+        for i in 0..viewer.len() {
+            viewer.goto(i);
+            print!("{}", viewer.render_current());
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    match run_viewer(&mut viewer) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Raw mode on the alternate screen, restored when dropped, even on panic
+struct RawScreen;
+
+impl RawScreen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let guard = RawScreen;
+        execute!(io::stdout(), EnterAlternateScreen, DisableLineWrap, cursor::Hide)?;
+        Ok(guard)
+    }
+}
+
+impl Drop for RawScreen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, EnableLineWrap, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn run_viewer(viewer: &mut Viewer) -> io::Result<()> {
+    let _screen = RawScreen::enter()?;
+    let mut stdout = io::stdout();
+    let mut scroll = 0;
+    let mut status = String::from("? for help");
+    let mut last_search = String::new();
+    let mut show_help = false;
+
+    loop {
+        let (_, height) = terminal::size()?;
+        let page = (height as usize).saturating_sub(1).max(1);
+        let rendered = if show_help { VIEW_HELP.to_string() } else { viewer.render_current() };
+        let lines: Vec<&str> = rendered.lines().collect();
+        scroll = scroll.min(lines.len().saturating_sub(page));
+        draw_page(&mut stdout, &lines[scroll..], page, &status)?;
+        status.clear();
+
+        let Some(key) = read_key()? else { continue };
+        if show_help {
+            show_help = false;
+            continue;
+        }
+        let turn = viewer.current();
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('q') | KeyCode::Esc, _) => break,
+            (KeyCode::Char('c'), KeyModifiers::CONTROL) => break,
+            (KeyCode::Char('n') | KeyCode::Char(' ') | KeyCode::Right, _) => {
+                if !viewer.next_turn() {
+                    status.push_str("end of conversation");
+                }
+            }
+            (KeyCode::Char('p') | KeyCode::Left, _) => {
+                if !viewer.prev_turn() {
+                    status.push_str("start of conversation");
+                }
+            }
+            (KeyCode::Char('j') | KeyCode::Down | KeyCode::Enter, _) => scroll += 1,
+            (KeyCode::Char('k') | KeyCode::Up, _) => scroll = scroll.saturating_sub(1),
+            (KeyCode::PageDown, _) => scroll += page,
+            (KeyCode::PageUp, _) => scroll = scroll.saturating_sub(page),
+            (KeyCode::Home, _) => scroll = 0,
+            (KeyCode::End, _) => scroll = usize::MAX,
+            (KeyCode::Char('f'), _) => viewer.toggle_fold(),
+            (KeyCode::Char('?'), _) => show_help = true,
+            (KeyCode::Char('g'), _) => {
+                if let Some(n) = prompt(&mut stdout, page, "go to turn: ")? {
+                    match n.trim().parse::<usize>() {
+                        Ok(n) if n > 0 && viewer.goto(n - 1) => {}
+                        _ => status = format!("no turn {} (1-{})", n.trim(), viewer.len()),
+                    }
+                }
+            }
+            (KeyCode::Char('/'), _) => {
+                if let Some(query) = prompt(&mut stdout, page, "/")? {
+                    if !query.is_empty() {
+                        last_search = query;
+                    }
+                    if !last_search.is_empty() && viewer.search(&last_search).is_none() {
+                        status = format!("not found: {}", last_search);
+                    }
+                }
+            }
+            (KeyCode::Char('w'), _) => {
+                let count = viewer.code_blocks().len();
+                if count == 0 {
+                    status.push_str("no code blocks in this turn");
+                } else if let Some(answer) = prompt(&mut stdout, page, &format!("write block (1-{}) to file: ", count))? {
+                    status = write_code_block(viewer, &answer);
+                }
+            }
+            _ => status.push_str("? for help"),
+        }
+        if viewer.current() != turn {
+            scroll = 0;
+        }
+    }
+    Ok(())
+}
+
+/// Draw a page of lines with a status line at the bottom
+fn draw_page(out: &mut impl Write, lines: &[&str], page: usize, status: &str) -> io::Result<()> {
+    queue!(out, Clear(ClearType::All))?;
+    for (row, line) in lines.iter().take(page).enumerate() {
+        queue!(out, cursor::MoveTo(0, row as u16), Print(line))?;
+    }
+    queue!(out, cursor::MoveTo(0, page as u16), Print(status))?;
+    out.flush()
+}
+
+/// Wait for the next key press, ignoring other events
+fn read_key() -> io::Result<Option<KeyEvent>> {
+    match event::read()? {
+        Event::Key(key) if key.kind != KeyEventKind::Release => Ok(Some(key)),
+        _ => Ok(None),
+    }
+}
+
+/// Read a line on the status line; None when cancelled with Esc
+fn prompt(out: &mut impl Write, row: usize, label: &str) -> io::Result<Option<String>> {
+    let mut input = String::new();
+    queue!(out, cursor::Show)?;
+    let result = loop {
+        queue!(
+            out,
+            cursor::MoveTo(0, row as u16),
+            Clear(ClearType::CurrentLine),
+            Print(label),
+            Print(&input)
+        )?;
+        out.flush()?;
+        let Some(key) = read_key()? else { continue };
+        match key.code {
+            KeyCode::Enter => break Some(input),
+            KeyCode::Esc => break None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break None,
+            KeyCode::Backspace => {
+                input.pop();
+            }
+            KeyCode::Char(c) => input.push(c),
+            _ => {}
+        }
+    };
+    queue!(out, cursor::Hide)?;
+    Ok(result)
+}
+
+/// Write code block `<n> <file>` of the current turn, returning a status
+fn write_code_block(viewer: &Viewer, answer: &str) -> String {
+    let mut args = answer.split_whitespace();
+    let (Some(index), Some(path)) = (args.next().and_then(|n| n.parse::<usize>().ok()), args.next()) else {
+        return "usage: <n> <file>".to_string();
+    };
+
+    let blocks = viewer.code_blocks();
+    match index.checked_sub(1).and_then(|i| blocks.get(i)) {
+        Some(block) => match fs::write(path, &block.code) {
+            Ok(()) => format!("wrote {}", path),
+            Err(e) => format!("error: {}: {}", path, e),
+        },
+        None => format!("no code block {} ({} in this turn)", index, blocks.len()),
+    }
+}
+
+fn cmd_chat(file: &str, client: &ChatClient, inline_attachments: bool, style: &RenderArgs) -> ExitCode {
+    // Opening first locks the file, so no other writer changes it under us
+    let mut writer = match CmfWriter::open(file) {
+        Ok(writer) => writer,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut doc = match writer.read_document() {
+        Ok(doc) => doc,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let options = AttachmentOptions::new().base_dir(base_dir(file)).inline(inline_attachments);
+    let renderer = MarkdownRenderer::with_options(style.options());
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let content = line.trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        warn_unsent(file, &find_attachments(content), &options);
+        if let Err(e) = writer.user(None, content) {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+        doc.turns.push(Turn {
+            user: UserMessage {
+                username: None,
+                content: content.to_string(),
+                metadata: Metadata::new(),
+            },
+            assistant: String::new(),
+        });
+
+        let mut output = RenderStream::new(&renderer);
+        let reply = client.complete(&doc.to_openai_chat_with(&options), |token| {
+            writer.assistant(token)?;
+            output.push(token);
+            Ok(())
+        });
+        output.finish();
+        // Whatever arrived is kept, even when the reply was cut off
+        if let Err(e) = writer.end_turn() {
+            eprintln!("error: {}: {}", file, e);
+            return ExitCode::FAILURE;
+        }
+        match reply {
+            Ok(reply) => doc.turns.last_mut().expect("just pushed").assistant = reply.trim().to_string(),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return ExitCode::FAILURE;
+            }
         }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn cmd_serve(addr: &str, max_body: usize, workers: usize) -> ExitCode {
+    let server = match tiny_http::Server::http(addr) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("error: {}: {}", addr, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("listening on http://{} (API description at /openapi.json)", addr);
+    match server::serve(&server, max_body, workers) {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             eprintln!("error: {}", e);
             ExitCode::FAILURE
@@ -128,21 +986,473 @@ fn cmd_to_openai_chat(file: &str) -> ExitCode {
     }
 }
 
-fn cmd_to_openai_responses(file: &str) -> ExitCode {
+fn cmd_stats(files: &[String], json: bool) -> ExitCode {
+    let mut code = ExitCode::SUCCESS;
+    let mut reports = Vec::new();
+    let mut total = Stats::new();
+
+    'files: for file in files {
+        let reader = match open_file(file) {
+            Ok(r) => r,
+            Err(failure) => {
+                code = failure;
+                continue;
+            }
+        };
+        let mut stats = Stats::new();
+        for turn in TurnReader::new(reader) {
+            match turn {
+                Ok(turn) => stats.add_turn(&turn),
+                Err(e) => {
+                    eprintln!("error: {}: {}", file, e);
+                    code = ExitCode::FAILURE;
+                    continue 'files;
+                }
+            }
+        }
+        let stats = stats.in_file(file);
+        total.merge(&stats);
+        reports.push((file.as_str(), stats));
+    }
+
+    if json {
+        #[derive(Serialize)]
+        struct FileStats<'a> {
+            file: &'a str,
+            #[serde(flatten)]
+            stats: &'a Stats,
+        }
+        let files: Vec<_> = reports.iter().map(|(file, stats)| FileStats { file, stats }).collect();
+        let report = serde_json::json!({ "files": files, "total": total });
+        println!("{}", serde_json::to_string_pretty(&report).expect("stats always serialize"));
+    } else {
+        for (file, stats) in &reports {
+            print!("{}", format_stats(file, stats));
+        }
+        if reports.len() > 1 {
+            print!("{}", format_stats(&format!("total ({} files)", reports.len()), &total));
+        }
+    }
+    code
+}
+
+fn cmd_grep(pattern: &str, files: &[String], ignore_case: bool, options: &GrepOptions, context: usize, turns: bool) -> ExitCode {
+    let pattern = match Regex::new(&format!("{}{}", if ignore_case { "(?i)" } else { "" }, pattern)) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let from_stdin = files.is_empty();
+    let stdin = ["(standard input)".to_string()];
+    let files = if from_stdin { &stdin[..] } else { files };
+
+    let mut found = false;
+    let mut failed = false;
+    let mut printed_turns = false;
+    for file in files {
+        let input = if from_stdin {
+            io::read_to_string(io::stdin()).map_err(|e| eprintln!("error: {}: {}", file, e))
+        } else {
+            read_file(file).map_err(|_| ())
+        };
+        let Ok(input) = input else {
+            failed = true;
+            continue;
+        };
+
+        let matches = grep::grep(&input, &pattern, options);
+        found |= !matches.is_empty();
+        if turns {
+            let doc = Document::parse(&input);
+            let mut numbers: Vec<usize> = matches.iter().map(|m| m.turn).collect();
+            numbers.dedup();
+            if !numbers.is_empty() {
+                // A blank line keeps the next file's first user line valid
+                if printed_turns {
+                    println!();
+                }
+                let turns = numbers.into_iter().map(|n| doc.turns[n - 1].clone()).collect();
+                println!("{}", Document { turns }.to_cmf());
+                printed_turns = true;
+            }
+            continue;
+        }
+
+        // Like grep: `:` after matching lines, `-` after context, `--` between groups
+        let lines: Vec<&str> = input.lines().collect();
+        let mut printed = 0;
+        for (i, m) in matches.iter().enumerate() {
+            let first = m.line.saturating_sub(context).max(printed + 1);
+            if context > 0 && printed > 0 && first > printed + 1 {
+                println!("--");
+            }
+            for line in first..m.line {
+                println!("{}-{}- {}", file, line, lines[line - 1]);
+            }
+            println!("{}:{}: #{} {}: {}", file, m.line, m.turn, m.speaker, m.text);
+            let next = matches.get(i + 1).map_or(usize::MAX, |next| next.line);
+            let last = (m.line + context).min(lines.len()).min(next - 1);
+            for line in m.line + 1..=last {
+                println!("{}-{}- {}", file, line, lines[line - 1]);
+            }
+            printed = last.max(m.line);
+        }
+    }
+
+    if failed || !found {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn cmd_index(dir: &str) -> ExitCode {
+    let path = Path::new(dir).join(INDEX_FILE);
+    let mut index = match Index::load(&path) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Index::new(),
+        Err(e) => {
+            eprintln!("{}: rebuilding index: {}", path.display(), e);
+            Index::new()
+        }
+    };
+
+    let summary = match index.update(Path::new(dir)) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("error: {}: {}", dir, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    for (file, e) in &summary.skipped {
+        eprintln!("{}: warning: skipped: {}", Path::new(dir).join(file).display(), e);
+    }
+    if let Err(e) = index.save(&path) {
+        eprintln!("error: {}: {}", path.display(), e);
+        return ExitCode::FAILURE;
+    }
+    eprintln!(
+        "indexed {} turns in {} files ({} reindexed, {} removed)",
+        index.len(),
+        summary.files,
+        summary.indexed,
+        summary.removed
+    );
+    ExitCode::SUCCESS
+}
+
+fn cmd_search(query: &str, dir: &str, limit: usize, json: bool) -> ExitCode {
+    let query: Query = match query.parse() {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let path = Path::new(dir).join(INDEX_FILE);
+    let index = match Index::load(&path) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("error: {}: no index, run `cmf index {}` first", dir, dir);
+            return ExitCode::FAILURE;
+        }
+        Err(e) => {
+            eprintln!("error: {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let hits = index.search(&query, limit);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&hits).expect("hits always serialize"));
+        return ExitCode::SUCCESS;
+    }
+    for hit in &hits {
+        let doc = hit.doc;
+        let who = match doc.users.first() {
+            Some(user) => format!("@{}", user),
+            None => doc.role.to_string(),
+        };
+        let date = doc.date.as_deref().map(|date| format!(" {}", date)).unwrap_or_default();
+        println!(
+            "{}:{}: #{} {}{}: {}",
+            Path::new(dir).join(&doc.file).display(),
+            doc.line,
+            doc.turn,
+            who,
+            date,
+            doc.preview
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn cmd_tokens(file: &str, encoding: Option<&str>, limit: Option<usize>, turns: bool, json: bool) -> ExitCode {
+    let tokenizer = match load_tokenizer(encoding) {
+        Ok(tokenizer) => tokenizer,
+        Err(code) => return code,
+    };
+    let input = match read_file(file) {
+        Ok(s) => s,
+        Err(code) => return code,
+    };
+    let count = Document::parse(&input).token_count(tokenizer.as_ref());
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&count).expect("token counts always serialize"));
+    } else {
+        let approx = if count.exact { "" } else { "~" };
+        if turns {
+            for (i, tokens) in count.turns.iter().enumerate() {
+                println!("#{}: {}{}", i + 1, approx, tokens);
+            }
+        } else {
+            for message in &count.messages {
+                println!("#{} {}: {}{}", message.turn, message.role, approx, message.tokens);
+            }
+        }
+        println!(
+            "total: {}{} tokens ({} per message and {} for the reply included)",
+            approx,
+            count.total,
+            tokens::MESSAGE_OVERHEAD,
+            tokens::REPLY_OVERHEAD
+        );
+    }
+
+    match limit {
+        Some(limit) if count.total > limit => {
+            eprintln!("error: {}: {} tokens exceed the limit of {}", file, count.total, limit);
+            ExitCode::FAILURE
+        }
+        Some(limit) if !json => {
+            println!("fits in {} ({} left)", limit, limit - count.total);
+            ExitCode::SUCCESS
+        }
+        _ => ExitCode::SUCCESS,
+    }
+}
+
+fn format_stats(title: &str, stats: &Stats) -> String {
+    let list = |counts: &std::collections::BTreeMap<String, usize>| {
+        let items: Vec<String> = counts.iter().map(|(name, count)| format!("{} {}", name, count)).collect();
+        if items.is_empty() { "none".to_string() } else { items.join(", ") }
+    };
+    let turn = |turn: &Option<cmf::stats::TurnLength>| match turn {
+        Some(turn) => format!("#{} ({} chars)", turn.turn, turn.chars),
+        None => "-".to_string(),
+    };
+
+    let mut out = format!("{}\n", title);
+    out.push_str(&format!("  turns: {} ({} unanswered)\n", stats.turns, stats.unanswered));
+    out.push_str(&format!("  participants: {}\n", list(&stats.participants)));
+    for (role, text) in &stats.roles {
+        out.push_str(&format!(
+            "  {}: {} messages, {} chars, {} words, ~{} tokens\n",
+            role, text.messages, text.chars, text.words, text.tokens
+        ));
+    }
+    out.push_str(&format!(
+        "  longest turn: {}, shortest turn: {}\n",
+        turn(&stats.longest_turn),
+        turn(&stats.shortest_turn)
+    ));
+    out.push_str(&format!("  code blocks: {}\n", list(&stats.code_blocks)));
+    out.push_str(&format!("  tables: {}, links: {}\n", stats.tables, stats.links));
+    out
+}
+
+/// Renders streamed markdown a block at a time, as blank lines outside
+/// code fences complete them
+///
+/// The reply so far is re-rendered as a whole, so the output ends up the
+/// same as rendering the finished reply; only the new part is printed.
+struct RenderStream<'a> {
+    renderer: &'a MarkdownRenderer,
+    text: String,
+    /// Length of `text` that has been rendered
+    rendered: usize,
+    printed: String,
+}
+
+impl<'a> RenderStream<'a> {
+    fn new(renderer: &'a MarkdownRenderer) -> Self {
+        Self {
+            renderer,
+            text: String::new(),
+            rendered: 0,
+            printed: String::new(),
+        }
+    }
+
+    fn push(&mut self, token: &str) {
+        self.text.push_str(token);
+        match complete_blocks(&self.text) {
+            Some(end) if end > self.rendered => {
+                let output = self.renderer.render(&self.text[..end]);
+                // Trailing blank lines depend on what follows
+                self.print(output.trim_end_matches('\n'), end);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) {
+        if self.text.len() > self.rendered {
+            let output = self.renderer.render(&self.text);
+            self.print(&output, self.text.len());
+        }
+        if !self.printed.is_empty() && !self.printed.ends_with('\n') {
+            println!();
+        }
+    }
+
+    fn print(&mut self, output: &str, rendered: usize) {
+        match output.strip_prefix(self.printed.as_str()) {
+            Some(new) => print!("{}", new),
+            // Earlier blocks rendered differently in context; start afresh
+            None => print!("\n{}", self.renderer.render(&self.text[self.rendered..rendered])),
+        }
+        let _ = io::stdout().flush();
+        self.printed = output.to_string();
+        self.rendered = rendered;
+    }
+}
+
+/// Length of the complete blocks at the start of `text`: up to its last
+/// blank line outside a code fence
+fn complete_blocks(text: &str) -> Option<usize> {
+    let mut in_fence = false;
+    let mut end = None;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n').filter(|line| line.ends_with('\n')) {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if trimmed.is_empty() && !in_fence {
+            end = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+    end
+}
+
+/// Directory that relative attachment paths in `file` are resolved against
+fn base_dir(file: &str) -> &Path {
+    Path::new(file).parent().unwrap_or(Path::new("."))
+}
+
+fn cmd_to_openai_chat(file: &str, selection: &Selection, budget: &Budget, inline_attachments: bool) -> ExitCode {
+    let turns = match selection.read_turns(file).and_then(|turns| budget.fit(file, turns)) {
+        Ok(turns) => turns,
+        Err(code) => return code,
+    };
+
+    let options = AttachmentOptions::new().base_dir(base_dir(file)).inline(inline_attachments);
+    let messages = selection
+        .messages(turns)
+        .map(|message| {
+            message.map(|message| {
+                warn_unsent(file, &message.attachments, &options);
+                ChatMessage::from_message(message, &options)
+            })
+        });
+    print_json_stream(file, messages)
+}
+
+fn cmd_to_openai_responses(file: &str, selection: &Selection, budget: &Budget, inline_attachments: bool) -> ExitCode {
+    let turns = match selection.read_turns(file).and_then(|turns| budget.fit(file, turns)) {
+        Ok(turns) => turns,
+        Err(code) => return code,
+    };
+
+    let options = AttachmentOptions::new().base_dir(base_dir(file)).inline(inline_attachments);
+    let items = selection.messages(turns).flat_map(|message| match message {
+        Ok(message) => {
+            warn_unsent(file, &message.attachments, &options);
+            ResponsesItem::from_message(message, &options).into_iter().map(Ok).collect()
+        }
+        Err(e) => vec![Err(e)],
+    });
+    print_json_stream(file, items)
+}
+
+/// Warn about local attachments that converters leave as links in the text
+fn warn_unsent(file: &str, attachments: &[Attachment], options: &AttachmentOptions) {
+    for attachment in attachments.iter().filter(|attachment| attachment.url(options).is_none()) {
+        let reason = if options.inline {
+            "cannot be read"
+        } else {
+            "is not inlined (use --inline-attachments)"
+        };
+        eprintln!("{}: warning: {} {}, sent as a link only", file, attachment.target, reason);
+    }
+}
+
+/// Serializes items as a JSON array while they are read, so the input
+/// never has to fit in memory
+struct JsonStream<I>(RefCell<I>);
+
+impl<I, T> Serialize for JsonStream<I>
+where
+    I: Iterator<Item = io::Result<T>>,
+    T: Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(None)?;
+        for item in &mut *self.0.borrow_mut() {
+            seq.serialize_element(&item.map_err(S::Error::custom)?)?;
+        }
+        seq.end()
+    }
+}
+
+/// Print items as a pretty JSON array, the same as serializing a `Vec`
+fn print_json_stream<T: Serialize>(file: &str, items: impl Iterator<Item = io::Result<T>>) -> ExitCode {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = serde_json::to_writer_pretty(&mut out, &JsonStream(RefCell::new(items)))
+        .and_then(|_| writeln!(out).map_err(serde_json::Error::io));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}: {}", file, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn cmd_to_json(file: &str, selection: &Selection) -> ExitCode {
+    // The JSON form is made of whole turns, so there is no assistant-only view
+    if selection.assistant_only {
+        eprintln!("error: --assistant-only is not supported by to-json");
+        return ExitCode::FAILURE;
+    }
     let content = match read_file(file) {
         Ok(c) => c,
         Err(code) => return code,
     };
 
-    let doc = Document::parse(&content);
-    let messages = doc.to_openai_responses();
-    match serde_json::to_string_pretty(&messages) {
-        Ok(json) => {
-            println!("{}", json);
+    let doc = selection.apply(Document::parse(&content));
+    println!("{}", doc.to_json());
+    ExitCode::SUCCESS
+}
+
+fn cmd_from_json(file: &str) -> ExitCode {
+    let content = match read_file(file) {
+        Ok(c) => c,
+        Err(code) => return code,
+    };
+
+    match Document::from_json(&content) {
+        Ok(doc) => {
+            println!("{}", doc.to_cmf());
             ExitCode::SUCCESS
         }
         Err(e) => {
-            eprintln!("error: {}", e);
+            eprintln!("error: {}: {}", file, e);
             ExitCode::FAILURE
         }
     }
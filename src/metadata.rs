@@ -0,0 +1,188 @@
+//! Per-message metadata in `<!-- cmf: ... -->` comments
+//!
+//! A metadata comment sits on its own line and describes the message that
+//! follows it:
+//!
+//! ```markdown
+//! <!-- cmf: ts=2026-01-01T12:00Z -->
+//!
+//! > What time is it?
+//! <!-- cmf: model=gpt-x id=msg_1 -->
+//! Noon.
+//! ```
+//!
+//! Values containing spaces, quotes or `--` are written in double quotes
+//! with backslash escapes: `\"`, `\\`, `\n` and `\r`, and `-\-` so that no
+//! value can end the comment. The bare `<!-- cmf -->` separator is a
+//! metadata comment with no entries.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::{MESSAGE_SEPARATOR, SPEAKER_KEY};
+
+/// Key/value metadata attached to a message
+pub type Metadata = BTreeMap<String, String>;
+
+/// Parse a metadata comment line; `None` when the line is not one
+///
+/// Like user lines, comments start in column 1: an indented one is
+/// escaped assistant text.
+pub fn parse_comment(line: &str) -> Option<Metadata> {
+    let inner = line
+        .trim_end()
+        .strip_prefix("<!--")?
+        .strip_suffix("-->")?
+        .trim()
+        .strip_prefix("cmf")?;
+
+    if inner.trim().is_empty() {
+        return Some(Metadata::new());
+    }
+    parse_entries(inner.strip_prefix(':')?)
+}
+
+fn parse_entries(input: &str) -> Option<Metadata> {
+    let mut metadata = Metadata::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Some(metadata);
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|&c| c != '=' && !c.is_whitespace()) {
+            key.push(c);
+        }
+        chars.next_if_eq(&'=')?;
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(match chars.next()? {
+                        'n' => '\n',
+                        'r' => '\r',
+                        c => c,
+                    }),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        metadata.insert(key, value);
+    }
+}
+
+/// The assistant speaker named by a metadata comment, if any
+pub fn speaker(line: &str) -> Option<String> {
+    parse_comment(line)?.remove(SPEAKER_KEY)
+}
+
+/// The assistant speaker named by the comment at `range` of `markdown`, as
+/// found by a markdown parser; indented, the comment is escaped text
+pub fn speaker_at(markdown: &str, range: Range<usize>) -> Option<String> {
+    let line_start = markdown[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    speaker(&markdown[line_start..range.end])
+}
+
+/// Write metadata as a comment line; empty metadata is the bare separator
+pub fn write_comment(metadata: &Metadata) -> String {
+    if metadata.is_empty() {
+        return MESSAGE_SEPARATOR.to_string();
+    }
+
+    let entries: Vec<String> = metadata
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, quote_value(value)))
+        .collect();
+    format!("<!-- cmf: {} -->", entries.join(" "))
+}
+
+/// Quote and escape a value that could break the comment: written raw, a
+/// newline would split it and `--` could end it
+fn quote_value(value: &str) -> String {
+    let needs_quotes =
+        value.is_empty() || value.contains("--") || value.chars().any(|c| c.is_whitespace() || c == '"' || c == '\\');
+    if !needs_quotes {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    let mut previous = None;
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '-' if previous == Some('-') => quoted.push_str("\\-"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c => quoted.push(c),
+        }
+        previous = Some(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_comment() {
+        let metadata = parse_comment("<!-- cmf: ts=2026-01-01T12:00Z model=gpt-x -->").unwrap();
+        assert_eq!(metadata["ts"], "2026-01-01T12:00Z");
+        assert_eq!(metadata["model"], "gpt-x");
+
+        assert_eq!(parse_comment("<!-- cmf -->"), Some(Metadata::new()));
+        assert_eq!(parse_comment(" <!-- cmf -->"), None);
+        assert_eq!(parse_comment("<!-- note -->"), None);
+        assert_eq!(parse_comment("<!-- cmf: broken -->"), None);
+        assert_eq!(parse_comment("text"), None);
+        assert_eq!(speaker("<!-- cmf: name=coder -->").as_deref(), Some("coder"));
+    }
+
+    #[test]
+    fn test_quoted_values_roundtrip() {
+        let mut metadata = Metadata::new();
+        metadata.insert("title".to_string(), "say \"hi\" \\ bye".to_string());
+        metadata.insert("empty".to_string(), String::new());
+        metadata.insert("tokens".to_string(), "42".to_string());
+
+        let comment = write_comment(&metadata);
+        assert!(comment.contains("tokens=42"));
+        assert_eq!(parse_comment(&comment), Some(metadata));
+        assert_eq!(write_comment(&Metadata::new()), MESSAGE_SEPARATOR);
+    }
+
+    #[test]
+    fn test_newlines_roundtrip() {
+        let mut metadata = Metadata::new();
+        metadata.insert("note".to_string(), "first\r\nsecond\nthird".to_string());
+
+        let comment = write_comment(&metadata);
+        assert_eq!(comment.lines().count(), 1);
+        assert_eq!(parse_comment(&comment), Some(metadata));
+    }
+
+    #[test]
+    fn test_comment_end_in_value_roundtrip() {
+        let mut metadata = Metadata::new();
+        metadata.insert("note".to_string(), "a-->b".to_string());
+        metadata.insert("dashes".to_string(), "---".to_string());
+        metadata.insert("range".to_string(), "1-2".to_string());
+
+        let comment = write_comment(&metadata);
+        assert!(!comment[4..comment.len() - 3].contains("--"));
+        assert!(comment.contains("range=1-2"));
+        assert_eq!(parse_comment(&comment), Some(metadata));
+    }
+}
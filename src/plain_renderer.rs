@@ -0,0 +1,254 @@
+//! Plain-text markdown renderer for search indexes, TTS and SMS-style channels.
+//!
+//! Strips all markdown formatting and decoration:
+//! - Emphasis, inline code and headings become plain text
+//! - Links and images become `text (url)`
+//! - Lists use `-` bullets or `1.` numbers
+//! - Tables become tab-separated rows
+//! - Code blocks and blockquotes are emitted as-is, without prefixes
+//! - Named assistant messages start with an `@name:` line
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::metadata::speaker_at;
+
+/// Renders markdown as readable, unformatted text
+pub struct PlainTextRenderer;
+
+impl Default for PlainTextRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlainTextRenderer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn render(&self, markdown: &str) -> String {
+        let mut state = PlainState::default();
+        // Keep the author's quotes and dashes; plain output shouldn't rewrite text
+        let options = Options::all() - Options::ENABLE_SMART_PUNCTUATION;
+
+        for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+            match event {
+                Event::Start(tag) => state.start(tag),
+                Event::End(tag) => state.end(tag),
+                Event::Text(text) | Event::Code(text) => state.push_text(&text),
+                Event::SoftBreak => state.push_text(" "),
+                Event::HardBreak => state.line_break(),
+                Event::Rule => state.block_break(),
+                Event::TaskListMarker(checked) => {
+                    state.push_text(if checked { "[x] " } else { "[ ] " })
+                }
+                Event::Html(_) => {
+                    if let Some(name) = speaker_at(markdown, range) {
+                        state.block_break();
+                        state.push_text(&format!("@{}:", name));
+                        state.line_break();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        state.output.trim_end().to_string() + "\n"
+    }
+}
+
+#[derive(Default)]
+struct PlainState {
+    output: String,
+    /// Next number for each open list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    /// Set right after a list marker so the item's first paragraph stays on its line
+    at_item_start: bool,
+    in_code_block: bool,
+    /// Link/image destinations with the output length where their text began
+    links: Vec<(String, usize)>,
+    table_row: Option<Vec<String>>,
+}
+
+impl PlainState {
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading(..) | Tag::BlockQuote => {
+                if self.at_item_start {
+                    return;
+                }
+                self.block_break();
+                self.indent();
+            }
+            Tag::CodeBlock(_) => {
+                self.block_break();
+                self.in_code_block = true;
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block_break();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.ensure_newline();
+                let depth = self.lists.len().saturating_sub(1);
+                self.output.push_str(&"  ".repeat(depth));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.output.push_str(&marker);
+                self.at_item_start = true;
+            }
+            Tag::Table(_) => self.block_break(),
+            Tag::TableHead | Tag::TableRow => self.table_row = Some(Vec::new()),
+            Tag::TableCell => {
+                if let Some(ref mut row) = self.table_row {
+                    row.push(String::new());
+                }
+            }
+            Tag::Link(_, url, _) | Tag::Image(_, url, _) => {
+                let start = self.current_len();
+                self.links.push((url.to_string(), start));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph | Tag::Heading(..) => self.ensure_newline(),
+            Tag::CodeBlock(_) => {
+                self.in_code_block = false;
+                self.ensure_newline();
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                self.ensure_newline();
+            }
+            Tag::Item => {
+                self.at_item_start = false;
+                self.ensure_newline();
+            }
+            Tag::TableHead | Tag::TableRow => {
+                if let Some(row) = self.table_row.take() {
+                    self.output.push_str(&row.join("\t"));
+                    self.output.push('\n');
+                }
+            }
+            Tag::Link(..) | Tag::Image(..) => {
+                if let Some((url, start)) = self.links.pop() {
+                    let text = self.current_text_since(start);
+                    if !url.is_empty() && text != url {
+                        self.push_text(&format!(" ({})", url));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if let Some(cell) = self.table_row.as_mut().and_then(|row| row.last_mut()) {
+            cell.push_str(text);
+            return;
+        }
+        self.at_item_start = false;
+        if self.in_code_block {
+            self.output.push_str(text);
+        } else {
+            // Keep continuation lines aligned with list item text
+            let indent = "  ".repeat(self.lists.len());
+            self.output.push_str(&text.replace('\n', &format!("\n{}", indent)));
+        }
+    }
+
+    fn line_break(&mut self) {
+        self.output.push('\n');
+        self.indent();
+    }
+
+    fn indent(&mut self) {
+        self.output.push_str(&"  ".repeat(self.lists.len()));
+    }
+
+    fn ensure_newline(&mut self) {
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            self.output.push('\n');
+        }
+    }
+
+    /// Separate blocks by a blank line, or a single newline inside lists
+    fn block_break(&mut self) {
+        self.ensure_newline();
+        if self.lists.is_empty() && !self.output.is_empty() && !self.output.ends_with("\n\n") {
+            self.output.push('\n');
+        }
+    }
+
+    fn current_len(&self) -> usize {
+        match self.table_row.as_ref().and_then(|row| row.last()) {
+            Some(cell) => cell.len(),
+            None => self.output.len(),
+        }
+    }
+
+    fn current_text_since(&self, start: usize) -> String {
+        match self.table_row.as_ref().and_then(|row| row.last()) {
+            Some(cell) => cell[start..].to_string(),
+            None => self.output[start..].to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &str) -> String {
+        PlainTextRenderer::new().render(markdown)
+    }
+
+    #[test]
+    fn test_strips_inline_formatting() {
+        let result = render("# Title\n\nSome **bold**, *italic* and `code`.");
+        assert_eq!(result, "Title\n\nSome bold, italic and code.\n");
+    }
+
+    #[test]
+    fn test_links() {
+        assert_eq!(render("See [docs](https://example.com)."), "See docs (https://example.com).\n");
+        assert_eq!(render("<https://example.com>"), "https://example.com\n");
+    }
+
+    #[test]
+    fn test_lists() {
+        let result = render("- one\n- two\n  - nested\n\n1. first\n2. second\n- [x] done");
+        assert_eq!(result, "- one\n- two\n  - nested\n\n1. first\n2. second\n\n- [x] done\n");
+    }
+
+    #[test]
+    fn test_table_as_tab_separated() {
+        let result = render("| A | B |\n|---|---|\n| 1 | [x](http://x.io) |");
+        assert_eq!(result, "A\tB\n1\tx (http://x.io)\n");
+    }
+
+    #[test]
+    fn test_code_block_and_quote() {
+        let result = render("Intro\n\n```rust\nfn main() {}\n```\n\n> quoted\n\n---\n\nEnd");
+        assert_eq!(result, "Intro\n\nfn main() {}\n\nquoted\n\nEnd\n");
+    }
+
+    #[test]
+    fn test_speaker_label() {
+        let result = render("<!-- cmf: name=planner -->\nPlan it.");
+        assert_eq!(result, "@planner:\n\nPlan it.\n");
+
+        // An escaped comment is text, not a label
+        assert_eq!(render("Plan it.\n <!-- cmf: name=planner -->\nDone."), "Plan it.\n\nDone.\n");
+    }
+}
@@ -0,0 +1,206 @@
+//! Incremental parsing over `io::BufRead`
+//!
+//! `TurnReader` yields turns as soon as the next user block starts, so a
+//! log of any size is processed holding one turn at a time. It shares its
+//! state machine with `Document::parse` and produces identical turns.
+
+use std::io::{self, BufRead};
+
+use crate::metadata::parse_comment;
+use crate::tool_calls::ToolIds;
+use crate::{parse_user_block, trim_assistant_block, Message, Metadata, Turn};
+
+/// Line-by-line CMF state machine
+#[derive(Debug, Default)]
+pub(crate) struct TurnParser {
+    user_lines: Vec<String>,
+    assistant_lines: Vec<String>,
+    metadata: Metadata,
+    in_user_block: bool,
+    seen_first_user: bool,
+}
+
+impl TurnParser {
+    /// Feed one line (without its line ending); returns the turn it completes
+    pub fn push_line(&mut self, line: &str) -> Option<Turn> {
+        let mut completed = None;
+
+        if line.starts_with('>') {
+            if !self.in_user_block {
+                // A metadata comment right before the block belongs to it
+                let metadata = take_trailing_metadata(&mut self.assistant_lines);
+
+                // If we were collecting assistant content, finalize the previous turn
+                if self.seen_first_user && !self.user_lines.is_empty() {
+                    completed = Some(self.take_turn(metadata));
+                } else {
+                    self.metadata = metadata;
+                }
+                self.assistant_lines.clear();
+            }
+
+            self.in_user_block = true;
+            self.seen_first_user = true;
+            // Strip the leading `>` and optional single space
+            let content = line.strip_prefix('>').unwrap_or(line);
+            let content = content.strip_prefix(' ').unwrap_or(content);
+            self.user_lines.push(content.to_string());
+        } else {
+            // Transition from user to assistant
+            self.in_user_block = false;
+            // Lines before the first user block are dropped (preamble/frontmatter);
+            // only the last one is kept, in case it is a metadata comment
+            if !self.seen_first_user && !line.trim().is_empty() {
+                self.assistant_lines.clear();
+            }
+            self.assistant_lines.push(line.to_string());
+        }
+
+        completed
+    }
+
+    /// Finalize the last turn if we have user content
+    pub fn finish(&mut self) -> Option<Turn> {
+        if self.user_lines.is_empty() {
+            return None;
+        }
+        Some(self.take_turn(Metadata::new()))
+    }
+
+    /// Build the pending turn and start the next one with `next_metadata`
+    fn take_turn(&mut self, next_metadata: Metadata) -> Turn {
+        let metadata = std::mem::replace(&mut self.metadata, next_metadata);
+        let user = parse_user_block(&self.user_lines, metadata);
+        let assistant = trim_assistant_block(&self.assistant_lines);
+        self.user_lines.clear();
+        Turn { user, assistant }
+    }
+}
+
+/// Remove a metadata comment that is the last non-blank line
+fn take_trailing_metadata(lines: &mut Vec<String>) -> Metadata {
+    let Some(last) = lines.iter().rposition(|line| !line.trim().is_empty()) else {
+        return Metadata::new();
+    };
+    match parse_comment(&lines[last]) {
+        Some(metadata) => {
+            lines.truncate(last);
+            metadata
+        }
+        None => Metadata::new(),
+    }
+}
+
+/// Iterator over the turns of a CMF stream
+///
+/// ```
+/// use std::io::Cursor;
+/// use cmf::reader::TurnReader;
+///
+/// let input = Cursor::new("> Hi\nHello!\n\n> Bye\nSee you.");
+/// let turns: Vec<_> = TurnReader::new(input).collect::<Result<_, _>>().unwrap();
+/// assert_eq!(turns[1].assistant, "See you.");
+/// ```
+pub struct TurnReader<R> {
+    reader: R,
+    parser: TurnParser,
+    line: String,
+    done: bool,
+}
+
+impl<R: BufRead> TurnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            parser: TurnParser::default(),
+            line: String::new(),
+            done: false,
+        }
+    }
+
+    /// Flatten the turns into messages, pairing tool calls and results
+    /// across turns like `Document::messages`
+    pub fn messages(self) -> impl Iterator<Item = io::Result<Message>> {
+        messages(self)
+    }
+}
+
+impl<R: BufRead> Iterator for TurnReader<R> {
+    type Item = io::Result<Turn>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.line.clear();
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => {
+                    self.done = true;
+                    return self.parser.finish().map(Ok);
+                }
+                Ok(_) => {
+                    // Same line endings as `str::lines`: `\n` or `\r\n`
+                    let line = match self.line.strip_suffix('\n') {
+                        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+                        None => &self.line,
+                    };
+                    if let Some(turn) = self.parser.push_line(line) {
+                        return Some(Ok(turn));
+                    }
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Flatten a stream of turns into messages, like `Document::messages`;
+/// generated tool ids skip only the ids of turns read so far
+pub fn messages<I>(turns: I) -> impl Iterator<Item = io::Result<Message>>
+where
+    I: IntoIterator<Item = io::Result<Turn>>,
+{
+    let mut ids = ToolIds::default();
+    turns.into_iter().flat_map(move |turn| match turn {
+        Ok(turn) => {
+            ids.reserve(&turn.assistant);
+            turn.messages_with_ids(&mut ids).into_iter().map(Ok).collect()
+        }
+        Err(e) => vec![Err(e)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+    use std::io::Cursor;
+
+    const INPUT: &str = "preamble\n<!-- cmf: ts=1 -->\n\n> @alice: Hi\r\n> there\nHello!\n\n```tool_call name=f\n{}\n```\n\n<!-- cmf: ts=2 -->\n\n> Bye\n\n```tool_result\nok\n```\n";
+
+    #[test]
+    fn test_same_turns_as_parse() {
+        let turns: Vec<Turn> = TurnReader::new(Cursor::new(INPUT)).collect::<io::Result<_>>().unwrap();
+        assert_eq!(turns, Document::parse(INPUT).turns);
+        assert_eq!(turns[0].user.metadata["ts"], "1");
+        assert_eq!(turns[1].user.metadata["ts"], "2");
+    }
+
+    #[test]
+    fn test_same_messages_as_document() {
+        let messages: Vec<Message> = TurnReader::new(Cursor::new(INPUT)).messages().collect::<io::Result<_>>().unwrap();
+        assert_eq!(messages, Document::parse(INPUT).messages());
+        assert_eq!(messages.last().unwrap().tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_turns_yielded_incrementally() {
+        let mut reader = TurnReader::new(Cursor::new("> One\nA\n\n> Two\nB"));
+        assert_eq!(reader.next().unwrap().unwrap().assistant, "A");
+        assert_eq!(reader.next().unwrap().unwrap().assistant, "B");
+        assert!(reader.next().is_none());
+        assert!(TurnReader::new(Cursor::new("no user lines")).next().is_none());
+    }
+}
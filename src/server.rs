@@ -0,0 +1,341 @@
+//! Local HTTP API over the library
+//!
+//! `cmf serve` lets programs in other languages parse CMF without linking
+//! Rust. Each `POST` takes CMF text as its body:
+//!
+//! - `/parse`: the versioned JSON form from `Document::to_json`
+//! - `/check`: conformance issues
+//! - `/convert?to=openai-chat|openai-responses|json`
+//! - `/render?format=html|ansi|plain[&width=N]`
+//!
+//! `GET /openapi.json` describes the API and `GET /cmf.schema.json` the
+//! parsed form. Errors are JSON objects with an `error` message.
+//!
+//! HTML output is safe to embed: raw HTML in the input is escaped and
+//! links with schemes other than `http`, `https` and `mailto` are dropped.
+
+use std::io::{self, Read};
+use std::thread;
+
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag};
+use serde::Serialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Server};
+
+use crate::plain_renderer::PlainTextRenderer;
+use crate::terminal_renderer::{ColorMode, MarkdownRenderer, RendererOptions};
+use crate::Document;
+
+/// Default limit on request bodies, in bytes
+pub const DEFAULT_MAX_BODY: usize = 1024 * 1024;
+
+/// Default number of threads serving requests
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// OpenAPI description of the endpoints
+pub const OPENAPI: &str = include_str!("../schema/openapi.json");
+
+/// An HTTP response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(value).expect("responses always serialize"),
+        }
+    }
+
+    fn text(content_type: &'static str, body: String) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &json!({ "error": message }))
+    }
+}
+
+/// Answer a request for `url` (path and query) with the given body
+pub fn handle(method: &str, url: &str, body: &str) -> Response {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let Some(params) = parse_query(query) else {
+        return Response::error(400, "invalid percent-encoding in query");
+    };
+    let param = |name: &str| params.iter().find_map(|(key, value)| (key == name).then_some(value.as_str()));
+
+    match (method, path) {
+        ("GET", "/openapi.json") => Response::text("application/json", OPENAPI.to_string()),
+        ("GET", "/cmf.schema.json") => Response::text("application/json", Document::json_schema()),
+        ("POST", "/parse") => Response::text("application/json", Document::parse(body).to_json()),
+        ("POST", "/check") => {
+            let issues = Document::check(body);
+            Response::json(200, &json!({ "valid": issues.is_empty(), "issues": issues }))
+        }
+        ("POST", "/convert") => {
+            let doc = Document::parse(body);
+            match param("to") {
+                Some("openai-chat") => Response::json(200, &doc.to_openai_chat()),
+                Some("openai-responses") => Response::json(200, &doc.to_openai_responses()),
+                Some("json") => Response::text("application/json", doc.to_json()),
+                Some(other) => Response::error(400, &format!("unknown format: {}", other)),
+                None => Response::error(400, "missing query parameter: to"),
+            }
+        }
+        ("POST", "/render") => {
+            let width = match param("width").map(str::parse::<usize>) {
+                Some(Ok(width)) => Some(width),
+                Some(Err(_)) => return Response::error(400, "width must be a number"),
+                None => None,
+            };
+            match param("format").unwrap_or("html") {
+                "html" => Response::text("text/html; charset=utf-8", safe_html(body)),
+                "ansi" => {
+                    let mut options = RendererOptions::new().color(ColorMode::Always);
+                    if let Some(width) = width {
+                        options = options.width(width);
+                    }
+                    let output = MarkdownRenderer::with_options(options).render(body);
+                    Response::text("text/plain; charset=utf-8", output)
+                }
+                "plain" => Response::text("text/plain; charset=utf-8", PlainTextRenderer::new().render(body)),
+                other => Response::error(400, &format!("unknown format: {}", other)),
+            }
+        }
+        (_, "/parse" | "/check" | "/convert" | "/render" | "/openapi.json" | "/cmf.schema.json") => {
+            Response::error(405, &format!("method not allowed: {}", method))
+        }
+        _ => Response::error(404, &format!("not found: {}", path)),
+    }
+}
+
+/// Decode `key=value` pairs of a query string; `None` when an escape is
+/// invalid
+fn parse_query(query: &str) -> Option<Vec<(String, String)>> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Some((percent_decode(key)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes and `+` for space
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        bytes.push(match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            byte => byte,
+        });
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Render markdown as HTML with raw HTML escaped and unsafe links dropped
+fn safe_html(markdown: &str) -> String {
+    let is_safe = |url: &str| match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains('/') => {
+            ["http", "https", "mailto"].iter().any(|safe| scheme.eq_ignore_ascii_case(safe))
+        }
+        _ => true,
+    };
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        Event::Start(Tag::Link(kind, url, title)) if !is_safe(&url) => {
+            Event::Start(Tag::Link(kind, CowStr::Borrowed(""), title))
+        }
+        Event::Start(Tag::Image(kind, url, title)) if !is_safe(&url) => {
+            Event::Start(Tag::Image(kind, CowStr::Borrowed(""), title))
+        }
+        event => event,
+    });
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+/// Serve requests on `workers` threads until the server is shut down,
+/// rejecting bodies over `max_body` bytes
+///
+/// `Server::unblock` stops the workers: each one that stops wakes the next.
+/// A client that hangs up before its response doesn't stop the server.
+pub fn serve(server: &Server, max_body: usize, workers: usize) -> io::Result<()> {
+    thread::scope(|scope| {
+        for i in 0..workers.max(1) {
+            let spawned = thread::Builder::new().name(format!("cmf-serve-{}", i)).spawn_scoped(scope, || {
+                for mut request in server.incoming_requests() {
+                    let response = match read_body(&mut request, max_body) {
+                        Ok(body) => handle(request.method().as_str(), request.url(), &body),
+                        Err(response) => response,
+                    };
+                    let _ = respond(request, response);
+                }
+                server.unblock();
+            });
+            if let Err(e) = spawned {
+                // Stop the workers already running before reporting
+                server.unblock();
+                return Err(e);
+            }
+        }
+        Ok(())
+    })
+}
+
+fn read_body(request: &mut Request, max_body: usize) -> Result<String, Response> {
+    let too_large = || Response::error(413, &format!("request body exceeds {} bytes", max_body));
+    if *request.method() == Method::Get {
+        return Ok(String::new());
+    }
+    if request.body_length().is_some_and(|length| length > max_body) {
+        return Err(too_large());
+    }
+
+    // The length header may be missing or wrong, so stop reading at the limit
+    let mut body = Vec::new();
+    let limit = max_body as u64 + 1;
+    if let Err(e) = request.as_reader().take(limit).read_to_end(&mut body) {
+        return Err(Response::error(400, &e.to_string()));
+    }
+    if body.len() > max_body {
+        return Err(too_large());
+    }
+    String::from_utf8(body).map_err(|_| Response::error(400, "body is not UTF-8"))
+}
+
+fn respond(request: Request, response: Response) -> io::Result<()> {
+    let header = Header::from_bytes("Content-Type", response.content_type).expect("content types are valid headers");
+    let reply = tiny_http::Response::from_string(response.body)
+        .with_status_code(response.status)
+        .with_header(header);
+    request.respond(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const INPUT: &str = "> Hi\nHello **there**";
+
+    fn body(response: &Response) -> serde_json::Value {
+        serde_json::from_str(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let parsed = handle("POST", "/parse", INPUT);
+        assert_eq!(Document::from_json(&parsed.body).unwrap(), Document::parse(INPUT));
+
+        let checked = body(&handle("POST", "/check", "> Hi\nHello\n> again"));
+        assert_eq!(checked["valid"], false);
+        assert_eq!(checked["issues"][0]["line"], 3);
+
+        let chat = body(&handle("POST", "/convert?to=openai-chat", INPUT));
+        assert_eq!(chat[1]["role"], "assistant");
+
+        let html = handle("POST", "/render?format=html", INPUT);
+        assert_eq!(html.body, "<blockquote>\n<p>Hi\nHello <strong>there</strong></p>\n</blockquote>\n");
+        let ansi = handle("POST", "/render?format=%61nsi&width=40", INPUT);
+        assert_eq!(ansi, handle("POST", "/render?format=ansi&width=40", INPUT));
+        assert_eq!(ansi.content_type, "text/plain; charset=utf-8");
+
+        let schema = body(&handle("GET", "/cmf.schema.json", ""));
+        assert_eq!(schema["title"], "CMF document");
+        let plain = handle("POST", "/render?format=plain", INPUT);
+        assert!(plain.body.contains("Hello there"));
+    }
+
+    #[test]
+    fn test_errors_are_json() {
+        for (method, url, status) in [
+            ("POST", "/convert", 400),
+            ("POST", "/convert?to=yaml", 400),
+            ("POST", "/render?format=pdf", 400),
+            ("GET", "/parse", 405),
+            ("POST", "/nope", 404),
+        ] {
+            let response = handle(method, url, INPUT);
+            assert_eq!(response.status, status, "{} {}", method, url);
+            assert!(body(&response)["error"].is_string());
+        }
+    }
+
+    #[test]
+    fn test_query_decoding() {
+        assert_eq!(
+            parse_query("to=openai%2Dchat&q=a+b%20c&flag"),
+            Some(vec![
+                ("to".to_string(), "openai-chat".to_string()),
+                ("q".to_string(), "a b c".to_string()),
+                ("flag".to_string(), String::new()),
+            ])
+        );
+        assert_eq!(parse_query("to=%zz"), None);
+        assert_eq!(handle("POST", "/convert?to=%4", INPUT).status, 400);
+    }
+
+    #[test]
+    fn test_html_is_escaped() {
+        let html = safe_html("> Hi\n<script>alert(1)</script>\n\n[x](javascript:alert(1)) [y](https://example.com) [z](./a.md)");
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("href=\"https://example.com\""));
+        assert!(html.contains("href=\"./a.md\""));
+    }
+
+    #[test]
+    fn test_openapi_lists_endpoints() {
+        let openapi: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+        for path in ["/parse", "/check", "/convert", "/render"] {
+            assert!(openapi["paths"][path]["post"].is_object(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_serve_limits_body_size() {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let handle = thread::spawn({
+            let server = Arc::clone(&server);
+            move || serve(&server, 16, 3)
+        });
+
+        // Requests are served concurrently by the workers
+        let clients: Vec<_> = (0..6)
+            .map(|_| {
+                let url = url.clone();
+                thread::spawn(move || ureq::post(&format!("{}/parse", url)).send_string("> Hi").unwrap())
+            })
+            .collect();
+        for client in clients {
+            assert_eq!(client.join().unwrap().content_type(), "application/json");
+        }
+
+        match ureq::post(&format!("{}/parse", url)).send_string(&"> long\n".repeat(10)) {
+            Err(ureq::Error::Status(413, _)) => {}
+            other => panic!("expected 413, got {:?}", other.map(|r| r.status())),
+        }
+
+        server.unblock();
+        handle.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+//! Conversation statistics
+//!
+//! `Stats` accumulates counts turn by turn, so it can be fed from a
+//! `TurnReader` without loading a file, and merged across files.
+
+use std::collections::BTreeMap;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use serde::Serialize;
+
+use crate::{Role, Turn};
+
+/// Fence languages of tool activity, which are not counted as code
+pub(crate) const TOOL_LANGUAGES: [&str; 2] = ["tool_call", "tool_result"];
+
+/// Rough token count for budgeting: about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Size of the messages of one role
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TextStats {
+    pub messages: usize,
+    pub chars: usize,
+    pub words: usize,
+    /// Estimated with `estimate_tokens`
+    pub tokens: usize,
+}
+
+impl TextStats {
+    fn add(&mut self, text: &str) {
+        self.messages += 1;
+        self.chars += text.chars().count();
+        self.words += text.split_whitespace().count();
+        self.tokens += estimate_tokens(text);
+    }
+
+    fn merge(&mut self, other: &TextStats) {
+        self.messages += other.messages;
+        self.chars += other.chars;
+        self.words += other.words;
+        self.tokens += other.tokens;
+    }
+}
+
+/// A turn picked out by length
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TurnLength {
+    /// File the turn is in, once stats from several files are merged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// 1-based turn number
+    pub turn: usize,
+    /// Characters of user and assistant content
+    pub chars: usize,
+}
+
+/// Statistics of one or more conversations
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub turns: usize,
+    /// Messages per participant: `@username` for named users and speakers,
+    /// otherwise the role
+    pub participants: BTreeMap<String, usize>,
+    /// Message sizes by role
+    pub roles: BTreeMap<String, TextStats>,
+    pub longest_turn: Option<TurnLength>,
+    pub shortest_turn: Option<TurnLength>,
+    /// Fenced and indented code blocks by language (`none` without one)
+    pub code_blocks: BTreeMap<String, usize>,
+    pub tables: usize,
+    pub links: usize,
+    /// User messages without an assistant reply
+    pub unanswered: usize,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stats of a sequence of turns
+    pub fn from_turns<'a>(turns: impl IntoIterator<Item = &'a Turn>) -> Self {
+        let mut stats = Self::new();
+        for turn in turns {
+            stats.add_turn(turn);
+        }
+        stats
+    }
+
+    /// Count the next turn of the conversation
+    pub fn add_turn(&mut self, turn: &Turn) {
+        self.turns += 1;
+
+        for message in turn.messages() {
+            let participant = match (message.role, message.name) {
+                (Role::User | Role::Assistant, Some(name)) => format!("@{}", name),
+                (role, _) => role.as_str().to_string(),
+            };
+            *self.participants.entry(participant).or_default() += 1;
+            self.roles.entry(message.role.as_str().to_string()).or_default().add(&message.content);
+        }
+
+        let length = TurnLength {
+            file: None,
+            turn: self.turns,
+            chars: turn.user.content.chars().count() + turn.assistant.chars().count(),
+        };
+        if self.longest_turn.as_ref().is_none_or(|longest| length.chars > longest.chars) {
+            self.longest_turn = Some(length.clone());
+        }
+        if self.shortest_turn.as_ref().is_none_or(|shortest| length.chars < shortest.chars) {
+            self.shortest_turn = Some(length);
+        }
+
+        for block in turn.code_blocks() {
+            let language = block.language.unwrap_or_else(|| "none".to_string());
+            if !TOOL_LANGUAGES.contains(&language.as_str()) {
+                *self.code_blocks.entry(language).or_default() += 1;
+            }
+        }
+        for text in [&turn.user.content, &turn.assistant] {
+            for event in Parser::new_ext(text, Options::ENABLE_TABLES) {
+                match event {
+                    Event::Start(Tag::Table(_)) => self.tables += 1,
+                    Event::Start(Tag::Link(..)) => self.links += 1,
+                    _ => {}
+                }
+            }
+        }
+
+        if turn.assistant.trim().is_empty() && turn.role() == Role::User {
+            self.unanswered += 1;
+        }
+    }
+
+    /// Record `file` as the source of the longest and shortest turns
+    pub fn in_file(mut self, file: &str) -> Self {
+        for turn in [&mut self.longest_turn, &mut self.shortest_turn].into_iter().flatten() {
+            turn.file = Some(file.to_string());
+        }
+        self
+    }
+
+    /// Add the counts of another conversation
+    pub fn merge(&mut self, other: &Stats) {
+        self.turns += other.turns;
+        for (participant, count) in &other.participants {
+            *self.participants.entry(participant.clone()).or_default() += count;
+        }
+        for (role, stats) in &other.roles {
+            self.roles.entry(role.clone()).or_default().merge(stats);
+        }
+        if let Some(ref longest) = other.longest_turn {
+            if self.longest_turn.as_ref().is_none_or(|current| longest.chars > current.chars) {
+                self.longest_turn = Some(longest.clone());
+            }
+        }
+        if let Some(ref shortest) = other.shortest_turn {
+            if self.shortest_turn.as_ref().is_none_or(|current| shortest.chars < current.chars) {
+                self.shortest_turn = Some(shortest.clone());
+            }
+        }
+        for (language, count) in &other.code_blocks {
+            *self.code_blocks.entry(language.clone()).or_default() += count;
+        }
+        self.tables += other.tables;
+        self.links += other.links;
+        self.unanswered += other.unanswered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Document;
+
+    const INPUT: &str = "> @system: Be brief\n\n> @alice: Compare [these](https://a.example)\n\
+                         <!-- cmf: name=coder -->\n\n```rust\nfn main() {}\n```\n\n\
+                         | a | b |\n|---|---|\n| 1 | 2 |\n\n```tool_call name=run\n{}\n```\n\n\
+                         > @bob: And?\n\n> Still there?";
+
+    #[test]
+    fn test_counts() {
+        let stats = Stats::from_turns(&Document::parse(INPUT).turns);
+        assert_eq!(stats.turns, 4);
+        assert_eq!(stats.participants["@alice"], 1);
+        assert_eq!(stats.participants["@coder"], 1);
+        assert_eq!(stats.participants["user"], 1);
+        assert_eq!(stats.participants["system"], 1);
+        assert_eq!(stats.roles["user"].messages, 3);
+        assert_eq!(stats.roles["user"].words, 5);
+        assert_eq!(stats.code_blocks, BTreeMap::from([("rust".to_string(), 1)]));
+        assert_eq!((stats.tables, stats.links), (1, 1));
+        assert_eq!(stats.unanswered, 2);
+        assert_eq!(stats.longest_turn.as_ref().unwrap().turn, 2);
+        assert_eq!(stats.shortest_turn.as_ref().unwrap().turn, 3);
+    }
+
+    #[test]
+    fn test_merge() {
+        let doc = Document::parse(INPUT);
+        let mut total = Stats::from_turns(&doc.turns[..2]).in_file("a.cmf");
+        total.merge(&Stats::from_turns(&doc.turns[2..]).in_file("b.cmf"));
+
+        let whole = Stats::from_turns(&doc.turns);
+        assert_eq!(total.turns, whole.turns);
+        assert_eq!(total.roles, whole.roles);
+        assert_eq!(total.unanswered, whole.unanswered);
+        assert_eq!(total.shortest_turn.unwrap().file.as_deref(), Some("b.cmf"));
+        assert_eq!(estimate_tokens("12345"), 2);
+    }
+}
@@ -2,6 +2,8 @@
 
 use std::collections::VecDeque;
 
+use crate::terminal_renderer::options::RendererOptions;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FormattingState {
     Bold,
@@ -14,17 +16,21 @@ pub struct RenderContext {
     pub output: String,
     pub formatting_stack: VecDeque<FormattingState>,
     pub pending_newlines: usize,
-    #[allow(dead_code)]
     pub use_colors: bool,
+    /// Output width in columns, resolved from the options
+    pub width: usize,
+    pub options: RendererOptions,
 }
 
 impl RenderContext {
-    pub fn new(use_colors: bool) -> Self {
+    pub fn new(options: &RendererOptions) -> Self {
         Self {
             output: String::new(),
             formatting_stack: VecDeque::new(),
             pending_newlines: 0,
-            use_colors,
+            use_colors: options.use_colors(),
+            width: options.resolved_width(),
+            options: options.clone(),
         }
     }
 
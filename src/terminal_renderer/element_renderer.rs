@@ -1,12 +1,81 @@
 //! Trait for composable element renderers
 
+use pulldown_cmark::Alignment;
+
 use crate::terminal_renderer::context::RenderContext;
 
+/// Block-level markdown elements that can be handled by an `ElementRenderer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementKind {
+    CodeBlock,
+    Table,
+    BlockQuote,
+    Heading,
+    /// A list, rendered as a container: see `ElementRenderer::start_item`
+    List,
+}
+
+/// Metadata about the element being rendered, taken from the markdown tag
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementInfo {
+    pub kind: ElementKind,
+    /// Full info string of a fenced code block (e.g. `rust ignore`)
+    pub fence_info: Option<String>,
+    /// Column alignments of a table
+    pub alignments: Vec<Alignment>,
+    /// Level of a heading (1-6), or nesting depth of a list (1 at the top)
+    pub level: u32,
+    /// Section number of a heading (e.g. `1.2`) with numbered headings on
+    pub section: Option<String>,
+    /// First number of an ordered list; `None` for a bullet list
+    pub list_start: Option<u64>,
+}
+
+impl ElementInfo {
+    pub fn new(kind: ElementKind) -> Self {
+        Self {
+            kind,
+            fence_info: None,
+            alignments: Vec::new(),
+            level: 1,
+            section: None,
+            list_start: None,
+        }
+    }
+
+    /// Language of a fenced code block (first word of the info string)
+    pub fn language(&self) -> Option<&str> {
+        self.fence_info
+            .as_deref()
+            .and_then(|info| info.split_whitespace().next())
+    }
+}
+
 /// Trait for rendering specific markdown element types
 pub trait ElementRenderer {
-    fn start(&mut self, context: &mut RenderContext);
+    fn start(&mut self, info: &ElementInfo, context: &mut RenderContext);
     fn handle_text(&mut self, text: &str, context: &mut RenderContext);
+    /// Inline text with its ANSI styles; renderers that keep styles
+    /// override this, others get the unstyled text
+    fn handle_styled(&mut self, styled: &str, raw: &str, context: &mut RenderContext) {
+        let _ = styled;
+        self.handle_text(raw, context);
+    }
     fn handle_soft_break(&mut self, context: &mut RenderContext);
     fn handle_hard_break(&mut self, context: &mut RenderContext);
     fn end(&mut self, context: &mut RenderContext) -> Option<String>;
+
+    // Table structure events; only table renderers need to care about these
+    fn start_row(&mut self) {}
+    fn end_row(&mut self) {}
+    fn start_cell(&mut self) {}
+    fn end_cell(&mut self) {}
+
+    // List items; a list's content is rendered in place, so list renderers
+    // only write item markers here and get no text
+    fn start_item(&mut self, _context: &mut RenderContext) {}
+    fn end_item(&mut self, _context: &mut RenderContext) {}
 }
+
+/// Creates a fresh renderer for each element occurrence
+pub type RendererFactory = Box<dyn Fn() -> Box<dyn ElementRenderer>>;
@@ -2,10 +2,12 @@
 
 use colored::*;
 use std::collections::VecDeque;
+use unicode_width::UnicodeWidthChar;
 use crate::terminal_renderer::context::FormattingState;
+use crate::terminal_renderer::options::{Glyphs, Theme};
 
 /// Format text with applied formatting styles
-pub fn format_text(text: &str, formatting_stack: &VecDeque<FormattingState>, use_colors: bool) -> String {
+pub fn format_text(text: &str, formatting_stack: &VecDeque<FormattingState>, use_colors: bool, theme: Theme) -> String {
     if !use_colors {
         return text.to_string();
     }
@@ -14,16 +16,52 @@ pub fn format_text(text: &str, formatting_stack: &VecDeque<FormattingState>, use
 
     // Apply formatting in reverse order (innermost first)
     for state in formatting_stack.iter().rev() {
-        result = match state {
-            FormattingState::Bold => result.bold().to_string(),
-            FormattingState::Italic => result.italic().to_string(),
-            FormattingState::Link => result.blue().underline().to_string(),
+        result = match (state, theme) {
+            (FormattingState::Bold, _) => result.bold().to_string(),
+            (FormattingState::Italic, _) => result.italic().to_string(),
+            (FormattingState::Link, Theme::Default) => result.blue().underline().to_string(),
+            (FormattingState::Link, Theme::Monochrome) => result.underline().to_string(),
         };
     }
 
     result
 }
 
+/// Style a line of code block content
+pub fn format_code_line(line: &str, use_colors: bool, theme: Theme) -> String {
+    if !use_colors {
+        return line.to_string();
+    }
+
+    match theme {
+        Theme::Default => line.green().to_string(),
+        Theme::Monochrome => line.dimmed().to_string(),
+    }
+}
+
+/// Start an OSC 8 terminal hyperlink; text up to `osc8_close` is clickable
+pub fn osc8_open(url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\", url)
+}
+
+/// End an OSC 8 terminal hyperlink
+pub fn osc8_close() -> &'static str {
+    "\x1b]8;;\x1b\\"
+}
+
+/// Format the `@name:` label shown before a named assistant's message
+pub fn format_speaker(name: &str, use_colors: bool, theme: Theme) -> String {
+    let label = format!("@{}:", name);
+    if !use_colors {
+        return label;
+    }
+
+    match theme {
+        Theme::Default => label.magenta().bold().to_string(),
+        Theme::Monochrome => label.bold().to_string(),
+    }
+}
+
 /// Format inline code with reversed colors
 pub fn format_inline_code(code: &str, use_colors: bool) -> String {
     if !use_colors {
@@ -34,44 +72,150 @@ pub fn format_inline_code(code: &str, use_colors: bool) -> String {
     code.reversed().to_string()
 }
 
-/// Format heading text based on level
-pub fn format_heading(text: &str, level: u32, use_colors: bool) -> String {
-    match level {
-        1 => {
-            // H1: Bold with decorative lines top and bottom
-            let border = "─".repeat(text.len() + 4);
-            let bold_text = if use_colors {
-                text.bold().to_string()
-            } else {
-                text.to_string()
-            };
-            format!("{}\n {} \n{}", border, bold_text, border)
+/// Decoration used for headings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadingStyle {
+    /// H1 between horizontal rules, H2/H3 flanked by dashes
+    #[default]
+    Rules,
+    /// H1/H2 underlined with a rule as wide as the text
+    Underline,
+    /// H1/H2 drawn inside a box
+    Boxed,
+}
+
+/// Terminal display width of text, ignoring ANSI escape sequences
+pub fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            match chars.next() {
+                // OSC sequence (e.g. hyperlinks), terminated by ESC \\ or BEL
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                            break;
+                        }
+                    }
+                }
+                // CSI sequence, up to and including its final byte
+                _ => {
+                    for c in chars.by_ref() {
+                        if c.is_ascii_alphabetic() {
+                            break;
+                        }
+                    }
+                }
+            }
+        } else {
+            width += UnicodeWidthChar::width(c).unwrap_or(0);
         }
-        2 => {
-            // H2: Bold with decorative lines on sides
-            let bold_text = if use_colors {
-                text.bold().to_string()
-            } else {
-                text.to_string()
-            };
-            format!("─── {} ───", bold_text)
+    }
+    width
+}
+
+/// Decorate already-styled heading text based on level and style
+pub fn format_heading(text: &str, level: u32, style: HeadingStyle, glyphs: &Glyphs) -> String {
+    let width = display_width(text);
+    let rule = |n: usize| glyphs.horizontal.to_string().repeat(n);
+    match (style, level) {
+        (HeadingStyle::Rules, 1) => {
+            // H1: decorative lines top and bottom
+            let border = rule(width + 4);
+            format!("{}\n {} \n{}", border, text, border)
         }
-        3 => {
-            // H3: Bold with dashes on sides
-            let bold_text = if use_colors {
-                text.bold().to_string()
-            } else {
-                text.to_string()
-            };
-            format!("- {} -", bold_text)
+        // H2: decorative lines on sides
+        (HeadingStyle::Rules, 2) => format!("{} {} {}", rule(3), text, rule(3)),
+        // H3: dashes on sides
+        (HeadingStyle::Rules, 3) => format!("- {} -", text),
+        (HeadingStyle::Underline, 1) => {
+            format!("{}\n{}", text, glyphs.double_horizontal.to_string().repeat(width))
         }
-        _ => {
-            // H4+: Just bold
-            if use_colors {
-                text.bold().to_string()
-            } else {
-                text.to_string()
-            }
+        (HeadingStyle::Underline, 2) => format!("{}\n{}", text, rule(width)),
+        (HeadingStyle::Boxed, 1 | 2) => {
+            let [top_left, top_right, bottom_left, bottom_right] = glyphs.corners;
+            let bar = rule(width + 2);
+            format!(
+                "{}{}{}\n{} {} {}\n{}{}{}",
+                top_left, bar, top_right,
+                glyphs.vertical, text, glyphs.vertical,
+                bottom_left, bar, bottom_right
+            )
+        }
+        // Everything else: just the (bold) text
+        _ => text.to_string(),
+    }
+}
+
+/// Tracks heading counts per level to produce section numbers like `1.2`
+#[derive(Debug, Default)]
+pub struct SectionCounter {
+    counts: [usize; 6],
+}
+
+impl SectionCounter {
+    /// Advance the counter for a heading of `level` and return its number
+    pub fn next(&mut self, level: u32) -> String {
+        let idx = (level.clamp(1, 6) - 1) as usize;
+        self.counts[idx] += 1;
+        for count in &mut self.counts[idx + 1..] {
+            *count = 0;
         }
+        // Documents often start below H1, so skip unused leading levels
+        self.counts[..=idx]
+            .iter()
+            .skip_while(|&&count| count == 0)
+            .map(|count| count.to_string())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal_renderer::options::Charset;
+
+    #[test]
+    fn test_h1_border_uses_display_width() {
+        let heading = format_heading("日本語", 1, HeadingStyle::Rules, &Charset::Unicode.glyphs());
+        let border = "─".repeat(10);
+        assert_eq!(heading, format!("{}\n 日本語 \n{}", border, border));
+    }
+
+    #[test]
+    fn test_display_width_ignores_ansi() {
+        assert_eq!(display_width("\x1b[1mbold\x1b[0m"), 4);
+        let link = format!("{}docs{}", osc8_open("https://x.io"), osc8_close());
+        assert_eq!(display_width(&link), 4);
+    }
+
+    #[test]
+    fn test_underline_and_boxed() {
+        let glyphs = Charset::Unicode.glyphs();
+        assert_eq!(format_heading("Title", 1, HeadingStyle::Underline, &glyphs), "Title\n═════");
+        assert_eq!(format_heading("Hi", 2, HeadingStyle::Boxed, &glyphs), "┌────┐\n│ Hi │\n└────┘");
+        assert_eq!(format_heading("Sub", 3, HeadingStyle::Boxed, &glyphs), "Sub");
+    }
+
+    #[test]
+    fn test_ascii_headings() {
+        let glyphs = Charset::Ascii.glyphs();
+        assert_eq!(format_heading("Hi", 1, HeadingStyle::Rules, &glyphs), "------\n Hi \n------");
+        assert_eq!(format_heading("Hi", 2, HeadingStyle::Rules, &glyphs), "--- Hi ---");
+        assert_eq!(format_heading("Hi", 1, HeadingStyle::Underline, &glyphs), "Hi\n==");
+        assert_eq!(format_heading("Hi", 1, HeadingStyle::Boxed, &glyphs), "+----+\n| Hi |\n+----+");
+    }
+
+    #[test]
+    fn test_section_numbers() {
+        let mut counter = SectionCounter::default();
+        assert_eq!(counter.next(2), "1");
+        assert_eq!(counter.next(3), "1.1");
+        assert_eq!(counter.next(3), "1.2");
+        assert_eq!(counter.next(2), "2");
+        assert_eq!(counter.next(1), "1");
+        assert_eq!(counter.next(2), "1.1");
     }
 }
@@ -13,11 +13,14 @@
 pub mod context;
 pub mod element_renderer;
 pub mod formatters;
+pub mod options;
 pub mod renderers;
 pub mod renderer;
 
 // Re-export public API
 pub use context::{RenderContext, FormattingState};
-pub use element_renderer::ElementRenderer;
+pub use formatters::HeadingStyle;
+pub use options::{Charset, ColorMode, Glyphs, HyperlinkMode, RendererOptions, TableStyle, Theme};
+pub use element_renderer::{ElementInfo, ElementKind, ElementRenderer, RendererFactory};
 pub use renderer::MarkdownRenderer;
-pub use renderers::{CodeBlockRenderer, TableRenderer, BlockquoteRenderer, ListRenderer};
+pub use renderers::{CodeBlockRenderer, TableRenderer, BlockquoteRenderer, ListRenderer, HeadingRenderer};
@@ -0,0 +1,366 @@
+//! Renderer configuration
+
+use std::env;
+use std::str::FromStr;
+
+use terminal_size::{terminal_size, Width};
+
+use crate::terminal_renderer::formatters::HeadingStyle;
+
+/// Width used when no width is configured and stdout is not a terminal
+const DEFAULT_WIDTH: usize = 80;
+
+/// When to emit ANSI colour codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// Colour when stdout is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Colour palette for styled output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Theme {
+    #[default]
+    Default,
+    /// Only bold, italic, underline and reverse video; no hues
+    Monochrome,
+}
+
+/// How link destinations are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HyperlinkMode {
+    /// Show the link text only
+    #[default]
+    Off,
+    /// Show `text (url)`
+    Inline,
+    /// Emit OSC 8 terminal hyperlinks
+    Osc8,
+}
+
+/// Characters used to draw table borders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TableStyle {
+    #[default]
+    Unicode,
+    Ascii,
+}
+
+/// Character repertoire used for decoration (rules, borders, bullets, markers)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Charset {
+    #[default]
+    Unicode,
+    /// Plain ASCII for legacy terminals, Windows code pages, CI logs and serial consoles
+    Ascii,
+}
+
+/// Decoration characters for a charset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Glyphs {
+    pub horizontal: char,
+    pub double_horizontal: char,
+    pub vertical: char,
+    /// Top-left, top-right, bottom-left and bottom-right corners
+    pub corners: [char; 4],
+    pub bullet: &'static str,
+    pub quote: &'static str,
+    pub checked: &'static str,
+    pub unchecked: &'static str,
+    pub ellipsis: &'static str,
+}
+
+impl Charset {
+    /// Pick Unicode when the locale (`LC_ALL`, `LC_CTYPE` or `LANG`) is UTF-8,
+    /// ASCII otherwise
+    pub fn from_locale() -> Self {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|var| env::var(var).ok().filter(|value| !value.is_empty()));
+        match locale {
+            Some(locale) if locale.to_lowercase().replace('-', "").contains("utf8") => Charset::Unicode,
+            _ => Charset::Ascii,
+        }
+    }
+
+    pub fn glyphs(self) -> Glyphs {
+        match self {
+            Charset::Unicode => Glyphs {
+                horizontal: '─',
+                double_horizontal: '═',
+                vertical: '│',
+                corners: ['┌', '┐', '└', '┘'],
+                bullet: "•",
+                quote: "▌",
+                checked: "☑",
+                unchecked: "☐",
+                ellipsis: "…",
+            },
+            Charset::Ascii => Glyphs {
+                horizontal: '-',
+                double_horizontal: '=',
+                vertical: '|',
+                corners: ['+', '+', '+', '+'],
+                bullet: "*",
+                quote: ">",
+                checked: "[x]",
+                unchecked: "[ ]",
+                ellipsis: "...",
+            },
+        }
+    }
+
+    fn table_style(self) -> TableStyle {
+        match self {
+            Charset::Unicode => TableStyle::Unicode,
+            Charset::Ascii => TableStyle::Ascii,
+        }
+    }
+}
+
+/// Configuration for `MarkdownRenderer`
+///
+/// ```
+/// use cmf::terminal_renderer::{ColorMode, MarkdownRenderer, RendererOptions};
+///
+/// let options = RendererOptions::new().color(ColorMode::Never).width(60);
+/// let renderer = MarkdownRenderer::with_options(options);
+/// assert_eq!(renderer.render("**hi**"), "hi\n");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RendererOptions {
+    pub color: ColorMode,
+    /// Output width in columns; `None` uses the terminal width
+    pub width: Option<usize>,
+    pub theme: Theme,
+    /// Style code block contents (only when colours are enabled)
+    pub highlight_code: bool,
+    pub hyperlinks: HyperlinkMode,
+    pub charset: Charset,
+    pub table_style: TableStyle,
+    pub heading_style: HeadingStyle,
+    /// Prefix headings with section numbers like `1.2`
+    pub numbered_headings: bool,
+    pub bullet: String,
+    pub quote_glyph: String,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self {
+            color: ColorMode::Auto,
+            width: None,
+            theme: Theme::Default,
+            highlight_code: true,
+            hyperlinks: HyperlinkMode::Off,
+            charset: Charset::Unicode,
+            table_style: TableStyle::Unicode,
+            heading_style: HeadingStyle::Rules,
+            numbered_headings: false,
+            bullet: "•".to_string(),
+            quote_glyph: "▌".to_string(),
+        }
+    }
+}
+
+impl RendererOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn color(mut self, color: ColorMode) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    pub fn highlight_code(mut self, highlight: bool) -> Self {
+        self.highlight_code = highlight;
+        self
+    }
+
+    pub fn hyperlinks(mut self, mode: HyperlinkMode) -> Self {
+        self.hyperlinks = mode;
+        self
+    }
+
+    /// Switch all decoration to the given charset; table style, bullet and
+    /// quote glyph can still be overridden afterwards
+    pub fn charset(mut self, charset: Charset) -> Self {
+        let glyphs = charset.glyphs();
+        self.charset = charset;
+        self.table_style = charset.table_style();
+        self.bullet = glyphs.bullet.to_string();
+        self.quote_glyph = glyphs.quote.to_string();
+        self
+    }
+
+    pub fn glyphs(&self) -> Glyphs {
+        self.charset.glyphs()
+    }
+
+    pub fn table_style(mut self, style: TableStyle) -> Self {
+        self.table_style = style;
+        self
+    }
+
+    pub fn heading_style(mut self, style: HeadingStyle) -> Self {
+        self.heading_style = style;
+        self
+    }
+
+    pub fn numbered_headings(mut self, numbered: bool) -> Self {
+        self.numbered_headings = numbered;
+        self
+    }
+
+    pub fn bullet(mut self, glyph: &str) -> Self {
+        self.bullet = glyph.to_string();
+        self
+    }
+
+    pub fn quote_glyph(mut self, glyph: &str) -> Self {
+        self.quote_glyph = glyph.to_string();
+        self
+    }
+
+    /// Whether colour codes should be emitted
+    pub fn use_colors(&self) -> bool {
+        match self.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                env::var_os("NO_COLOR").is_none() && atty::is(atty::Stream::Stdout)
+            }
+        }
+    }
+
+    /// Configured width, falling back to the terminal width
+    pub fn resolved_width(&self) -> usize {
+        self.width.unwrap_or_else(|| match terminal_size() {
+            Some((Width(w), _)) if atty::is(atty::Stream::Stdout) => w as usize,
+            _ => DEFAULT_WIDTH,
+        })
+    }
+}
+
+/// Parse a lowercase option name into one of the given values
+fn parse_choice<T: Copy>(s: &str, choices: &[(&str, T)]) -> Result<T, String> {
+    choices
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        .map(|(_, value)| *value)
+        .ok_or_else(|| {
+            let names: Vec<&str> = choices.iter().map(|(name, _)| *name).collect();
+            format!("invalid value '{}' (expected one of: {})", s, names.join(", "))
+        })
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_choice(
+            s,
+            &[("auto", Self::Auto), ("always", Self::Always), ("never", Self::Never)],
+        )
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_choice(s, &[("default", Self::Default), ("mono", Self::Monochrome)])
+    }
+}
+
+impl FromStr for HyperlinkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_choice(s, &[("off", Self::Off), ("inline", Self::Inline), ("osc8", Self::Osc8)])
+    }
+}
+
+impl FromStr for TableStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_choice(s, &[("unicode", Self::Unicode), ("ascii", Self::Ascii)])
+    }
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    /// Also accepts `auto`, resolved from the locale at parse time
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Charset::from_locale());
+        }
+        parse_choice(s, &[("unicode", Self::Unicode), ("ascii", Self::Ascii)])
+    }
+}
+
+impl FromStr for HeadingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_choice(
+            s,
+            &[("rules", Self::Rules), ("underline", Self::Underline), ("boxed", Self::Boxed)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        let options = RendererOptions::new()
+            .color(ColorMode::Always)
+            .width(40)
+            .bullet("-")
+            .table_style(TableStyle::Ascii);
+        assert!(options.use_colors());
+        assert_eq!(options.resolved_width(), 40);
+        assert_eq!(options.bullet, "-");
+        assert_eq!(options.table_style, TableStyle::Ascii);
+        assert!(!options.clone().color(ColorMode::Never).use_colors());
+    }
+
+    #[test]
+    fn test_ascii_charset() {
+        let options = RendererOptions::new().charset(Charset::Ascii);
+        assert_eq!(options.table_style, TableStyle::Ascii);
+        assert_eq!(options.bullet, "*");
+        assert_eq!(options.quote_glyph, ">");
+        assert_eq!(options.glyphs().checked, "[x]");
+
+        let options = RendererOptions::new().charset(Charset::Ascii).bullet("-");
+        assert_eq!(options.bullet, "-");
+    }
+
+    #[test]
+    fn test_parse_choices() {
+        assert_eq!("never".parse(), Ok(ColorMode::Never));
+        assert_eq!("OSC8".parse(), Ok(HyperlinkMode::Osc8));
+        assert_eq!("boxed".parse(), Ok(HeadingStyle::Boxed));
+        assert!("fancy".parse::<TableStyle>().is_err());
+    }
+}
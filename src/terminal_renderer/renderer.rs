@@ -1,22 +1,85 @@
 //! Main markdown renderer orchestrating all element renderers
 
-use atty;
-use pulldown_cmark::{Event, Parser, Tag, Options};
+use std::collections::HashMap;
+
+use pulldown_cmark::{CodeBlockKind, Event, LinkType, Parser, Tag, Options};
 
 use crate::terminal_renderer::context::{RenderContext, FormattingState};
-use crate::terminal_renderer::element_renderer::ElementRenderer;
-use crate::terminal_renderer::formatters::{format_text, format_inline_code, format_heading};
-use crate::terminal_renderer::renderers::{CodeBlockRenderer, TableRenderer, BlockquoteRenderer, ListRenderer};
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementKind, ElementRenderer, RendererFactory};
+use crate::metadata::speaker_at;
+use crate::terminal_renderer::formatters::{
+    format_inline_code, format_speaker, format_text, osc8_close, osc8_open, SectionCounter,
+};
+use crate::terminal_renderer::options::{HyperlinkMode, RendererOptions};
+use crate::terminal_renderer::renderers::{CodeBlockRenderer, TableRenderer, BlockquoteRenderer, ListRenderer, HeadingRenderer};
+use crate::terminal_renderer::renderers::table::draw_table;
 
 pub struct MarkdownRenderer {
-    use_colors: bool,
+    options: RendererOptions,
+    element_renderers: HashMap<ElementKind, RendererFactory>,
+    code_language_renderers: HashMap<String, RendererFactory>,
+}
+
+impl Default for MarkdownRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MarkdownRenderer {
     pub fn new() -> Self {
-        Self {
-            use_colors: atty::is(atty::Stream::Stdout),
-        }
+        Self::with_options(RendererOptions::default())
+    }
+
+    pub fn with_options(options: RendererOptions) -> Self {
+        let mut renderer = Self {
+            options,
+            element_renderers: HashMap::new(),
+            code_language_renderers: HashMap::new(),
+        };
+        renderer.register_renderer(ElementKind::CodeBlock, || Box::new(CodeBlockRenderer::new()));
+        renderer.register_renderer(ElementKind::Table, || Box::new(TableRenderer::new()));
+        renderer.register_renderer(ElementKind::BlockQuote, || Box::new(BlockquoteRenderer::new()));
+        renderer.register_renderer(ElementKind::Heading, || Box::new(HeadingRenderer::new()));
+        renderer.register_renderer(ElementKind::List, || Box::new(ListRenderer::new(false, 1)));
+        renderer
+    }
+
+    pub fn options(&self) -> &RendererOptions {
+        &self.options
+    }
+
+    /// Replace the renderer used for an element kind
+    pub fn register_renderer<F>(&mut self, kind: ElementKind, factory: F)
+    where
+        F: Fn() -> Box<dyn ElementRenderer> + 'static,
+    {
+        self.element_renderers.insert(kind, Box::new(factory));
+    }
+
+    /// Use a custom renderer for fenced code blocks of the given language
+    /// (e.g. `mermaid`), taking precedence over the code block renderer
+    pub fn register_code_language<F>(&mut self, language: &str, factory: F)
+    where
+        F: Fn() -> Box<dyn ElementRenderer> + 'static,
+    {
+        self.code_language_renderers.insert(language.to_string(), Box::new(factory));
+    }
+
+    fn renderer_for(&self, info: &ElementInfo) -> Option<Box<dyn ElementRenderer>> {
+        let by_language = info
+            .language()
+            .filter(|_| info.kind == ElementKind::CodeBlock)
+            .and_then(|lang| self.code_language_renderers.get(lang));
+        by_language
+            .or_else(|| self.element_renderers.get(&info.kind))
+            .map(|factory| factory())
+    }
+
+    fn start_element(&self, info: ElementInfo, context: &mut RenderContext) -> Option<Box<dyn ElementRenderer>> {
+        let mut renderer = self.renderer_for(&info)?;
+        renderer.start(&info, context);
+        Some(renderer)
     }
 
     pub fn render(&self, markdown: &str) -> String {
@@ -26,19 +89,22 @@ impl MarkdownRenderer {
         }
 
         let parser = Parser::new_ext(markdown, Options::all());
-        let mut context = RenderContext::new(self.use_colors);
-
-        let mut code_renderer: Option<CodeBlockRenderer> = None;
-        let mut table_renderer: Option<TableRenderer> = None;
-        let mut blockquote_renderer: Option<BlockquoteRenderer> = None;
-        let mut list_depth = 0;
-        let mut list_renderer: Option<ListRenderer> = None;
+        let mut context = RenderContext::new(&self.options);
+        let use_colors = context.use_colors;
+        let theme = self.options.theme;
+        let glyphs = self.options.glyphs();
+
+        let mut code_renderer: Option<Box<dyn ElementRenderer>> = None;
+        let mut table_renderer: Option<Box<dyn ElementRenderer>> = None;
+        let mut blockquote_renderer: Option<Box<dyn ElementRenderer>> = None;
+        let mut heading_renderer: Option<Box<dyn ElementRenderer>> = None;
+        // One entry per open list, innermost last
+        let mut list_renderers: Vec<Option<Box<dyn ElementRenderer>>> = Vec::new();
         let mut in_list_item = false;
-        let mut in_heading = false;
-        let mut heading_level = 0u32;
-        let mut heading_buffer = String::new();
+        let mut sections = SectionCounter::default();
+        let mut link_urls: Vec<(LinkType, String)> = Vec::new();
 
-        for event in parser {
+        for (event, range) in parser.into_offset_iter() {
             match event {
                 Event::Start(tag) => {
                     match tag {
@@ -48,8 +114,8 @@ impl MarkdownRenderer {
                         Tag::Heading(level, ..) => {
                             context.ensure_newline();
                             context.pending_newlines = 0;
-                            in_heading = true;
-                            heading_level = match level {
+                            let mut info = ElementInfo::new(ElementKind::Heading);
+                            info.level = match level {
                                 pulldown_cmark::HeadingLevel::H1 => 1,
                                 pulldown_cmark::HeadingLevel::H2 => 2,
                                 pulldown_cmark::HeadingLevel::H3 => 3,
@@ -57,34 +123,42 @@ impl MarkdownRenderer {
                                 pulldown_cmark::HeadingLevel::H5 => 5,
                                 pulldown_cmark::HeadingLevel::H6 => 6,
                             };
-                            heading_buffer.clear();
+                            if self.options.numbered_headings {
+                                info.section = Some(sections.next(info.level));
+                            }
+                            heading_renderer = self.start_element(info, &mut context);
+                            // Headings are bold; inline styles inside them are kept
+                            context.formatting_stack.push_back(FormattingState::Bold);
                         }
-                        Tag::List(ordered) => {
-                            list_depth += 1;
-                            list_renderer = Some(ListRenderer::new(ordered.is_some(), list_depth));
+                        Tag::List(start) => {
+                            let mut info = ElementInfo::new(ElementKind::List);
+                            info.level = list_renderers.len() as u32 + 1;
+                            info.list_start = start;
+                            list_renderers.push(self.start_element(info, &mut context));
                         }
                         Tag::Item => {
                             in_list_item = true;
-                            if let Some(ref mut renderer) = list_renderer {
-                                renderer.start_item(&mut context.output, list_depth);
+                            if let Some(Some(renderer)) = list_renderers.last_mut() {
+                                renderer.start_item(&mut context);
                             }
                         }
-                        Tag::CodeBlock(_) => {
+                        Tag::CodeBlock(kind) => {
                             context.ensure_blank_line();
-                            code_renderer = Some(CodeBlockRenderer::new());
-                            if let Some(ref mut renderer) = code_renderer {
-                                renderer.start(&mut context);
+                            let mut info = ElementInfo::new(ElementKind::CodeBlock);
+                            if let CodeBlockKind::Fenced(fence) = kind {
+                                info.fence_info = Some(fence.to_string());
                             }
+                            code_renderer = self.start_element(info, &mut context);
                         }
                         Tag::BlockQuote => {
-                            blockquote_renderer = Some(BlockquoteRenderer::new());
+                            let info = ElementInfo::new(ElementKind::BlockQuote);
+                            blockquote_renderer = self.start_element(info, &mut context);
                             context.ensure_newline();
                         }
-                        Tag::Table(_) => {
-                            table_renderer = Some(TableRenderer::new());
-                            if let Some(ref mut renderer) = table_renderer {
-                                renderer.start(&mut context);
-                            }
+                        Tag::Table(alignments) => {
+                            let mut info = ElementInfo::new(ElementKind::Table);
+                            info.alignments = alignments;
+                            table_renderer = self.start_element(info, &mut context);
                             context.ensure_newline();
                         }
                         Tag::TableHead | Tag::TableRow => {
@@ -103,8 +177,14 @@ impl MarkdownRenderer {
                         Tag::Strong => {
                             context.formatting_stack.push_back(FormattingState::Bold);
                         }
-                        Tag::Link(..) => {
+                        Tag::Link(link_type, url, _) => {
                             context.formatting_stack.push_back(FormattingState::Link);
+                            if self.options.hyperlinks == HyperlinkMode::Osc8 {
+                                let element = heading_renderer.as_mut().or(code_renderer.as_mut()).or(table_renderer.as_mut()).or(blockquote_renderer.as_mut());
+                                let open = osc8_open(&url);
+                                push_inline(&open, &open, element, &mut context);
+                            }
+                            link_urls.push((link_type, url.to_string()));
                         }
                         _ => {}
                     }
@@ -116,23 +196,32 @@ impl MarkdownRenderer {
                             context.pending_newlines = 1;
                         }
                         Tag::Heading(..) => {
-                            let formatted = format_heading(&heading_buffer, heading_level, self.use_colors);
-                            context.push_str(&formatted);
+                            context.formatting_stack.pop_back();
+                            if let Some(mut renderer) = heading_renderer.take() {
+                                if let Some(output) = renderer.end(&mut context) {
+                                    context.push_str(&output);
+                                }
+                            }
                             context.push_newline();
                             context.push_newline();
                             context.pending_newlines = 2;
-                            in_heading = false;
                         }
                         Tag::List(_) => {
-                            list_depth -= 1;
-                            if list_depth == 0 {
+                            if let Some(Some(mut renderer)) = list_renderers.pop() {
+                                if let Some(output) = renderer.end(&mut context) {
+                                    context.push_str(&output);
+                                }
+                            }
+                            if list_renderers.is_empty() {
                                 context.push_newline();
                                 context.pending_newlines = 1;
-                                list_renderer = None;
                             }
                         }
                         Tag::Item => {
                             in_list_item = false;
+                            if let Some(Some(renderer)) = list_renderers.last_mut() {
+                                renderer.end_item(&mut context);
+                            }
                             context.push_newline();
                             context.pending_newlines = 1;
                         }
@@ -181,39 +270,49 @@ impl MarkdownRenderer {
                         }
                         Tag::Link(..) => {
                             context.formatting_stack.pop_back();
+                            let Some((link_type, url)) = link_urls.pop() else {
+                                continue;
+                            };
+                            let element = heading_renderer.as_mut().or(code_renderer.as_mut()).or(table_renderer.as_mut()).or(blockquote_renderer.as_mut());
+                            match self.options.hyperlinks {
+                                HyperlinkMode::Off => {}
+                                HyperlinkMode::Inline => {
+                                    // Autolinks already show their destination
+                                    if !matches!(link_type, LinkType::Autolink | LinkType::Email) {
+                                        let suffix = format!(" ({})", url);
+                                        push_inline(&suffix, &suffix, element, &mut context);
+                                    }
+                                }
+                                HyperlinkMode::Osc8 => {
+                                    push_inline(osc8_close(), osc8_close(), element, &mut context);
+                                }
+                            }
                         }
                         _ => {}
                     }
                 }
                 Event::Text(text) => {
-                    if in_heading {
-                        heading_buffer.push_str(&text);
-                    } else if let Some(ref mut renderer) = code_renderer {
-                        renderer.handle_text(&text, &mut context);
-                    } else if let Some(ref mut renderer) = table_renderer {
-                        renderer.handle_text(&text, &mut context);
-                    } else if let Some(ref mut renderer) = blockquote_renderer {
-                        renderer.handle_text(&text, &mut context);
-                    } else {
-                        let rendered = format_text(&text, &context.formatting_stack, self.use_colors);
-                        context.push_str(&rendered);
-                    }
+                    let rendered = format_text(&text, &context.formatting_stack, use_colors, theme);
+                    let element = heading_renderer.as_mut().or(code_renderer.as_mut()).or(table_renderer.as_mut()).or(blockquote_renderer.as_mut());
+                    push_inline(&rendered, &text, element, &mut context);
                 }
                 Event::SoftBreak => {
-                    if let Some(ref mut renderer) = code_renderer {
+                    if let Some(ref mut renderer) = heading_renderer {
+                        renderer.handle_soft_break(&mut context);
+                    } else if let Some(ref mut renderer) = code_renderer {
                         renderer.handle_soft_break(&mut context);
                     } else if let Some(ref mut renderer) = table_renderer {
                         renderer.handle_soft_break(&mut context);
                     } else if let Some(ref mut renderer) = blockquote_renderer {
                         renderer.handle_soft_break(&mut context);
-                    } else if in_list_item {
-                        context.push_str(" ");
                     } else {
                         context.push_str(" ");
                     }
                 }
                 Event::HardBreak => {
-                    if let Some(ref mut renderer) = code_renderer {
+                    if let Some(ref mut renderer) = heading_renderer {
+                        renderer.handle_hard_break(&mut context);
+                    } else if let Some(ref mut renderer) = code_renderer {
                         renderer.handle_hard_break(&mut context);
                     } else if let Some(ref mut renderer) = table_renderer {
                         renderer.handle_hard_break(&mut context);
@@ -221,23 +320,33 @@ impl MarkdownRenderer {
                         renderer.handle_hard_break(&mut context);
                     } else {
                         context.push_newline();
-                        if in_list_item && list_depth > 0 {
-                            for _ in 0..(list_depth - 1) {
-                                context.push_str("  ");
-                            }
-                            context.push_str("  ");
+                        if in_list_item && !list_renderers.is_empty() {
+                            context.push_str(&"  ".repeat(list_renderers.len()));
                         }
                     }
                 }
-                Event::Html(_html) => {
-                    // Skip HTML tags
+                Event::Html(_) => {
+                    // Skip HTML tags, but label named assistants
+                    if let Some(name) = speaker_at(markdown, range) {
+                        context.ensure_blank_line();
+                        context.push_str(&format_speaker(&name, use_colors, context.options.theme));
+                        context.push_newline();
+                    }
                 }
                 Event::Code(code) => {
-                    let rendered = format_inline_code(&code, self.use_colors);
-                    context.push_str(&rendered);
+                    let rendered = format_inline_code(&code, use_colors);
+                    let element = heading_renderer.as_mut().or(code_renderer.as_mut()).or(table_renderer.as_mut()).or(blockquote_renderer.as_mut());
+                    push_inline(&rendered, &code, element, &mut context);
+                }
+                Event::Rule => {
+                    context.ensure_blank_line();
+                    context.push_str(&glyphs.horizontal.to_string().repeat(context.width));
+                    context.push_newline();
+                    context.pending_newlines = 1;
                 }
                 Event::TaskListMarker(checked) => {
-                    context.push_str(if checked { "☑ " } else { "☐ " });
+                    context.push_str(if checked { glyphs.checked } else { glyphs.unchecked });
+                    context.push_str(" ");
                 }
                 _ => {}
             }
@@ -247,85 +356,89 @@ impl MarkdownRenderer {
     }
 
     /// Public API for rendering tables (used in tests)
-    #[allow(dead_code)]
     pub fn render_table(&self, rows: &[Vec<String>]) -> String {
-        if rows.is_empty() {
-            return String::new();
-        }
+        draw_table(rows, &[], self.options.table_style)
+    }
 
-        let num_cols = rows.iter().map(|r| r.len()).max().unwrap_or(0);
-        let mut col_widths = vec![0; num_cols];
+    fn has_markdown_syntax(&self, text: &str) -> bool {
+        // Quick heuristic: check for common markdown patterns
+        text.contains("**") || text.contains("*") || text.contains("`") ||
+        text.contains("#") || text.contains("[") || text.contains("- ") ||
+        text.contains("1. ") || text.contains("\n")
+    }
+}
+
+/// Send inline text to the innermost active element renderer, or straight
+/// to the output
+fn push_inline(styled: &str, raw: &str, element: Option<&mut Box<dyn ElementRenderer>>, context: &mut RenderContext) {
+    match element {
+        Some(renderer) => renderer.handle_styled(styled, raw, context),
+        None => context.push_str(styled),
+    }
+}
 
-        for row in rows {
-            for (i, cell) in row.iter().enumerate() {
-                col_widths[i] = col_widths[i].max(cell.len());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal_renderer::options::{Charset, ColorMode, TableStyle};
+    use crate::terminal_renderer::HeadingStyle;
+
+    fn renderer_no_colors() -> MarkdownRenderer {
+        MarkdownRenderer::with_options(RendererOptions::new().color(ColorMode::Never))
+    }
+
+    struct CsvTableRenderer {
+        rows: Vec<Vec<String>>,
+    }
+
+    impl ElementRenderer for CsvTableRenderer {
+        fn start(&mut self, _: &ElementInfo, _: &mut RenderContext) {
+            self.rows.clear();
+        }
+
+        fn handle_text(&mut self, text: &str, _: &mut RenderContext) {
+            if let Some(cell) = self.rows.last_mut().and_then(|row| row.last_mut()) {
+                cell.push_str(text);
             }
         }
 
-        let mut output = String::new();
+        fn handle_soft_break(&mut self, _: &mut RenderContext) {}
 
-        // Top border
-        output.push('┌');
-        for (i, width) in col_widths.iter().enumerate() {
-            output.push_str(&"─".repeat(width + 2));
-            if i < col_widths.len() - 1 {
-                output.push('┬');
-            }
+        fn handle_hard_break(&mut self, _: &mut RenderContext) {}
+
+        fn end(&mut self, _: &mut RenderContext) -> Option<String> {
+            let lines: Vec<String> = self.rows.iter().map(|row| row.join(",")).collect();
+            Some(lines.join("\n"))
         }
-        output.push_str("┐\n");
-
-        // Rows
-        for (row_idx, row) in rows.iter().enumerate() {
-            output.push('│');
-            for (col_idx, cell) in row.iter().enumerate() {
-                output.push(' ');
-                output.push_str(&format!("{:<width$}", cell, width = col_widths[col_idx]));
-                output.push(' ');
-                output.push('│');
-            }
-            output.push('\n');
-
-            // Add separator line between rows (or after header)
-            if row_idx < rows.len() - 1 {
-                output.push('├');
-                for (i, width) in col_widths.iter().enumerate() {
-                    output.push_str(&"─".repeat(width + 2));
-                    if i < col_widths.len() - 1 {
-                        output.push('┼');
-                    }
-                }
-                output.push_str("┤\n");
-            }
+
+        fn start_row(&mut self) {
+            self.rows.push(Vec::new());
         }
 
-        // Bottom border
-        output.push('└');
-        for (i, width) in col_widths.iter().enumerate() {
-            output.push_str(&"─".repeat(width + 2));
-            if i < col_widths.len() - 1 {
-                output.push('┴');
+        fn start_cell(&mut self) {
+            if let Some(row) = self.rows.last_mut() {
+                row.push(String::new());
             }
         }
-        output.push_str("┘");
-
-        output
     }
 
-    fn has_markdown_syntax(&self, text: &str) -> bool {
-        // Quick heuristic: check for common markdown patterns
-        text.contains("**") || text.contains("*") || text.contains("`") ||
-        text.contains("#") || text.contains("[") || text.contains("- ") ||
-        text.contains("1. ") || text.contains("\n")
+    struct FenceInfoRenderer {
+        fence_info: String,
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    impl ElementRenderer for FenceInfoRenderer {
+        fn start(&mut self, info: &ElementInfo, _: &mut RenderContext) {
+            self.fence_info = info.fence_info.clone().unwrap_or_default();
+        }
 
-    fn renderer_no_colors() -> MarkdownRenderer {
-        MarkdownRenderer {
-            use_colors: false,
+        fn handle_text(&mut self, _: &str, _: &mut RenderContext) {}
+
+        fn handle_soft_break(&mut self, _: &mut RenderContext) {}
+
+        fn handle_hard_break(&mut self, _: &mut RenderContext) {}
+
+        fn end(&mut self, _: &mut RenderContext) -> Option<String> {
+            Some(format!("[diagram: {}]", self.fence_info))
         }
     }
 
@@ -440,4 +553,208 @@ mod tests {
         assert!(result.contains("Alice"));
         assert!(result.contains("Bob"));
     }
+
+    #[test]
+    fn test_heading_keeps_inline_code_and_links() {
+        let renderer = renderer_no_colors();
+        let result = renderer.render("# Use `cargo` with [docs](https://x.io)");
+        let border = "─".repeat("Use cargo with docs".len() + 4);
+        assert_eq!(result, format!("{}\n Use cargo with docs \n{}\n", border, border));
+    }
+
+    #[test]
+    fn test_heading_styles_and_numbers() {
+        let options = RendererOptions::new()
+            .color(ColorMode::Never)
+            .heading_style(HeadingStyle::Underline)
+            .numbered_headings(true);
+        let renderer = MarkdownRenderer::with_options(options);
+        let result = renderer.render("# Intro\n\n## Setup\n\n## Usage");
+        assert!(result.contains("1 Intro\n═══════"));
+        assert!(result.contains("1.1 Setup\n─────────"));
+        assert!(result.contains("1.2 Usage"));
+    }
+
+    #[test]
+    fn test_table_renderer_built_by_hand() {
+        let mut table = TableRenderer::new();
+        for row in [["Name", "Age"], ["Alice", "30"]] {
+            table.start_row();
+            for cell in row {
+                table.start_cell();
+                table.handle_text(cell, &mut RenderContext::new(&RendererOptions::new()));
+                table.end_cell();
+            }
+            table.end_row();
+        }
+        let result = table.render_table();
+        assert!(result.contains("│ Alice │ 30  │"));
+    }
+
+    #[test]
+    fn test_custom_table_renderer() {
+        let mut renderer = renderer_no_colors();
+        renderer.register_renderer(ElementKind::Table, || Box::new(CsvTableRenderer { rows: Vec::new() }));
+        let result = renderer.render("| a | b |\n|---|---|\n| 1 | 2 |");
+        assert!(result.contains("a,b\n1,2"));
+        assert!(!result.contains("┌"));
+    }
+
+    /// Markdown-style `#` headings and `-`/`N)` list markers
+    struct HashHeadingRenderer {
+        level: u32,
+        text: String,
+    }
+
+    impl ElementRenderer for HashHeadingRenderer {
+        fn start(&mut self, info: &ElementInfo, _: &mut RenderContext) {
+            self.level = info.level;
+        }
+
+        fn handle_text(&mut self, text: &str, _: &mut RenderContext) {
+            self.text.push_str(text);
+        }
+
+        fn handle_soft_break(&mut self, _: &mut RenderContext) {}
+
+        fn handle_hard_break(&mut self, _: &mut RenderContext) {}
+
+        fn end(&mut self, _: &mut RenderContext) -> Option<String> {
+            Some(format!("{} {}", "#".repeat(self.level as usize), self.text))
+        }
+    }
+
+    struct ParenListRenderer {
+        number: Option<u64>,
+    }
+
+    impl ElementRenderer for ParenListRenderer {
+        fn start(&mut self, info: &ElementInfo, _: &mut RenderContext) {
+            self.number = info.list_start;
+        }
+
+        fn handle_text(&mut self, _: &str, _: &mut RenderContext) {}
+
+        fn handle_soft_break(&mut self, _: &mut RenderContext) {}
+
+        fn handle_hard_break(&mut self, _: &mut RenderContext) {}
+
+        fn end(&mut self, _: &mut RenderContext) -> Option<String> {
+            None
+        }
+
+        fn start_item(&mut self, context: &mut RenderContext) {
+            match self.number.as_mut() {
+                Some(n) => {
+                    context.output.push_str(&format!("{}) ", n));
+                    *n += 1;
+                }
+                None => context.output.push_str("- "),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_heading_and_list_renderers() {
+        let mut renderer = renderer_no_colors();
+        renderer.register_renderer(ElementKind::Heading, || {
+            Box::new(HashHeadingRenderer { level: 0, text: String::new() })
+        });
+        renderer.register_renderer(ElementKind::List, || Box::new(ParenListRenderer { number: None }));
+        let result = renderer.render("## Steps with `code`\n\n3. one\n4. two\n\n- a\n- b");
+        assert!(result.starts_with("## Steps with code\n"));
+        assert!(result.contains("3) one\n4) two"));
+        assert!(result.contains("- a\n- b"));
+    }
+
+    #[test]
+    fn test_nested_lists_keep_numbering() {
+        let renderer = renderer_no_colors();
+        let result = renderer.render("1. one\n   - inner\n2. two");
+        assert!(result.contains("1. one"));
+        assert!(result.contains("  • inner"));
+        assert!(result.contains("2. two"));
+    }
+
+    #[test]
+    fn test_custom_code_language_renderer() {
+        let mut renderer = renderer_no_colors();
+        renderer.register_code_language("mermaid", || {
+            Box::new(FenceInfoRenderer { fence_info: String::new() })
+        });
+        let result = renderer.render("```mermaid theme=dark\ngraph TD\n```\n\n```rust\nfn main() {}\n```");
+        assert!(result.contains("[diagram: mermaid theme=dark]"));
+        assert!(!result.contains("graph TD"));
+        assert!(result.contains("    fn main() {}"));
+    }
+
+    #[test]
+    fn test_table_alignment() {
+        let renderer = renderer_no_colors();
+        let result = renderer.render("| Name | Qty |\n|:-----|----:|\n| a | 1 |");
+        assert!(result.contains("│ a    │   1 │"));
+    }
+
+    #[test]
+    fn test_options_glyphs_and_table_style() {
+        let options = RendererOptions::new()
+            .color(ColorMode::Never)
+            .bullet("-")
+            .quote_glyph("|")
+            .table_style(TableStyle::Ascii);
+        let renderer = MarkdownRenderer::with_options(options);
+        let result = renderer.render("- item\n\n> quoted\n\n| a | b |\n|---|---|\n| 1 | 2 |");
+        assert!(result.contains("- item"));
+        assert!(result.contains("| quoted"));
+        assert!(result.contains("+---+---+\n| a | b |"));
+        assert!(!result.contains("┌"));
+    }
+
+    #[test]
+    fn test_inline_hyperlinks() {
+        let options = RendererOptions::new()
+            .color(ColorMode::Never)
+            .hyperlinks(HyperlinkMode::Inline);
+        let renderer = MarkdownRenderer::with_options(options);
+        let result = renderer.render("[docs](https://x.io) and <https://y.io>");
+        assert_eq!(result, "docs (https://x.io) and https://y.io\n");
+    }
+
+    #[test]
+    fn test_rule_uses_width() {
+        let options = RendererOptions::new().color(ColorMode::Never).width(10);
+        let renderer = MarkdownRenderer::with_options(options);
+        let result = renderer.render("above\n\n---\n\nbelow");
+        assert!(result.contains(&format!("\n{}\n", "─".repeat(10))));
+    }
+
+    #[test]
+    fn test_inline_code_in_table_cell() {
+        let renderer = renderer_no_colors();
+        let result = renderer.render("| cmd |\n|---|\n| `ls` |");
+        assert!(result.contains("│ ls  │"));
+    }
+
+    #[test]
+    fn test_ascii_charset_everywhere() {
+        let options = RendererOptions::new().color(ColorMode::Never).charset(Charset::Ascii).width(5);
+        let renderer = MarkdownRenderer::with_options(options);
+        let markdown = "# Title\n\n- [x] done\n- [ ] todo\n- plain\n\n> quote\n\n---\n\n| a |\n|---|\n| 1 |";
+        let result = renderer.render(markdown);
+        assert!(result.is_ascii(), "non-ASCII output: {}", result);
+        assert!(result.contains("* [x] done"));
+        assert!(result.contains("* [ ] todo"));
+        assert!(result.contains("> quote"));
+        assert!(result.contains("-----\n"));
+        assert!(result.contains("+---+"));
+    }
+
+    #[test]
+    fn test_speaker_labels() {
+        let renderer = renderer_no_colors();
+        let result = renderer.render("<!-- cmf: name=coder id=7 -->\nDone.\n<!-- cmf -->\nAnonymous.");
+        assert!(result.starts_with("@coder:\n"));
+        assert!(!result.contains("id=7"));
+        assert_eq!(result.matches('@').count(), 1);
+    }
 }
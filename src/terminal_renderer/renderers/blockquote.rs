@@ -1,7 +1,7 @@
 //! Blockquote renderer with vertical bar prefix
 
 use crate::terminal_renderer::context::RenderContext;
-use crate::terminal_renderer::element_renderer::ElementRenderer;
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementRenderer};
 
 /// Renders blockquotes with vertical bar prefix
 pub struct BlockquoteRenderer {
@@ -9,6 +9,12 @@ pub struct BlockquoteRenderer {
     current_line: String,
 }
 
+impl Default for BlockquoteRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BlockquoteRenderer {
     pub fn new() -> Self {
         Self {
@@ -17,16 +23,16 @@ impl BlockquoteRenderer {
         }
     }
 
-    fn add_prefix_to_lines(text: &str) -> String {
+    fn add_prefix_to_lines(text: &str, glyph: &str) -> String {
         text.lines()
-            .map(|line| format!("▌ {}", line))
+            .map(|line| format!("{} {}", glyph, line))
             .collect::<Vec<_>>()
             .join("\n")
     }
 }
 
 impl ElementRenderer for BlockquoteRenderer {
-    fn start(&mut self, _: &mut RenderContext) {
+    fn start(&mut self, _: &ElementInfo, _: &mut RenderContext) {
         self.lines.clear();
         self.current_line.clear();
     }
@@ -45,12 +51,12 @@ impl ElementRenderer for BlockquoteRenderer {
         self.current_line.clear();
     }
 
-    fn end(&mut self, _: &mut RenderContext) -> Option<String> {
+    fn end(&mut self, context: &mut RenderContext) -> Option<String> {
         if !self.current_line.is_empty() {
             self.lines.push(self.current_line.clone());
         }
 
         let full_text = self.lines.join("\n");
-        Some(Self::add_prefix_to_lines(&full_text))
+        Some(Self::add_prefix_to_lines(&full_text, &context.options.quote_glyph))
     }
 }
@@ -1,13 +1,20 @@
 //! Code block renderer
 
 use crate::terminal_renderer::context::RenderContext;
-use crate::terminal_renderer::element_renderer::ElementRenderer;
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementRenderer};
+use crate::terminal_renderer::formatters::format_code_line;
 
 /// Renders code blocks with 4-space indentation
 pub struct CodeBlockRenderer {
     buffer: String,
 }
 
+impl Default for CodeBlockRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CodeBlockRenderer {
     pub fn new() -> Self {
         Self {
@@ -15,7 +22,8 @@ impl CodeBlockRenderer {
         }
     }
 
-    fn render_code_block(&self, code: &str) -> String {
+    fn render_code_block(&self, code: &str, context: &RenderContext) -> String {
+        let highlight = context.use_colors && context.options.highlight_code;
         let lines: Vec<&str> = code.lines().collect();
         if lines.is_empty() {
             return String::new();
@@ -26,7 +34,7 @@ impl CodeBlockRenderer {
         // Indent each line by 4 spaces
         for line in lines {
             output.push_str("    ");
-            output.push_str(line);
+            output.push_str(&format_code_line(line, highlight, context.options.theme));
             output.push('\n');
         }
 
@@ -40,7 +48,7 @@ impl CodeBlockRenderer {
 }
 
 impl ElementRenderer for CodeBlockRenderer {
-    fn start(&mut self, _: &mut RenderContext) {
+    fn start(&mut self, _: &ElementInfo, _: &mut RenderContext) {
         self.buffer.clear();
     }
 
@@ -56,7 +64,7 @@ impl ElementRenderer for CodeBlockRenderer {
         self.buffer.push('\n');
     }
 
-    fn end(&mut self, _context: &mut RenderContext) -> Option<String> {
-        Some(self.render_code_block(&self.buffer))
+    fn end(&mut self, context: &mut RenderContext) -> Option<String> {
+        Some(self.render_code_block(&self.buffer, context))
     }
 }
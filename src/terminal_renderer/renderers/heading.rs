@@ -0,0 +1,58 @@
+//! Heading renderer with rules or underlines by level
+
+use crate::terminal_renderer::context::RenderContext;
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementRenderer};
+use crate::terminal_renderer::formatters::format_heading;
+
+/// Renders headings in the options' heading style, keeping inline styles
+pub struct HeadingRenderer {
+    level: u32,
+    text: String,
+}
+
+impl Default for HeadingRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadingRenderer {
+    pub fn new() -> Self {
+        Self {
+            level: 1,
+            text: String::new(),
+        }
+    }
+}
+
+impl ElementRenderer for HeadingRenderer {
+    fn start(&mut self, info: &ElementInfo, _: &mut RenderContext) {
+        self.level = info.level;
+        self.text.clear();
+        if let Some(ref section) = info.section {
+            self.text.push_str(section);
+            self.text.push(' ');
+        }
+    }
+
+    fn handle_text(&mut self, text: &str, _: &mut RenderContext) {
+        self.text.push_str(text);
+    }
+
+    fn handle_styled(&mut self, styled: &str, _: &str, _: &mut RenderContext) {
+        self.text.push_str(styled);
+    }
+
+    fn handle_soft_break(&mut self, _: &mut RenderContext) {
+        self.text.push(' ');
+    }
+
+    fn handle_hard_break(&mut self, _: &mut RenderContext) {
+        self.text.push(' ');
+    }
+
+    fn end(&mut self, context: &mut RenderContext) -> Option<String> {
+        let glyphs = context.options.glyphs();
+        Some(format_heading(&self.text, self.level, context.options.heading_style, &glyphs))
+    }
+}
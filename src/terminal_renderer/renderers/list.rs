@@ -1,16 +1,17 @@
 //! List renderer for ordered and unordered lists
 
 use crate::terminal_renderer::context::RenderContext;
-use crate::terminal_renderer::element_renderer::ElementRenderer;
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementRenderer};
 
-/// Renders lists with bullets or numbers
+/// Renders list item markers: indentation by depth, then a bullet or number
+///
+/// Lists are containers, so item content is rendered in place and this
+/// renderer only writes the markers.
 pub struct ListRenderer {
-    #[allow(dead_code)]
     depth: usize,
     is_ordered: bool,
-    item_indices: Vec<usize>,
-    in_item: bool,
-    buffer: String,
+    bullet: Option<String>,
+    next_number: u64,
 }
 
 impl ListRenderer {
@@ -18,56 +19,43 @@ impl ListRenderer {
         Self {
             depth,
             is_ordered: ordered,
-            item_indices: if ordered { vec![0] } else { Vec::new() },
-            in_item: false,
-            buffer: String::new(),
+            bullet: None,
+            next_number: 1,
         }
     }
 
-    pub fn start_item(&mut self, output: &mut String, depth: usize) {
-        self.in_item = true;
-
-        // Add indentation
-        for _ in 0..(depth - 1) {
-            output.push_str("  ");
-        }
-
-        // Add bullet or number
-        if self.is_ordered {
-            if let Some(idx) = self.item_indices.last_mut() {
-                *idx += 1;
-                output.push_str(&format!("{}. ", idx));
-            }
-        } else {
-            output.push_str("• ");
-        }
-    }
-
-    #[allow(dead_code)]
-    fn end_item(&mut self) {
-        self.in_item = false;
+    /// Use a different glyph for unordered list items than the options' bullet
+    pub fn with_bullet(mut self, bullet: &str) -> Self {
+        self.bullet = Some(bullet.to_string());
+        self
     }
 }
 
 impl ElementRenderer for ListRenderer {
-    fn start(&mut self, _: &mut RenderContext) {
-        self.buffer.clear();
+    fn start(&mut self, info: &ElementInfo, _: &mut RenderContext) {
+        self.depth = info.level as usize;
+        self.is_ordered = info.list_start.is_some();
+        self.next_number = info.list_start.unwrap_or(1);
     }
 
-    fn handle_text(&mut self, text: &str, _: &mut RenderContext) {
-        self.buffer.push_str(text);
-    }
+    fn handle_text(&mut self, _: &str, _: &mut RenderContext) {}
 
-    fn handle_soft_break(&mut self, _: &mut RenderContext) {
-        self.buffer.push(' ');
-    }
+    fn handle_soft_break(&mut self, _: &mut RenderContext) {}
 
-    fn handle_hard_break(&mut self, _: &mut RenderContext) {
-        self.buffer.push('\n');
-    }
+    fn handle_hard_break(&mut self, _: &mut RenderContext) {}
 
     fn end(&mut self, _: &mut RenderContext) -> Option<String> {
-        // ListRenderer is handled differently in main render loop
         None
     }
+
+    fn start_item(&mut self, context: &mut RenderContext) {
+        let indent = "  ".repeat(self.depth.saturating_sub(1));
+        let marker = if self.is_ordered {
+            self.next_number += 1;
+            format!("{}.", self.next_number - 1)
+        } else {
+            self.bullet.clone().unwrap_or_else(|| context.options.bullet.clone())
+        };
+        context.output.push_str(&format!("{}{} ", indent, marker));
+    }
 }
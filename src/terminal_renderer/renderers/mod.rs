@@ -4,8 +4,10 @@ pub mod code_block;
 pub mod table;
 pub mod blockquote;
 pub mod list;
+pub mod heading;
 
 pub use code_block::CodeBlockRenderer;
 pub use table::TableRenderer;
 pub use blockquote::BlockquoteRenderer;
 pub use list::ListRenderer;
+pub use heading::HeadingRenderer;
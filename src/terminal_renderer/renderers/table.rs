@@ -1,13 +1,56 @@
 //! Table renderer with box-drawing characters
 
+use pulldown_cmark::Alignment;
+
 use crate::terminal_renderer::context::RenderContext;
-use crate::terminal_renderer::element_renderer::ElementRenderer;
+use crate::terminal_renderer::element_renderer::{ElementInfo, ElementRenderer};
+use crate::terminal_renderer::formatters::display_width;
+use crate::terminal_renderer::options::TableStyle;
+
+/// Border characters: corners/junctions for top, middle and bottom lines,
+/// then the horizontal and vertical strokes
+struct BorderChars {
+    top: [char; 3],
+    middle: [char; 3],
+    bottom: [char; 3],
+    horizontal: char,
+    vertical: char,
+}
+
+impl TableStyle {
+    fn border_chars(self) -> BorderChars {
+        match self {
+            TableStyle::Unicode => BorderChars {
+                top: ['┌', '┬', '┐'],
+                middle: ['├', '┼', '┤'],
+                bottom: ['└', '┴', '┘'],
+                horizontal: '─',
+                vertical: '│',
+            },
+            TableStyle::Ascii => BorderChars {
+                top: ['+', '+', '+'],
+                middle: ['+', '+', '+'],
+                bottom: ['+', '+', '+'],
+                horizontal: '-',
+                vertical: '|',
+            },
+        }
+    }
+}
 
 /// Renders markdown tables with box-drawing characters
 pub struct TableRenderer {
     rows: Vec<Vec<String>>,
     current_row: Vec<String>,
     current_cell: String,
+    alignments: Vec<Alignment>,
+    style: TableStyle,
+}
+
+impl Default for TableRenderer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TableRenderer {
@@ -16,6 +59,8 @@ impl TableRenderer {
             rows: Vec::new(),
             current_row: Vec::new(),
             current_cell: String::new(),
+            alignments: Vec::new(),
+            style: TableStyle::default(),
         }
     }
 
@@ -31,66 +76,7 @@ impl TableRenderer {
     }
 
     pub fn render_table(&self) -> String {
-        if self.rows.is_empty() {
-            return String::new();
-        }
-
-        let num_cols = self.rows.iter().map(|r| r.len()).max().unwrap_or(0);
-        let mut col_widths = vec![0; num_cols];
-
-        for row in &self.rows {
-            for (i, cell) in row.iter().enumerate() {
-                col_widths[i] = col_widths[i].max(cell.len());
-            }
-        }
-
-        let mut output = String::new();
-
-        // Top border
-        output.push('┌');
-        for (i, width) in col_widths.iter().enumerate() {
-            output.push_str(&"─".repeat(width + 2));
-            if i < col_widths.len() - 1 {
-                output.push('┬');
-            }
-        }
-        output.push_str("┐\n");
-
-        // Rows
-        for (row_idx, row) in self.rows.iter().enumerate() {
-            output.push('│');
-            for (col_idx, cell) in row.iter().enumerate() {
-                output.push(' ');
-                output.push_str(&format!("{:<width$}", cell, width = col_widths[col_idx]));
-                output.push(' ');
-                output.push('│');
-            }
-            output.push('\n');
-
-            // Add separator line between rows (or after header)
-            if row_idx < self.rows.len() - 1 {
-                output.push('├');
-                for (i, width) in col_widths.iter().enumerate() {
-                    output.push_str(&"─".repeat(width + 2));
-                    if i < col_widths.len() - 1 {
-                        output.push('┼');
-                    }
-                }
-                output.push_str("┤\n");
-            }
-        }
-
-        // Bottom border
-        output.push('└');
-        for (i, width) in col_widths.iter().enumerate() {
-            output.push_str(&"─".repeat(width + 2));
-            if i < col_widths.len() - 1 {
-                output.push('┴');
-            }
-        }
-        output.push_str("┘");
-
-        output
+        draw_table(&self.rows, &self.alignments, self.style)
     }
 
     pub fn start_cell(&mut self) {
@@ -111,11 +97,82 @@ impl TableRenderer {
     }
 }
 
+fn pad_cell(cell: &str, alignment: Option<&Alignment>, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(cell));
+    let (left, right) = match alignment {
+        Some(Alignment::Right) => (padding, 0),
+        Some(Alignment::Center) => (padding / 2, padding - padding / 2),
+        _ => (0, padding),
+    };
+    format!("{}{}{}", " ".repeat(left), cell, " ".repeat(right))
+}
+
+fn border_line(col_widths: &[usize], [left, junction, right]: [char; 3], horizontal: char) -> String {
+    let mut line = String::new();
+    line.push(left);
+    for (i, width) in col_widths.iter().enumerate() {
+        line.push_str(&horizontal.to_string().repeat(width + 2));
+        if i < col_widths.len() - 1 {
+            line.push(junction);
+        }
+    }
+    line.push(right);
+    line
+}
+
+/// Draw rows as a bordered table, the first row being the header
+pub fn draw_table(rows: &[Vec<String>], alignments: &[Alignment], style: TableStyle) -> String {
+    if rows.is_empty() {
+        return String::new();
+    }
+
+    let chars = style.border_chars();
+    let num_cols = rows.iter().map(|r| r.len()).max().unwrap_or(0);
+    let mut col_widths = vec![0; num_cols];
+
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            col_widths[i] = col_widths[i].max(display_width(cell));
+        }
+    }
+
+    let mut output = String::new();
+
+    // Top border
+    output.push_str(&border_line(&col_widths, chars.top, chars.horizontal));
+    output.push('\n');
+
+    // Rows
+    for (row_idx, row) in rows.iter().enumerate() {
+        output.push(chars.vertical);
+        for (col_idx, cell) in row.iter().enumerate() {
+            output.push(' ');
+            output.push_str(&pad_cell(cell, alignments.get(col_idx), col_widths[col_idx]));
+            output.push(' ');
+            output.push(chars.vertical);
+        }
+        output.push('\n');
+
+        // Add separator line between rows (or after header)
+        if row_idx < rows.len() - 1 {
+            output.push_str(&border_line(&col_widths, chars.middle, chars.horizontal));
+            output.push('\n');
+        }
+    }
+
+    // Bottom border
+    output.push_str(&border_line(&col_widths, chars.bottom, chars.horizontal));
+
+    output
+}
+
 impl ElementRenderer for TableRenderer {
-    fn start(&mut self, _: &mut RenderContext) {
+    fn start(&mut self, info: &ElementInfo, context: &mut RenderContext) {
         self.rows.clear();
         self.current_row.clear();
         self.current_cell.clear();
+        self.alignments = info.alignments.clone();
+        self.style = context.options.table_style;
     }
 
     fn handle_text(&mut self, text: &str, _: &mut RenderContext) {
@@ -133,4 +190,20 @@ impl ElementRenderer for TableRenderer {
     fn end(&mut self, _: &mut RenderContext) -> Option<String> {
         Some(self.render_table())
     }
+
+    fn start_row(&mut self) {
+        TableRenderer::start_row(self);
+    }
+
+    fn end_row(&mut self) {
+        TableRenderer::end_row(self);
+    }
+
+    fn start_cell(&mut self) {
+        TableRenderer::start_cell(self);
+    }
+
+    fn end_cell(&mut self) {
+        TableRenderer::end_cell(self);
+    }
 }
@@ -0,0 +1,393 @@
+//! Token counting for context-window budgeting
+//!
+//! A `Tokenizer` counts the tokens of a text. `Bpe` is an exact byte-pair
+//! encoder over a rank table in tiktoken's format (one base64 token and its
+//! rank per line). The `cl100k_base` and `o200k_base` tables are bundled
+//! with the `bundled-encodings` feature, on by default; others are read
+//! from a file. `Heuristic` estimates from the text length instead.
+//!
+//! `Document::token_count` adds the overhead the Chat Completions format
+//! puts around each message, so the total is what `to_openai_chat()` costs.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::stats::estimate_tokens;
+use crate::{Document, Message, Role};
+
+/// Tokens framing each Chat Completions message
+pub const MESSAGE_OVERHEAD: usize = 3;
+/// Extra tokens for a message with a `name`
+pub const NAME_OVERHEAD: usize = 1;
+/// Tokens priming the assistant reply, once per request
+pub const REPLY_OVERHEAD: usize = 3;
+
+/// Counts the tokens of a text
+pub trait Tokenizer {
+    fn count(&self, text: &str) -> usize;
+
+    /// Whether counts are exact rather than estimated
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
+
+/// Estimates about four characters per token, without a table
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Heuristic;
+
+impl Tokenizer for Heuristic {
+    fn count(&self, text: &str) -> usize {
+        estimate_tokens(text)
+    }
+
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+/// An encoding whose pre-tokenizer `Bpe` knows, and whose table is bundled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// GPT-4 and GPT-3.5
+    #[default]
+    Cl100kBase,
+    /// GPT-4o and later
+    O200kBase,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => "cl100k_base",
+            Encoding::O200kBase => "o200k_base",
+        }
+    }
+
+    /// tiktoken's pattern for splitting text before merging, with its
+    /// `\s+(?!\S)|\s+` ending written as a group: the `regex` crate has no
+    /// lookahead, so `split` trims that group instead
+    fn pattern(self) -> &'static str {
+        match self {
+            Encoding::Cl100kBase => {
+                r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|(\s+)"
+            }
+            Encoding::O200kBase => concat!(
+                r"[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]*[\p{Ll}\p{Lm}\p{Lo}\p{M}]+(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|[^\r\n\p{L}\p{N}]?[\p{Lu}\p{Lt}\p{Lm}\p{Lo}\p{M}]+[\p{Ll}\p{Lm}\p{Lo}\p{M}]*(?i:'s|'t|'re|'ve|'m|'ll|'d)?",
+                r"|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n/]*|\s*[\r\n]+|(\s+)",
+            ),
+        }
+    }
+
+    /// The bundled rank table, in tiktoken's format
+    #[cfg(feature = "bundled-encodings")]
+    pub fn table(self) -> &'static [u8] {
+        match self {
+            Encoding::Cl100kBase => include_bytes!("../encodings/cl100k_base.tiktoken"),
+            Encoding::O200kBase => include_bytes!("../encodings/o200k_base.tiktoken"),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cl100k_base" => Ok(Encoding::Cl100kBase),
+            "o200k_base" => Ok(Encoding::O200kBase),
+            _ => Err(format!("unknown encoding '{}' (expected cl100k_base or o200k_base)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Byte-pair encoder over a tiktoken rank table
+///
+/// Text is split into pieces the way the table's encoding does before
+/// merging, so counts are exact for `cl100k_base` and `o200k_base` tables.
+#[derive(Debug, Clone)]
+pub struct Bpe {
+    ranks: HashMap<Vec<u8>, u32>,
+    pattern: Regex,
+}
+
+impl Bpe {
+    /// Build an encoder from `(token bytes, rank)` pairs, splitting text
+    /// like `cl100k_base`; every single byte must have a rank
+    pub fn new(ranks: HashMap<Vec<u8>, u32>) -> io::Result<Self> {
+        if let Some(byte) = (0..=255u8).find(|byte| !ranks.contains_key(&vec![*byte])) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("rank table has no token for byte {:#04x}", byte),
+            ));
+        }
+        Ok(Self {
+            ranks,
+            pattern: Self::compile(Encoding::Cl100kBase),
+        })
+    }
+
+    /// Split text the way `encoding` does
+    pub fn split_as(mut self, encoding: Encoding) -> Self {
+        self.pattern = Self::compile(encoding);
+        self
+    }
+
+    /// The bundled table of `encoding`
+    #[cfg(feature = "bundled-encodings")]
+    pub fn bundled(encoding: Encoding) -> Self {
+        Self::from_tiktoken(encoding.table())
+            .expect("bundled tables are valid")
+            .split_as(encoding)
+    }
+
+    fn compile(encoding: Encoding) -> Regex {
+        Regex::new(encoding.pattern()).expect("encoding patterns are valid")
+    }
+
+    /// Read a table in tiktoken's format
+    pub fn from_tiktoken<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut ranks = HashMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("line {}: expected a token and a rank", i + 1));
+            let (token, rank) = line.split_once(' ').ok_or_else(invalid)?;
+            let token = STANDARD.decode(token).map_err(|_| invalid())?;
+            let rank = rank.trim().parse().map_err(|_| invalid())?;
+            ranks.insert(token, rank);
+        }
+        Self::new(ranks)
+    }
+
+    /// Read a tiktoken file such as `cl100k_base.tiktoken`; a file named
+    /// after `o200k_base` is split like it
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let bpe = Self::from_tiktoken(BufReader::new(File::open(path)?))?;
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        Ok(match name.starts_with(Encoding::O200kBase.name()) {
+            true => bpe.split_as(Encoding::O200kBase),
+            false => bpe,
+        })
+    }
+
+    /// Token ranks of `text`
+    pub fn encode(&self, text: &str) -> Vec<u32> {
+        split(&self.pattern, text).into_iter().flat_map(|piece| self.merge(piece.as_bytes())).collect()
+    }
+
+    /// Merge the lowest-ranked adjacent pair until no pair is a token
+    fn merge(&self, piece: &[u8]) -> Vec<u32> {
+        if let Some(&rank) = self.ranks.get(piece) {
+            return vec![rank];
+        }
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let best = bounds
+                .windows(3)
+                .enumerate()
+                .filter_map(|(i, w)| self.ranks.get(&piece[w[0]..w[2]]).map(|&rank| (rank, i)))
+                .min();
+            match best {
+                Some((_, i)) => {
+                    bounds.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        bounds.windows(2).map(|w| self.ranks[&piece[w[0]..w[1]]]).collect()
+    }
+}
+
+impl Tokenizer for Bpe {
+    fn count(&self, text: &str) -> usize {
+        split(&self.pattern, text).into_iter().map(|piece| self.merge(piece.as_bytes()).len()).sum()
+    }
+}
+
+/// Split text with an encoding's pattern; a whitespace run in the last
+/// group leaves its final character to start the next piece when one
+/// follows, as `\s+(?!\S)` would
+fn split<'a>(pattern: &Regex, text: &'a str) -> Vec<&'a str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while let Some(captures) = pattern.captures_at(text, start) {
+        let whole = captures.get(0).expect("group 0 always matches");
+        let mut end = whole.end();
+        if let Some(space) = captures.get(1) {
+            let last = space.as_str().chars().next_back().map_or(0, char::len_utf8);
+            if end < text.len() && space.len() > last {
+                end -= last;
+            }
+        }
+        pieces.push(&text[whole.start()..end]);
+        start = end;
+    }
+    pieces
+}
+
+/// Tokens of one Chat Completions message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MessageTokens {
+    /// 1-based turn the message belongs to
+    pub turn: usize,
+    pub role: Role,
+    /// Content, name and tool calls, with the message overhead
+    pub tokens: usize,
+}
+
+/// Token counts of a document sent as Chat Completions messages
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenCount {
+    pub messages: Vec<MessageTokens>,
+    /// Tokens per turn, the sum of its messages
+    pub turns: Vec<usize>,
+    /// All messages plus the reply overhead
+    pub total: usize,
+    /// Whether the counts come from an exact tokenizer
+    pub exact: bool,
+}
+
+/// Tokens of one message in Chat Completions format
+pub fn message_tokens(message: &Message, tokenizer: &dyn Tokenizer) -> usize {
+    let mut tokens = MESSAGE_OVERHEAD + tokenizer.count(message.role.as_str()) + tokenizer.count(&message.content);
+    if let (Role::User | Role::Assistant, Some(name)) = (message.role, &message.name) {
+        tokens += NAME_OVERHEAD + tokenizer.count(name);
+    }
+    for call in &message.tool_calls {
+        tokens += tokenizer.count(&call.name) + tokenizer.count(&call.arguments);
+        tokens += tokenizer.count(&call.id);
+    }
+    tokens + message.tool_call_id.as_deref().map_or(0, |id| tokenizer.count(id))
+}
+
+impl Document {
+    /// Count the tokens of `to_openai_chat()` output, per message and turn
+    pub fn token_count(&self, tokenizer: &dyn Tokenizer) -> TokenCount {
+        let mut ids = self.tool_ids();
+        let mut messages = Vec::new();
+        let mut turns = Vec::new();
+        for (i, turn) in self.turns.iter().enumerate() {
+            let mut turn_tokens = 0;
+            for message in turn.messages_with_ids(&mut ids) {
+                let tokens = message_tokens(&message, tokenizer);
+                turn_tokens += tokens;
+                messages.push(MessageTokens {
+                    turn: i + 1,
+                    role: message.role,
+                    tokens,
+                });
+            }
+            turns.push(turn_tokens);
+        }
+        TokenCount {
+            total: turns.iter().sum::<usize>() + REPLY_OVERHEAD,
+            messages,
+            turns,
+            exact: tokenizer.is_exact(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Single bytes, then "he", "ll", "hell", "hello" and " w"
+    fn tiny_bpe() -> Bpe {
+        let mut ranks: HashMap<Vec<u8>, u32> = (0..=255u8).map(|b| (vec![b], b as u32)).collect();
+        for (rank, token) in ["he", "ll", "hell", "hello", " w"].into_iter().enumerate() {
+            ranks.insert(token.as_bytes().to_vec(), 256 + rank as u32);
+        }
+        Bpe::new(ranks).unwrap()
+    }
+
+    #[test]
+    fn test_split() {
+        let cl100k = Bpe::compile(Encoding::Cl100kBase);
+        assert_eq!(
+            split(&cl100k, "Hello world's 12345 ok!!\n\n  x   "),
+            ["Hello", " world", "'s", " ", "123", "45", " ok", "!!\n\n", " ", " x", "   "]
+        );
+        assert_eq!(split(&cl100k, "a  b\tc"), ["a", " ", " b", "\tc"]);
+        assert_eq!(split(&cl100k, "(ça) va"), ["(ça", ")", " va"]);
+
+        let o200k = Bpe::compile(Encoding::O200kBase);
+        assert_eq!(split(&o200k, "CamelCase don't a/b/"), ["Camel", "Case", " don't", " a", "/b", "/"]);
+    }
+
+    #[cfg(feature = "bundled-encodings")]
+    #[test]
+    fn test_bundled_counts() {
+        // Counts from tiktoken for the same texts
+        let texts = [
+            "Hello world's 12345 ok!!\n\n  x   ",
+            "I'LL say: don't 你好世界 3.14159 https://example.com/a/b",
+            "fn main() {\n    println!(\"hi\");\n}\n",
+            "CamelCaseHTTPServer über",
+        ];
+        let cl100k = Bpe::bundled(Encoding::Cl100kBase);
+        assert_eq!(texts.map(|text| cl100k.count(text)), [11, 24, 10, 6]);
+        let o200k = Bpe::bundled(Encoding::O200kBase);
+        assert_eq!(texts.map(|text| o200k.count(text)), [10, 20, 10, 5]);
+    }
+
+    #[test]
+    fn test_encoding_names() {
+        assert_eq!("o200k_base".parse(), Ok(Encoding::O200kBase));
+        assert_eq!(Encoding::default().to_string(), "cl100k_base");
+        assert!("p50k_base".parse::<Encoding>().is_err());
+    }
+
+    #[test]
+    fn test_bpe_merges() {
+        let bpe = tiny_bpe();
+        assert_eq!(bpe.encode("hello"), [259]);
+        assert_eq!(bpe.encode("hellx"), [258, b'x' as u32]);
+        assert_eq!(bpe.encode(" wo"), [260, b'o' as u32]);
+        assert_eq!(bpe.count("hello hello"), 1 + 2);
+    }
+
+    #[test]
+    fn test_from_tiktoken() {
+        let table: String = (0..=255u8).map(|b| format!("{} {}\n", STANDARD.encode([b]), b)).collect();
+        let bpe = Bpe::from_tiktoken(format!("{}{} 256\n", table, STANDARD.encode("ab")).as_bytes()).unwrap();
+        assert_eq!(bpe.encode("ab"), [256]);
+
+        let error = Bpe::from_tiktoken("YQ== 0\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("no token for byte 0x00"));
+        assert!(Bpe::from_tiktoken("nonsense\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_document_token_count() {
+        let doc = Document::parse("> @system: Be brief\n\n> @alice: Hi\nHello");
+        let count = doc.token_count(&tiny_bpe());
+        assert_eq!(count.messages.len(), 3);
+        assert_eq!(count.turns.len(), 2);
+        // "user" (4 bytes, no merges) + "Hi" (2) + name "alice" (5) + overheads
+        assert_eq!(count.messages[1].tokens, MESSAGE_OVERHEAD + 4 + 2 + NAME_OVERHEAD + 5);
+        assert_eq!(count.total, count.turns.iter().sum::<usize>() + REPLY_OVERHEAD);
+        assert!(count.exact);
+        assert!(!doc.token_count(&Heuristic).exact);
+    }
+}
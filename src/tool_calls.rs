@@ -0,0 +1,292 @@
+//! Tool call and tool result blocks inside assistant content
+//!
+//! Tool activity is written as fenced code blocks tagged in the info string:
+//!
+//! ````markdown
+//! ```tool_call name=get_weather id=call_1
+//! {"city": "Paris"}
+//! ```
+//!
+//! ```tool_result id=call_1
+//! {"temp": 21}
+//! ```
+//! ````
+//!
+//! `id` is optional on both: calls without one get a generated id and
+//! results without one answer the oldest unanswered call. `cmf check`
+//! reports results that answer no call.
+//!
+//! Body lines starting with `>` or `<!--`, after any spaces, are written
+//! with one more space so they are not read as user lines or comments, and
+//! read back without it.
+
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+use crate::editor::needs_escape;
+
+pub const TOOL_CALL_TAG: &str = "tool_call";
+pub const TOOL_RESULT_TAG: &str = "tool_result";
+
+/// A function call requested by the assistant
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// Arguments as written in the block, usually JSON
+    pub arguments: String,
+}
+
+/// What a tool block in assistant content contains
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ToolBlockKind {
+    Call { name: String },
+    Result { name: Option<String> },
+}
+
+/// A tool block found in markdown, with its byte range in the source
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ToolBlock {
+    pub range: Range<usize>,
+    pub kind: ToolBlockKind,
+    pub id: Option<String>,
+    pub body: String,
+}
+
+/// Find `tool_call` / `tool_result` fenced blocks in markdown
+pub(crate) fn find_tool_blocks(markdown: &str) -> Vec<ToolBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<ToolBlock> = None;
+
+    for (event, range) in Parser::new(markdown).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                current = parse_info(&info).map(|(kind, id)| ToolBlock {
+                    range,
+                    kind,
+                    id,
+                    body: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(ref mut block) = current {
+                    block.body.push_str(&text);
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some(mut block) = current.take() {
+                    if block.body.ends_with('\n') {
+                        block.body.pop();
+                    }
+                    block.body = unescape_body(&block.body);
+                    blocks.push(block);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+/// Parse an info string like `tool_call name=lookup id=call_1`
+fn parse_info(info: &str) -> Option<(ToolBlockKind, Option<String>)> {
+    let mut words = info.split_whitespace();
+    let tag = words.next()?;
+
+    let mut name = None;
+    let mut id = None;
+    for word in words {
+        match word.split_once('=') {
+            Some(("name", value)) => name = Some(value.to_string()),
+            Some(("id", value)) => id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    let kind = match tag {
+        TOOL_CALL_TAG => ToolBlockKind::Call {
+            name: name.unwrap_or_default(),
+        },
+        TOOL_RESULT_TAG => ToolBlockKind::Result { name },
+        _ => return None,
+    };
+    Some((kind, id))
+}
+
+/// Assigns ids to calls that lack one and pairs id-less results with calls
+///
+/// Generated ids skip those already seen or reserved.
+#[derive(Debug, Default)]
+pub(crate) struct ToolIds {
+    generated: usize,
+    taken: HashSet<String>,
+    unanswered: VecDeque<String>,
+}
+
+impl ToolIds {
+    /// Keep generated ids clear of the ids written in `markdown`
+    pub fn reserve(&mut self, markdown: &str) {
+        self.taken.extend(find_tool_blocks(markdown).into_iter().filter_map(|block| block.id));
+    }
+
+    pub fn call_id(&mut self, id: Option<String>) -> String {
+        let id = match id {
+            Some(id) => {
+                self.taken.insert(id.clone());
+                id
+            }
+            None => self.generate(),
+        };
+        self.unanswered.push_back(id.clone());
+        id
+    }
+
+    /// Id for a result: its own, or that of the oldest unanswered call; a
+    /// result with neither gets a fresh id rather than an empty one
+    pub fn result_id(&mut self, id: Option<String>) -> String {
+        match id {
+            Some(id) => {
+                self.unanswered.retain(|pending| *pending != id);
+                self.taken.insert(id.clone());
+                id
+            }
+            None => match self.unanswered.pop_front() {
+                Some(id) => id,
+                None => self.generate(),
+            },
+        }
+    }
+
+    /// Whether a result with this id answers a call still waiting for one
+    pub fn is_answer(&self, id: Option<&str>) -> bool {
+        match id {
+            Some(id) => self.unanswered.iter().any(|pending| pending == id),
+            None => !self.unanswered.is_empty(),
+        }
+    }
+
+    fn generate(&mut self) -> String {
+        loop {
+            self.generated += 1;
+            let id = format!("call_{}", self.generated);
+            if self.taken.insert(id.clone()) {
+                return id;
+            }
+        }
+    }
+}
+
+/// Whether a body line, after any spaces, would be read as a user line or
+/// a comment
+fn needs_body_escape(line: &str) -> bool {
+    needs_escape(line.trim_start_matches(' '))
+}
+
+fn escape_body(body: &str) -> String {
+    let lines: Vec<String> = body
+        .split('\n')
+        .map(|line| match needs_body_escape(line) {
+            true => format!(" {}", line),
+            false => line.to_string(),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn unescape_body(body: &str) -> String {
+    let lines: Vec<&str> = body
+        .split('\n')
+        .map(|line| match line.strip_prefix(' ') {
+            Some(rest) if needs_body_escape(rest) => rest,
+            _ => line,
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Write a fenced block, using a fence longer than any backtick run in the
+/// body, with its quote and comment lines escaped
+fn fenced_block(info: &str, body: &str) -> String {
+    let longest_run = body
+        .split(|c| c != '`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, info, escape_body(body), fence)
+}
+
+pub(crate) fn write_tool_call(call: &ToolCall) -> String {
+    let info = format!("{} name={} id={}", TOOL_CALL_TAG, call.name, call.id);
+    fenced_block(&info, &call.arguments)
+}
+
+pub(crate) fn write_tool_result(id: &str, name: Option<&str>, content: &str) -> String {
+    let mut info = TOOL_RESULT_TAG.to_string();
+    if let Some(name) = name {
+        info.push_str(&format!(" name={}", name));
+    }
+    info.push_str(&format!(" id={}", id));
+    fenced_block(&info, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_tool_blocks() {
+        let markdown = "Let me check.\n\n```tool_call name=weather id=c1\n{\"city\": \"Paris\"}\n```\n\n```tool_result\nsunny\n```\n\n```rust\nfn main() {}\n```";
+        let blocks = find_tool_blocks(markdown);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].kind, ToolBlockKind::Call { name: "weather".to_string() });
+        assert_eq!(blocks[0].id.as_deref(), Some("c1"));
+        assert_eq!(blocks[0].body, "{\"city\": \"Paris\"}");
+        assert_eq!(blocks[1].kind, ToolBlockKind::Result { name: None });
+        assert!(markdown[blocks[1].range.clone()].starts_with("```tool_result"));
+    }
+
+    #[test]
+    fn test_ids_pair_calls_and_results() {
+        let mut ids = ToolIds::default();
+        assert_eq!(ids.call_id(None), "call_1");
+        assert_eq!(ids.call_id(Some("x".to_string())), "x");
+        assert_eq!(ids.result_id(Some("x".to_string())), "x");
+        assert!(ids.is_answer(None));
+        assert_eq!(ids.result_id(None), "call_1");
+        assert!(!ids.is_answer(None));
+        assert!(!ids.is_answer(Some("x")));
+        // An orphan result still gets an id, distinct from the calls'
+        assert_eq!(ids.result_id(None), "call_2");
+
+        // Generated ids skip explicit ones, seen or reserved
+        let mut ids = ToolIds::default();
+        ids.reserve("```tool_call name=f id=call_2\n{}\n```");
+        assert_eq!(ids.call_id(Some("call_1".to_string())), "call_1");
+        assert_eq!(ids.call_id(None), "call_3");
+    }
+
+    #[test]
+    fn test_escaped_bodies() {
+        let output = "$ ls\n> prompt\n  <!-- x -->\n done";
+        let written = write_tool_result("c1", None, output);
+        assert_eq!(written, "```tool_result id=c1\n$ ls\n > prompt\n   <!-- x -->\n done\n```");
+        assert_eq!(find_tool_blocks(&written)[0].body, output);
+    }
+
+    #[test]
+    fn test_fence_longer_than_body_backticks() {
+        let call = ToolCall {
+            id: "c1".to_string(),
+            name: "run".to_string(),
+            arguments: "```nested```".to_string(),
+        };
+        let written = write_tool_call(&call);
+        assert!(written.starts_with("````tool_call name=run id=c1\n"));
+        assert_eq!(find_tool_blocks(&written)[0].body, "```nested```");
+    }
+}
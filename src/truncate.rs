@@ -0,0 +1,287 @@
+//! Fitting a conversation into a context window
+//!
+//! `Document::truncate` drops whole turns until the Chat Completions input
+//! fits a token budget. Turns keep their user message and replies together,
+//! so user/assistant alternation stays as valid as in the original.
+
+use std::fmt;
+use std::slice::ChunkBy;
+use std::str::FromStr;
+
+use crate::tokens::{message_tokens, Tokenizer, REPLY_OVERHEAD};
+use crate::{Document, Message, Role, Turn, UserMessage, SYSTEM_USERNAME};
+
+/// Which turns to give up first when a conversation is too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Drop turns from the start
+    #[default]
+    DropOldest,
+    /// Keep the first `first` turns, the last `last` ones and as many more
+    /// of the last ones as fit; without `last`, only as many as fit
+    KeepFirst { first: usize, last: Option<usize> },
+    /// Keep system messages and as many of the last turns as fit
+    KeepSystem,
+    /// Like `KeepSystem`, with a system note in place of each run of
+    /// dropped turns, as a stand-in for a summary
+    Placeholder,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    /// `drop-oldest`, `keep-first[=N][,last=M]` (N defaults to 1),
+    /// `keep-system` or `placeholder`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let turns = |n: &str| n.parse().map_err(|_| format!("invalid number of turns '{}'", n));
+        let (name, last) = match s.split_once(',') {
+            Some((name, last)) => match last.split_once('=') {
+                Some(("last", m)) => (name, Some(turns(m)?)),
+                _ => return Err(format!("invalid option '{}' (expected last=M)", last)),
+            },
+            None => (s, None),
+        };
+        match name.split_once('=') {
+            None if name == "keep-first" => Ok(Strategy::KeepFirst { first: 1, last }),
+            Some(("keep-first", n)) => Ok(Strategy::KeepFirst { first: turns(n)?, last }),
+            _ if last.is_some() => Err(format!("only keep-first takes last=M, not '{}'", name)),
+            None if name == "drop-oldest" => Ok(Strategy::DropOldest),
+            None if name == "keep-system" => Ok(Strategy::KeepSystem),
+            None if name == "placeholder" => Ok(Strategy::Placeholder),
+            _ => Err(format!(
+                "unknown strategy '{}' (expected drop-oldest, keep-first[=N][,last=M], keep-system or placeholder)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Strategy::DropOldest => write!(f, "drop-oldest"),
+            Strategy::KeepFirst { first, last: None } => write!(f, "keep-first={}", first),
+            Strategy::KeepFirst { first, last: Some(last) } => write!(f, "keep-first={},last={}", first, last),
+            Strategy::KeepSystem => write!(f, "keep-system"),
+            Strategy::Placeholder => write!(f, "placeholder"),
+        }
+    }
+}
+
+/// A truncated document and what was left out
+#[derive(Debug, Clone, PartialEq)]
+pub struct Truncation {
+    pub document: Document,
+    /// 1-based numbers of the dropped turns
+    pub dropped: Vec<usize>,
+    /// Tokens of the result; over the budget when even the last turn
+    /// doesn't fit
+    pub tokens: usize,
+}
+
+/// Text of the note standing in for a run of dropped turns
+pub fn placeholder_text(dropped: usize) -> String {
+    let turns = if dropped == 1 { "turn" } else { "turns" };
+    format!("[{} earlier {} omitted to fit the context window]", dropped, turns)
+}
+
+/// A system turn opening with assistant content, which can't lead a
+/// conversation once the turns before it are gone
+fn is_continuation(turn: &Turn) -> bool {
+    turn.role() == Role::System && turn.user.content.is_empty()
+}
+
+/// Runs of consecutive turn numbers in `dropped`
+fn runs(dropped: &[usize]) -> ChunkBy<'_, usize, fn(&usize, &usize) -> bool> {
+    dropped.chunk_by(|a, b| a + 1 == *b)
+}
+
+impl Document {
+    /// Drop turns by `strategy` until the Chat Completions input fits in
+    /// `max_tokens`; the last turn is always kept
+    pub fn truncate(&self, max_tokens: usize, strategy: Strategy, tokenizer: &dyn Tokenizer) -> Truncation {
+        let turn_tokens = self.token_count(tokenizer).turns;
+        let pinned: Vec<bool> = self
+            .turns
+            .iter()
+            .enumerate()
+            .map(|(i, turn)| match strategy {
+                Strategy::DropOldest => false,
+                Strategy::KeepFirst { first, last } => {
+                    i < first || i + last.unwrap_or(0) >= self.turns.len()
+                }
+                Strategy::KeepSystem | Strategy::Placeholder => {
+                    turn.role() == Role::System && !turn.user.content.is_empty()
+                }
+            })
+            .collect();
+        let placeholder_tokens = |dropped: &[usize]| match strategy {
+            Strategy::Placeholder => runs(dropped)
+                .map(|run| message_tokens(&Message::new(Role::System, &placeholder_text(run.len())), tokenizer))
+                .sum(),
+            _ => 0,
+        };
+
+        let mut kept = vec![true; self.turns.len()];
+        let mut dropped = Vec::new();
+        let mut tokens = turn_tokens.iter().sum::<usize>() + REPLY_OVERHEAD;
+        let last = self.turns.len().saturating_sub(1);
+        // Unpinned turns in order: drop from the front while over budget, and
+        // never leave a continuation at the front
+        for i in (0..last).filter(|&i| !pinned[i]) {
+            let must_go = !dropped.is_empty() && is_continuation(&self.turns[i]);
+            if tokens + placeholder_tokens(&dropped) <= max_tokens && !must_go {
+                break;
+            }
+            kept[i] = false;
+            dropped.push(i + 1);
+            tokens -= turn_tokens[i];
+        }
+        tokens += placeholder_tokens(&dropped);
+
+        let mut turns = Vec::new();
+        let mut runs = runs(&dropped).peekable();
+        for (i, turn) in self.turns.iter().enumerate() {
+            if kept[i] {
+                turns.push(turn.clone());
+                continue;
+            }
+            let Some(run) = runs.next_if(|run| run[0] == i + 1) else {
+                continue;
+            };
+            if strategy == Strategy::Placeholder {
+                turns.push(Turn {
+                    user: UserMessage {
+                        username: Some(SYSTEM_USERNAME.to_string()),
+                        content: placeholder_text(run.len()),
+                        metadata: Default::default(),
+                    },
+                    assistant: String::new(),
+                });
+            }
+        }
+
+        Truncation {
+            document: Document { turns },
+            dropped,
+            tokens,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokens::Heuristic;
+
+    /// A system prompt and five turns of about 30 tokens each
+    fn long_doc() -> Document {
+        let mut input = String::from("> @system: Be brief\n\n");
+        for i in 1..=5 {
+            input.push_str(&format!("> Question {}\n{}\n\n", i, "answer ".repeat(12)));
+        }
+        Document::parse(&input)
+    }
+
+    fn users(doc: &Document) -> Vec<&str> {
+        doc.turns.iter().map(|turn| turn.user.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_fits_untouched() {
+        let doc = long_doc();
+        let result = doc.truncate(10_000, Strategy::DropOldest, &Heuristic);
+        assert_eq!(result.document, doc);
+        assert!(result.dropped.is_empty());
+        assert_eq!(result.tokens, doc.token_count(&Heuristic).total);
+    }
+
+    #[test]
+    fn test_strategies() {
+        let doc = long_doc();
+        let budget = doc.token_count(&Heuristic).total - 40;
+
+        let oldest = doc.truncate(budget, Strategy::DropOldest, &Heuristic);
+        assert_eq!(oldest.dropped, [1, 2]);
+        assert!(oldest.tokens <= budget);
+
+        let system = doc.truncate(budget, Strategy::KeepSystem, &Heuristic);
+        assert_eq!(system.dropped, [2, 3]);
+        assert_eq!(users(&system.document), ["Be brief", "Question 3", "Question 4", "Question 5"]);
+
+        let first = doc.truncate(budget, Strategy::KeepFirst { first: 2, last: None }, &Heuristic);
+        assert_eq!(first.dropped, [3, 4]);
+
+        let window = Strategy::KeepFirst { first: 1, last: Some(3) };
+        let window = doc.truncate(budget, window, &Heuristic);
+        assert_eq!(users(&window.document), ["Be brief", "Question 3", "Question 4", "Question 5"]);
+        let window = doc.truncate(1, Strategy::KeepFirst { first: 1, last: Some(2) }, &Heuristic);
+        assert_eq!(users(&window.document), ["Be brief", "Question 4", "Question 5"]);
+
+        let placeholder = doc.truncate(budget, Strategy::Placeholder, &Heuristic);
+        assert_eq!(placeholder.dropped, [2, 3]);
+        assert_eq!(placeholder.document.turns[1].user.content, placeholder_text(2));
+        assert_eq!(placeholder.document.turns[1].role(), Role::System);
+        assert!(placeholder.tokens <= budget);
+    }
+
+    #[test]
+    fn test_placeholder_per_run() {
+        // The second system prompt is kept, splitting the dropped turns in two
+        let mut input = String::from("> @system: Be brief\n\n");
+        for i in 1..=6 {
+            if i == 4 {
+                input.push_str("> @system: Be kind\n\n");
+            }
+            input.push_str(&format!("> Question {}\n{}\n\n", i, "answer ".repeat(12)));
+        }
+        let doc = Document::parse(&input);
+        let result = doc.truncate(130, Strategy::Placeholder, &Heuristic);
+        assert_eq!(result.dropped, [2, 3, 4, 6]);
+        assert_eq!(
+            users(&result.document),
+            [
+                "Be brief",
+                &placeholder_text(3),
+                "Be kind",
+                &placeholder_text(1),
+                "Question 5",
+                "Question 6"
+            ]
+        );
+        assert_eq!(result.tokens, result.document.token_count(&Heuristic).total);
+    }
+
+    #[test]
+    fn test_keeps_last_turn() {
+        let doc = long_doc();
+        let result = doc.truncate(1, Strategy::DropOldest, &Heuristic);
+        assert_eq!(result.dropped, [1, 2, 3, 4, 5]);
+        assert_eq!(users(&result.document), ["Question 5"]);
+        assert!(result.tokens > 1);
+    }
+
+    #[test]
+    fn test_no_leading_continuation() {
+        // Turn 2 continues the assistant reply of turn 1 after a separator line
+        let doc = Document::parse("> Hi\nHello\n\n> @system:\nMore\n\n> Bye\nBye!");
+        assert!(is_continuation(&doc.turns[1]));
+        let result = doc.truncate(doc.token_count(&Heuristic).total - 1, Strategy::DropOldest, &Heuristic);
+        assert_eq!(result.dropped, [1, 2]);
+        assert_eq!(users(&result.document), ["Bye"]);
+    }
+
+    #[test]
+    fn test_parse_strategy() {
+        assert_eq!("keep-first=3".parse(), Ok(Strategy::KeepFirst { first: 3, last: None }));
+        assert_eq!("keep-first".parse(), Ok(Strategy::KeepFirst { first: 1, last: None }));
+        let window: Strategy = "keep-first=2,last=3".parse().unwrap();
+        assert_eq!(window, Strategy::KeepFirst { first: 2, last: Some(3) });
+        assert_eq!(window.to_string(), "keep-first=2,last=3");
+        assert!("keep-first,lats=3".parse::<Strategy>().is_err());
+        assert!("drop-oldest,last=3".parse::<Strategy>().is_err());
+        assert_eq!("placeholder".parse::<Strategy>().unwrap().to_string(), "placeholder");
+        assert!("keep-first=x".parse::<Strategy>().is_err());
+        assert!("newest".parse::<Strategy>().is_err());
+    }
+}
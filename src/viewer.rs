@@ -0,0 +1,187 @@
+//! Turn-by-turn navigation state for interactive viewing of a document
+
+use crate::terminal_renderer::MarkdownRenderer;
+use crate::{CodeBlock, Document, Turn};
+
+/// Rendered lines of an assistant answer kept visible when it is folded
+const FOLDED_LINES: usize = 12;
+
+/// Interactive view over a parsed document, one turn at a time
+pub struct Viewer<'a> {
+    doc: &'a Document,
+    renderer: &'a MarkdownRenderer,
+    current: usize,
+    folded: Vec<bool>,
+}
+
+impl<'a> Viewer<'a> {
+    pub fn new(doc: &'a Document, renderer: &'a MarkdownRenderer) -> Self {
+        Self {
+            doc,
+            renderer,
+            current: 0,
+            folded: vec![false; doc.turns.len()],
+        }
+    }
+
+    /// Index of the turn being shown
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.doc.turns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.doc.turns.is_empty()
+    }
+
+    /// Move to the next turn, returning false at the end
+    pub fn next_turn(&mut self) -> bool {
+        self.goto(self.current + 1)
+    }
+
+    /// Move to the previous turn, returning false at the start
+    pub fn prev_turn(&mut self) -> bool {
+        self.current > 0 && self.goto(self.current - 1)
+    }
+
+    /// Jump to a turn by index, returning false if it does not exist
+    pub fn goto(&mut self, index: usize) -> bool {
+        if index < self.len() {
+            self.current = index;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Find the next turn after the current one containing `query`
+    /// (case-insensitive), wrapping around, and move to it
+    pub fn search(&mut self, query: &str) -> Option<usize> {
+        let query = query.to_lowercase();
+        let len = self.len();
+        let found = (1..=len)
+            .map(|offset| (self.current + offset) % len)
+            .find(|&idx| {
+                let turn = &self.doc.turns[idx];
+                turn.user.content.to_lowercase().contains(&query)
+                    || turn.assistant.to_lowercase().contains(&query)
+            })?;
+        self.current = found;
+        Some(found)
+    }
+
+    /// Fold or unfold the assistant answer of the current turn
+    pub fn toggle_fold(&mut self) {
+        if let Some(folded) = self.folded.get_mut(self.current) {
+            *folded = !*folded;
+        }
+    }
+
+    /// Code blocks in the current turn's assistant answer
+    pub fn code_blocks(&self) -> Vec<CodeBlock> {
+        self.doc
+            .turns
+            .get(self.current)
+            .map(|turn| turn.code_blocks())
+            .unwrap_or_default()
+    }
+
+    /// Render the current turn with a position header; a folded turn shows
+    /// the whole user message and the start of the answer
+    pub fn render_current(&self) -> String {
+        let Some(turn) = self.doc.turns.get(self.current) else {
+            return String::new();
+        };
+
+        // The user block and the answer are rendered apart, so the answer
+        // can be folded on its own
+        let user = Document {
+            turns: vec![Turn {
+                user: turn.user.clone(),
+                assistant: String::new(),
+            }],
+        };
+        let glyphs = self.renderer.options().glyphs();
+        let rule = glyphs.horizontal.to_string().repeat(2);
+        let mut output = format!("{} Turn {}/{} {}\n", rule, self.current + 1, self.len(), rule);
+        output.push_str(&self.renderer.render(&format!("{}\n", user.to_cmf())));
+        if turn.assistant.is_empty() {
+            return output;
+        }
+        if !output.ends_with('\n') {
+            output.push('\n');
+        }
+
+        let rendered = self.renderer.render(&turn.assistant);
+        let lines: Vec<&str> = rendered.lines().collect();
+        if self.folded[self.current] && lines.len() > FOLDED_LINES {
+            for line in &lines[..FOLDED_LINES] {
+                output.push_str(line);
+                output.push('\n');
+            }
+            output.push_str(&format!(
+                "{} {} more lines folded\n",
+                glyphs.ellipsis,
+                lines.len() - FOLDED_LINES
+            ));
+        } else {
+            output.push_str(&rendered);
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Document {
+        Document::parse("> First\nAlpha answer.\n\n> Second\nBeta answer.\n\n> Third\nGamma answer.")
+    }
+
+    #[test]
+    fn test_navigation() {
+        let doc = sample();
+        let renderer = MarkdownRenderer::new();
+        let mut viewer = Viewer::new(&doc, &renderer);
+        assert!(!viewer.prev_turn());
+        assert!(viewer.next_turn());
+        assert!(viewer.next_turn());
+        assert!(!viewer.next_turn());
+        assert_eq!(viewer.current(), 2);
+        assert!(viewer.goto(0));
+        assert!(!viewer.goto(3));
+    }
+
+    #[test]
+    fn test_search_wraps() {
+        let doc = sample();
+        let renderer = MarkdownRenderer::new();
+        let mut viewer = Viewer::new(&doc, &renderer);
+        viewer.goto(2);
+        assert_eq!(viewer.search("alpha"), Some(0));
+        assert_eq!(viewer.search("missing"), None);
+        assert_eq!(viewer.current(), 0);
+    }
+
+    #[test]
+    fn test_fold_long_answer() {
+        let answer: Vec<String> = (0..30).map(|i| format!("Line {}  ", i)).collect();
+        let question: Vec<String> = (0..20).map(|i| format!("> Question {}", i)).collect();
+        let doc = Document::parse(&format!("{}\n{}", question.join("\n"), answer.join("\n")));
+        let renderer = MarkdownRenderer::new();
+        let mut viewer = Viewer::new(&doc, &renderer);
+        let full = viewer.render_current();
+        viewer.toggle_fold();
+        let folded = viewer.render_current();
+        assert!(full.contains("Line 29"));
+        assert!(!folded.contains("Line 29"));
+        assert!(folded.contains("Line 11"));
+        assert!(folded.contains("18 more lines folded"));
+        // The question stays whole
+        assert!(folded.contains("Question 19"));
+    }
+}
@@ -0,0 +1,331 @@
+//! Appending to CMF files during a live chat
+//!
+//! `CmfWriter` writes user messages and streamed assistant text with the
+//! format's prefixes, escaping and blank lines taken care of, so what a
+//! client appends reads back as the same turns.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::editor::{escape_assistant, user_block};
+use crate::metadata::{parse_comment, write_comment};
+use crate::{Document, Metadata, SYSTEM_USERNAME};
+
+/// What the output ends with, as far as blank-line rules care
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Last {
+    Nothing,
+    User,
+    Comment,
+    Text,
+}
+
+/// Append-only writer for CMF conversations
+///
+/// ```
+/// use cmf::{CmfWriter, Document};
+///
+/// let mut writer = CmfWriter::new(Vec::new());
+/// writer.user(None, "Hi")?;
+/// writer.assistant("Hello")?;
+/// writer.assistant(", how can I help?")?;
+/// writer.end_turn()?;
+///
+/// let doc = Document::parse(std::str::from_utf8(writer.get_ref()).unwrap());
+/// assert_eq!(doc.turns[0].assistant, "Hello, how can I help?");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct CmfWriter<W: Write> {
+    inner: W,
+    /// The file behind `inner`, synced at the end of each turn
+    file: Option<File>,
+    last: Last,
+    /// Consecutive newlines at the end of the output
+    newlines: usize,
+    /// Whether a user line has been written, so assistant text has a turn
+    in_turn: bool,
+    /// Whether the current turn has unsynced assistant output
+    turn_open: bool,
+    /// Start of a line that may still become `<!--`, written once it is
+    /// known whether it needs escaping
+    held: String,
+}
+
+impl<W: Write> CmfWriter<W> {
+    /// Write to empty output
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            file: None,
+            last: Last::Nothing,
+            newlines: 0,
+            in_turn: false,
+            turn_open: false,
+            held: String::new(),
+        }
+    }
+
+    /// Start a turn with a user message, ending the previous turn
+    pub fn user(&mut self, username: Option<&str>, content: &str) -> io::Result<()> {
+        if self.turn_open {
+            self.end_turn()?;
+        }
+        self.blank_line()?;
+        self.write(&user_block(username, content))?;
+        self.write("\n")?;
+        self.last = Last::User;
+        self.in_turn = true;
+        self.inner.flush()
+    }
+
+    /// Append streamed assistant text to the current turn
+    ///
+    /// Lines starting with `>` or `<!--` are indented so they stay assistant
+    /// content. Without a turn to answer, an empty `> @system:` line opens one.
+    pub fn assistant(&mut self, chunk: &str) -> io::Result<()> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        if !self.in_turn {
+            self.user(Some(SYSTEM_USERNAME), "")?;
+        }
+        if self.last == Last::Comment {
+            self.blank_line()?;
+        }
+        // Held text always starts a line
+        let at_line_start = self.newlines > 0 || !self.held.is_empty();
+        let mut text = std::mem::take(&mut self.held);
+        text.push_str(chunk);
+        let last_line = text.rfind('\n').map(|i| i + 1).or(at_line_start.then_some(0));
+        if let Some(start) = last_line.filter(|&start| is_comment_start(&text[start..])) {
+            self.held = text.split_off(start);
+        }
+        let text = escape_assistant(&text, at_line_start);
+        self.write(&text)?;
+        self.last = Last::Text;
+        self.turn_open = true;
+        Ok(())
+    }
+
+    /// Write a metadata comment for the message that follows
+    ///
+    /// Between assistant messages this also splits them; empty metadata
+    /// writes the bare `<!-- cmf -->` separator.
+    pub fn metadata(&mut self, metadata: &Metadata) -> io::Result<()> {
+        self.release()?;
+        match self.last {
+            Last::Nothing | Last::User => self.end_line()?,
+            Last::Comment | Last::Text => self.blank_line()?,
+        }
+        self.write(&write_comment(metadata))?;
+        self.write("\n")?;
+        self.last = Last::Comment;
+        Ok(())
+    }
+
+    /// Finish the current line, then flush and sync the file to disk
+    pub fn end_turn(&mut self) -> io::Result<()> {
+        self.release()?;
+        self.end_line()?;
+        self.inner.flush()?;
+        if let Some(ref file) = self.file {
+            file.sync_data()?;
+        }
+        self.turn_open = false;
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_all(text.as_bytes())?;
+        let trailing = text.len() - text.trim_end_matches('\n').len();
+        self.newlines = if trailing == text.len() { self.newlines + trailing } else { trailing };
+        Ok(())
+    }
+
+    /// Write held text as it is, now that no more of its line is coming
+    fn release(&mut self) -> io::Result<()> {
+        let held = std::mem::take(&mut self.held);
+        self.write(&held)
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        if self.last != Last::Nothing && self.newlines == 0 {
+            self.write("\n")?;
+        }
+        Ok(())
+    }
+
+    /// Leave a blank line before the next block, unless nothing was written
+    fn blank_line(&mut self) -> io::Result<()> {
+        if self.last == Last::Nothing {
+            return Ok(());
+        }
+        let missing = 2usize.saturating_sub(self.newlines);
+        self.write(&"\n".repeat(missing))
+    }
+}
+
+/// Whether a line so far is too short to tell if it starts `<!--`
+fn is_comment_start(line: &str) -> bool {
+    !line.is_empty() && line.len() < 4 && "<!--".starts_with(line)
+}
+
+impl CmfWriter<File> {
+    /// Open a file for appending, creating it if needed
+    ///
+    /// The file is locked until the writer is dropped; opening one that
+    /// another writer holds fails with `io::ErrorKind::WouldBlock`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "file is locked by another writer"));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        let mut writer = CmfWriter::new(file.try_clone()?);
+        writer.resume(BufReader::new(&file))?;
+        writer.file = Some(file);
+        Ok(writer)
+    }
+
+    /// Read the whole file through the locked handle, as a second handle
+    /// can't read it where locks are mandatory
+    pub fn read_document(&mut self) -> io::Result<Document> {
+        let mut file = self.file.as_ref().unwrap_or(&self.inner);
+        let mut content = String::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_string(&mut content)?;
+        Ok(Document::parse(&content))
+    }
+}
+
+impl<W: Write> CmfWriter<W> {
+    /// Pick up the state at the end of existing content
+    fn resume(&mut self, mut reader: impl BufRead) -> io::Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let text = line.trim_end_matches(['\n', '\r']);
+            if text.trim().is_empty() {
+                self.newlines += line.matches('\n').count();
+                continue;
+            }
+
+            self.newlines = line.matches('\n').count();
+            self.last = if text.starts_with('>') {
+                self.in_turn = true;
+                Last::User
+            } else if parse_comment(text).is_some() {
+                Last::Comment
+            } else {
+                Last::Text
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(writer: &CmfWriter<Vec<u8>>) -> &str {
+        std::str::from_utf8(writer.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_writes_parseable_turns() {
+        let mut writer = CmfWriter::new(Vec::new());
+        writer.user(Some("alice"), "Quote this\nplease").unwrap();
+        for chunk in ["Sure:\n", "> a quote", "\n\n", ">another\nDone."] {
+            writer.assistant(chunk).unwrap();
+        }
+        writer.metadata(&Metadata::from([("name".to_string(), "critic".to_string())])).unwrap();
+        writer.assistant("Looks fine.").unwrap();
+        writer.metadata(&Metadata::from([("ts".to_string(), "2".to_string())])).unwrap();
+        writer.user(None, "Thanks").unwrap();
+        writer.end_turn().unwrap();
+
+        assert_eq!(
+            output(&writer),
+            "> @alice: Quote this\n> please\nSure:\n > a quote\n\n >another\nDone.\n\n\
+             <!-- cmf: name=critic -->\n\nLooks fine.\n\n<!-- cmf: ts=2 -->\n\n> Thanks\n"
+        );
+        let doc = Document::parse(output(&writer));
+        assert_eq!(doc.turns.len(), 2);
+        assert_eq!(doc.turns[0].user.content, "Quote this\nplease");
+        assert_eq!(doc.turns[0].assistant_entries()[1].name.as_deref(), Some("critic"));
+        assert_eq!(doc.turns[1].user.metadata["ts"], "2");
+    }
+
+    #[test]
+    fn test_escapes_comment_lines() {
+        let mut writer = CmfWriter::new(Vec::new());
+        writer.user(None, "Show the separator").unwrap();
+        for chunk in ["It is\n", "<!", "-- cmf -->", "\nand\n<!-- cmf: name=x -->\n<", "b>bold</b>"] {
+            writer.assistant(chunk).unwrap();
+        }
+        writer.end_turn().unwrap();
+
+        assert_eq!(
+            output(&writer),
+            "> Show the separator\nIt is\n <!-- cmf -->\nand\n <!-- cmf: name=x -->\n<b>bold</b>\n"
+        );
+        let doc = Document::parse(output(&writer));
+        let entries = doc.turns[0].assistant_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, None);
+    }
+
+    #[test]
+    fn test_assistant_without_turn_opens_system_line() {
+        let mut writer = CmfWriter::new(Vec::new());
+        writer.assistant("Welcome!").unwrap();
+        writer.end_turn().unwrap();
+        assert_eq!(output(&writer), "> @system: \nWelcome!\n");
+    }
+
+    #[test]
+    fn test_open_appends_and_locks() {
+        let path = std::env::temp_dir().join(format!("cmf-writer-test-{}.cmf", std::process::id()));
+        std::fs::write(&path, "---\ntitle: log\n---\n\n> Hi\nHello").unwrap();
+
+        let mut writer = CmfWriter::open(&path).unwrap();
+        let err = CmfWriter::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(writer.read_document().unwrap().turns[0].assistant, "Hello");
+
+        writer.assistant(" again").unwrap();
+        writer.user(None, "Bye").unwrap();
+        writer.end_turn().unwrap();
+        drop(writer);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "---\ntitle: log\n---\n\n> Hi\nHello again\n\n> Bye\n");
+        std::fs::remove_file(&path).unwrap();
+
+        // A preamble alone is not a turn
+        let mut writer = CmfWriter::new(Vec::new());
+        writer.resume("# Notes\n".as_bytes()).unwrap();
+        writer.assistant("Hi").unwrap();
+        assert_eq!(output(&writer), "\n> @system: \nHi");
+    }
+}